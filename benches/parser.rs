@@ -0,0 +1,136 @@
+use criterion::measurement::{Measurement, ValueFormatter};
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+use fsdparser::Parser;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// Counts every allocation so the borrowed and owned parse paths can be compared
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+// Measures in allocations instead of time, so criterion reports and compares those
+struct Allocations;
+
+impl Measurement for Allocations {
+    type Intermediate = usize;
+    type Value = usize;
+
+    fn start(&self) -> usize {
+        ALLOCATIONS.load(Ordering::Relaxed)
+    }
+
+    fn end(&self, start: usize) -> usize {
+        ALLOCATIONS.load(Ordering::Relaxed) - start
+    }
+
+    fn add(&self, first: &usize, second: &usize) -> usize {
+        first + second
+    }
+
+    fn zero(&self) -> usize {
+        0
+    }
+
+    fn to_f64(&self, value: &usize) -> f64 {
+        *value as f64
+    }
+
+    fn formatter(&self) -> &dyn ValueFormatter {
+        &AllocationFormatter
+    }
+}
+
+struct AllocationFormatter;
+
+impl ValueFormatter for AllocationFormatter {
+    fn scale_values(&self, _typical_value: f64, _values: &mut [f64]) -> &'static str {
+        "allocs"
+    }
+
+    // Lines parsed per allocation
+    fn scale_throughputs(
+        &self,
+        _typical_value: f64,
+        throughput: &Throughput,
+        values: &mut [f64],
+    ) -> &'static str {
+        if let Throughput::Elements(lines) = throughput {
+            for value in values {
+                *value = *lines as f64 / *value;
+            }
+        }
+        "lines/alloc"
+    }
+
+    fn scale_for_machines(&self, _values: &mut [f64]) -> &'static str {
+        "allocs"
+    }
+}
+
+const LINES: &[&str] = &[
+    "@S:N513PW:4717:1:41.93848:-72.69294:174:0:4282386784:61",
+    "@N:DAL1:0200:1:-33.5:151.25:35000:450:25170944:-120",
+    "%BOS_APP:33000:5:150:5:42.35745:-70.98955:0",
+    "#TMNY_CAM_APP:@28120:EK188,turnrightheading310",
+    "$CQBOS_GND:@94835:BC:DAL1:1001",
+    "$FPSWA1895:*A:I:B738/L:461:KBNA:1835:1835:35000:KRDU:1:14:3:4:KIAD:PBN/A1B1C1D1:TAZMO3 BURME VXV",
+];
+
+// Baseline: the `String`-based parser that came before borrowed packets copied every field and
+// made 44 allocations for these lines. It was counted with the same allocator, as it cannot be
+// built next to the borrowed packets that replaced it.
+fn parse_lines<M: Measurement>(c: &mut Criterion<M>, name: &str) {
+    let mut group = c.benchmark_group(name);
+    group.throughput(Throughput::Elements(LINES.len() as u64));
+    group.bench_function("borrowed", |b| {
+        b.iter(|| {
+            for line in LINES {
+                black_box(Parser::parse(black_box(line)).unwrap());
+            }
+        })
+    });
+    group.bench_function("owned", |b| {
+        b.iter(|| {
+            for line in LINES {
+                black_box(Parser::parse(black_box(line)).unwrap().into_owned());
+            }
+        })
+    });
+    group.finish();
+}
+
+fn parse_time(c: &mut Criterion) {
+    parse_lines(c, "parse time");
+}
+
+fn parse_allocations(c: &mut Criterion<Allocations>) {
+    parse_lines(c, "parse allocations");
+}
+
+// Every sample is the same, which the plots cannot cope with. The command line turns them back
+// on, so they are turned off after it is read rather than through `criterion_group!`.
+fn allocations() {
+    let mut criterion = Criterion::default()
+        .with_measurement(Allocations)
+        .configure_from_args()
+        .without_plots();
+    parse_allocations(&mut criterion);
+}
+
+criterion_group!(time, parse_time);
+criterion_main!(time, allocations);
@@ -0,0 +1,15 @@
+// Command line helpers shared by the binaries
+
+/// Removes every `name <value>` pair from the arguments and returns the values
+pub fn take_option(args: &mut Vec<String>, name: &str) -> Vec<String> {
+    let mut values = Vec::new();
+    while let Some(index) = args.iter().position(|arg| arg == name) {
+        let value = args
+            .get(index + 1)
+            .unwrap_or_else(|| panic!("{} needs a value!", name))
+            .clone();
+        args.drain(index..index + 2);
+        values.push(value);
+    }
+    values
+}
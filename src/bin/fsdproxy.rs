@@ -0,0 +1,77 @@
+mod args;
+
+use args::take_option;
+use fsdparser::{Direction, FsdProxy, ProxiedLine, ProxyAction, DEFAULT_FSD_PORT};
+use std::env;
+use std::time::UNIX_EPOCH;
+
+fn main() {
+    let mut args: Vec<String> = env::args().skip(1).collect();
+    // --drop <prefix> throws away lines starting with it, e.g. `--drop $CQ`
+    let drop_prefixes = take_option(&mut args, "--drop");
+    // --listen <address> is where clients connect instead of the server
+    let listen = take_option(&mut args, "--listen")
+        .pop()
+        .unwrap_or_else(|| format!("127.0.0.1:{}", DEFAULT_FSD_PORT));
+    let upstream = match args.first() {
+        Some(upstream) if upstream.contains(':') => upstream.clone(),
+        Some(host) => format!("{}:{}", host, DEFAULT_FSD_PORT),
+        None => {
+            eprintln!("Usage: fsdproxy <server[:port]> [--listen <address>] [--drop <prefix>]");
+            return;
+        }
+    };
+
+    let mut proxy = FsdProxy::bind(&listen, &upstream).expect("Could not start the proxy!");
+    proxy.set_hook(move |line: &ProxiedLine| {
+        let dropped = drop_prefixes
+            .iter()
+            .any(|prefix| line.raw.starts_with(prefix.as_str()));
+        log(line, dropped);
+        match dropped {
+            true => ProxyAction::Drop,
+            false => ProxyAction::Forward,
+        }
+    });
+    println!(
+        "Forwarding {} to {}",
+        proxy.local_addr().expect("Could not get the address!"),
+        upstream
+    );
+    proxy.run().expect("Proxy stopped!");
+}
+
+fn log(line: &ProxiedLine, dropped: bool) {
+    let arrow = match line.direction {
+        Direction::ServerToClient => "<-",
+        Direction::ClientToServer => "->",
+    };
+    let timestamp = line
+        .timestamp
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let marker = if dropped { " (dropped)" } else { "" };
+    match &line.packet {
+        Some(packet) => println!(
+            "{}.{:06} {} {} {}{} {:?}",
+            timestamp.as_secs(),
+            timestamp.subsec_micros(),
+            line.connection.source,
+            arrow,
+            line.connection.destination,
+            marker,
+            packet
+        ),
+        // Unknown packets still get through, they are shown as they were sent
+        None => println!(
+            "{}.{:06} {} {} {}{} {}",
+            timestamp.as_secs(),
+            timestamp.subsec_micros(),
+            line.connection.source,
+            arrow,
+            line.connection.destination,
+            marker,
+            line.raw
+        ),
+    }
+}
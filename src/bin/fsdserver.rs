@@ -0,0 +1,16 @@
+use fsdparser::{FsdServer, DEFAULT_FSD_PORT};
+use std::env;
+
+fn main() {
+    // Listens on every interface on the standard FSD port unless given an address
+    let address = env::args()
+        .nth(1)
+        .unwrap_or_else(|| format!("0.0.0.0:{}", DEFAULT_FSD_PORT));
+
+    let server = FsdServer::bind(&address).expect("Could not listen on the address!");
+    println!(
+        "Listening on {}",
+        server.local_addr().expect("Could not get the address!")
+    );
+    server.run().expect("Server stopped!");
+}
@@ -0,0 +1,145 @@
+#![cfg(feature = "sniffer")]
+use crate::pcap::{PcapFrame, PcapReader};
+use crate::sniffer::LinkType;
+use pnet::datalink;
+use pnet::datalink::{Channel, Config, DataLinkReceiver, NetworkInterface};
+use std::collections::VecDeque;
+use std::io;
+use std::io::{Error, ErrorKind, Read};
+use std::time::{Duration, SystemTime};
+
+// Reads give up this often on a quiet interface, so a capture thread notices being stopped
+const READ_TIMEOUT: Duration = Duration::from_millis(250);
+
+/// Somewhere captured frames come from
+pub trait CaptureSource {
+    /// Prepares the source, e.g. opens the network interface
+    fn start(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Returns the next frame, or `None` once the source has no more frames. A `TimedOut` or
+    /// `WouldBlock` error only means no frame arrived yet.
+    fn next_frame(&mut self) -> io::Result<Option<PcapFrame<'_>>>;
+}
+
+/// Live capture from a network interface
+pub struct PacketSniffer {
+    rx: Option<Box<dyn DataLinkReceiver>>,
+    using_interface: Option<NetworkInterface>,
+    link_type: LinkType,
+}
+
+impl PacketSniffer {
+    pub fn new() -> PacketSniffer {
+        PacketSniffer {
+            rx: None,
+            using_interface: None,
+            link_type: LinkType::Ethernet,
+        }
+    }
+
+    pub fn set_link_type(&mut self, link_type: LinkType) {
+        self.link_type = link_type;
+    }
+
+    pub fn get_available_interfaces(&self) -> Vec<NetworkInterface> {
+        datalink::interfaces()
+    }
+
+    pub fn set_user_interface(&mut self, interface: &NetworkInterface) {
+        self.using_interface = Some(interface.clone());
+    }
+}
+
+impl Default for PacketSniffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CaptureSource for PacketSniffer {
+    fn start(&mut self) -> io::Result<()> {
+        // Establish link
+        let interface = match &self.using_interface {
+            Some(interface) => interface,
+            None => return Err(Error::new(ErrorKind::InvalidInput, "No interface.")),
+        };
+
+        let config = Config {
+            read_timeout: Some(READ_TIMEOUT),
+            ..Default::default()
+        };
+        self.rx = match datalink::channel(interface, config)? {
+            Channel::Ethernet(_, rx) => Some(rx),
+            _ => return Err(Error::other("Unhandled channel type.")),
+        };
+        Ok(())
+    }
+
+    // Live frames are stamped with the time they were read
+    fn next_frame(&mut self) -> io::Result<Option<PcapFrame<'_>>> {
+        let rx = match self.rx.as_mut() {
+            Some(rx) => rx,
+            None => {
+                return Err(Error::new(
+                    ErrorKind::NotConnected,
+                    "Sniffer was not started.",
+                ))
+            }
+        };
+
+        Ok(Some(PcapFrame {
+            timestamp: SystemTime::now(),
+            link_type: self.link_type.to_pcap(),
+            data: rx.next()?,
+        }))
+    }
+}
+
+impl<R: Read> CaptureSource for PcapReader<R> {
+    fn next_frame(&mut self) -> io::Result<Option<PcapFrame<'_>>> {
+        PcapReader::next_frame(self)
+    }
+}
+
+/// Frames held in memory, mainly for feeding synthetic traffic to a `Sniffer`
+#[derive(Debug, Default)]
+pub struct MemoryCapture {
+    frames: VecDeque<(SystemTime, LinkType, Vec<u8>)>,
+    current: Vec<u8>,
+}
+
+impl MemoryCapture {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, timestamp: SystemTime, link_type: LinkType, data: Vec<u8>) {
+        self.frames.push_back((timestamp, link_type, data));
+    }
+
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+}
+
+impl CaptureSource for MemoryCapture {
+    fn next_frame(&mut self) -> io::Result<Option<PcapFrame<'_>>> {
+        Ok(match self.frames.pop_front() {
+            Some((timestamp, link_type, data)) => {
+                self.current = data;
+                Some(PcapFrame {
+                    timestamp,
+                    link_type: link_type.to_pcap(),
+                    data: &self.current,
+                })
+            }
+            None => None,
+        })
+    }
+}
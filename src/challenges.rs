@@ -0,0 +1,153 @@
+use crate::flows::{Direction, PacketSource};
+use crate::pairing::PendingRequests;
+use crate::parser::PacketTypes;
+use crate::reassembly::Connection;
+use std::time::SystemTime;
+
+/// A `$ZC` challenge and the `$ZR` that answered it
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChallengeExchange {
+    /// The connection from the client to the server, whichever side challenged
+    pub connection: Connection,
+    /// Which way the challenge went
+    pub direction: Direction,
+    pub challenger: String,
+    pub responder: String,
+    pub challenge: String,
+    pub response: String,
+    pub challenged_at: SystemTime,
+    pub answered_at: SystemTime,
+}
+
+#[derive(Debug)]
+struct Challenge {
+    challenger: String,
+    responder: String,
+    challenge: String,
+    timestamp: SystemTime,
+}
+
+/// Pairs challenges with their responses on each connection, for packets from a `Sniffer`
+/// or anything else that produces `PacketSource`s.
+///
+/// A response answers the oldest challenge sent the other way between the same two
+/// callsigns.
+#[derive(Debug, Default)]
+pub struct ChallengeTracker {
+    pending: PendingRequests<Challenge>,
+}
+
+impl ChallengeTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the exchange a response completes, other packets are ignored
+    pub fn process(&mut self, packet: &PacketSource) -> Option<ChallengeExchange> {
+        match &packet.packet {
+            PacketTypes::AuthChallenge(challenge) => {
+                self.pending.request(
+                    packet,
+                    Challenge {
+                        challenger: challenge.from.to_string(),
+                        responder: challenge.to.to_string(),
+                        challenge: challenge.challenge.to_string(),
+                        timestamp: packet.timestamp,
+                    },
+                );
+                None
+            }
+            PacketTypes::AuthResponse(response) => {
+                let (connection, direction, challenge) =
+                    self.pending.respond(packet, |challenge| {
+                        challenge.challenger.eq_ignore_ascii_case(&response.to)
+                            && challenge.responder.eq_ignore_ascii_case(&response.from)
+                    })?;
+                Some(ChallengeExchange {
+                    connection,
+                    direction,
+                    challenger: challenge.challenger,
+                    responder: challenge.responder,
+                    challenge: challenge.challenge,
+                    response: response.response.to_string(),
+                    challenged_at: challenge.timestamp,
+                    answered_at: packet.timestamp,
+                })
+            }
+            _ => None,
+        }
+    }
+
+    /// Challenges still waiting for a response
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Drops the unanswered challenges of a connection, e.g. once the client logged off
+    pub fn forget(&mut self, connection: &Connection) {
+        self.pending.forget(connection);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::pairing::fixtures::{client_to_server, packet};
+    use std::time::Duration;
+
+    #[test]
+    fn test_pairing() {
+        let mut tracker = ChallengeTracker::new();
+        let from_server = Direction::ServerToClient;
+        let from_client = Direction::ClientToServer;
+        assert_eq!(
+            tracker.process(&packet(from_server, 1000, "$ZCSERVER:N123:aaa")),
+            None
+        );
+        tracker.process(&packet(from_client, 2000, "$ZCN123:SERVER:bbb"));
+        tracker.process(&packet(from_server, 3000, "$ZCSERVER:N123:ccc"));
+        assert_eq!(tracker.pending(), 3);
+
+        // The client's own challenge is not answered by its response
+        let exchange = tracker
+            .process(&packet(from_client, 4000, "$ZRN123:SERVER:AAA"))
+            .unwrap();
+        assert_eq!(exchange.connection, client_to_server());
+        assert_eq!(exchange.direction, from_server);
+        assert_eq!(
+            (exchange.challenger.as_str(), exchange.responder.as_str()),
+            ("SERVER", "N123")
+        );
+        assert_eq!(
+            (exchange.challenge.as_str(), exchange.response.as_str()),
+            ("aaa", "AAA")
+        );
+        assert_eq!(
+            exchange
+                .answered_at
+                .duration_since(exchange.challenged_at)
+                .unwrap(),
+            Duration::from_secs(3)
+        );
+
+        let exchange = tracker
+            .process(&packet(from_server, 5000, "$ZRSERVER:N123:BBB"))
+            .unwrap();
+        assert_eq!(
+            (exchange.challenge.as_str(), exchange.direction),
+            ("bbb", from_client)
+        );
+        // Nobody asked for this one
+        assert_eq!(
+            tracker.process(&packet(from_server, 6000, "$ZRSERVER:N123:XXX")),
+            None
+        );
+
+        tracker.forget(&client_to_server().reversed());
+        assert_eq!(tracker.pending(), 0);
+        assert_eq!(
+            tracker.process(&packet(from_client, 7000, "$ZRN123:SERVER:CCC")),
+            None
+        );
+    }
+}
@@ -0,0 +1,610 @@
+use crate::fsdpackets::*;
+use crate::lines::{write_line, Incoming, LineReader};
+use crate::parser::{PacketTypes, Parser};
+use std::borrow::Cow;
+use std::fmt::{self, Debug, Formatter};
+use std::io;
+use std::io::{Error, ErrorKind};
+use std::net::{Shutdown, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::sync::mpsc::{Receiver, Sender};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+/// How often positions are sent unless changed with `set_position_interval`
+pub const DEFAULT_POSITION_INTERVAL: Duration = Duration::from_secs(5);
+
+// How long the server has to greet a new connection with `$DI`
+const LOGIN_TIMEOUT: Duration = Duration::from_secs(10);
+// The session thread wakes up this often to send positions and notice being closed
+const TICK: Duration = Duration::from_millis(50);
+
+/// Answers the `$ZC` challenges servers use to check for authorized client software. The
+/// networks keep their algorithms to themselves, so a client brings its own.
+pub trait ChallengeResponder {
+    /// Called with the initial challenge from the server's `$DI`, before logging in
+    fn initialize(&mut self, _initial_challenge: &str) {}
+
+    fn respond(&mut self, challenge: &str) -> String;
+}
+
+impl<F: FnMut(&str) -> String> ChallengeResponder for F {
+    fn respond(&mut self, challenge: &str) -> String {
+        self(challenge)
+    }
+}
+
+// Keeps `FsdClient` cloneable and printable with a responder in it
+#[derive(Clone)]
+struct SharedResponder(Arc<Mutex<dyn ChallengeResponder + Send>>);
+
+impl SharedResponder {
+    fn lock(&self) -> MutexGuard<'_, dyn ChallengeResponder + Send + 'static> {
+        self.0.lock().unwrap_or_else(|error| error.into_inner())
+    }
+}
+
+impl Debug for SharedResponder {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "ChallengeResponder")
+    }
+}
+
+/// Connection details for joining an FSD server as a pilot or controller.
+///
+/// The login sends `$ID` from `identification` and then `#AP` or `#AA` from `login`,
+/// depending on its client type. Queries from other clients for `CAPS`, `RN`, `ATC` and
+/// `INF` are answered by the session, as are pings, and so are `$ZC` challenges once a
+/// `ChallengeResponder` is set.
+#[derive(Debug, Clone)]
+pub struct FsdClient {
+    identification: ClientIdentification<'static>,
+    login: NetworkClient<'static>,
+    capabilities: ClientCapabilities,
+    info: Option<String>,
+    position_interval: Duration,
+    position: Option<PacketTypes<'static>>,
+    responder: Option<SharedResponder>,
+}
+
+impl FsdClient {
+    pub fn new(
+        identification: ClientIdentification<'static>,
+        login: NetworkClient<'static>,
+    ) -> Self {
+        FsdClient {
+            identification,
+            login,
+            capabilities: [Capability::Version].iter().copied().collect(),
+            info: None,
+            position_interval: DEFAULT_POSITION_INTERVAL,
+            position: None,
+            responder: None,
+        }
+    }
+
+    /// Without one, challenges are passed on to the application unanswered
+    pub fn set_challenge_responder<R: ChallengeResponder + Send + 'static>(
+        &mut self,
+        responder: R,
+    ) {
+        self.responder = Some(SharedResponder(Arc::new(Mutex::new(responder))));
+    }
+
+    /// Flags sent in answer to `CAPS`, only `VERSION` by default
+    pub fn set_capabilities(&mut self, capabilities: ClientCapabilities) {
+        self.capabilities = capabilities;
+    }
+
+    /// Text sent in answer to `INF`, the client name, version and CID by default
+    pub fn set_info(&mut self, info: String) {
+        self.info = Some(info);
+    }
+
+    pub fn set_position_interval(&mut self, interval: Duration) {
+        self.position_interval = interval;
+    }
+
+    /// The first position to send, `ClientSession` can update it later
+    pub fn set_pilot_position(&mut self, position: PilotPosition<'_>) {
+        self.position = Some(PacketTypes::PilotPosition(position.into_owned()));
+    }
+
+    pub fn set_atc_position(&mut self, position: ATCPosition<'_>) {
+        self.position = Some(PacketTypes::ATCPosition(position.into_owned()));
+    }
+
+    /// Connects and logs in, waiting for the server to greet the client first
+    pub fn connect<A: ToSocketAddrs>(mut self, address: A) -> io::Result<ClientSession> {
+        let stream = TcpStream::connect(address)?;
+        stream.set_nodelay(true)?;
+        let mut reader = LineReader::new(stream.try_clone()?);
+        let mut writer = stream;
+
+        reader.get_ref().set_read_timeout(Some(TICK))?;
+        let deadline = Instant::now() + LOGIN_TIMEOUT;
+        let greeting = loop {
+            match reader.next_line()? {
+                Incoming::Line(line) if line.starts_with("$DI") => break line,
+                Incoming::Line(_) | Incoming::Idle if Instant::now() < deadline => (),
+                Incoming::Closed => {
+                    return Err(Error::new(
+                        ErrorKind::ConnectionAborted,
+                        "Server closed the connection before the login",
+                    ))
+                }
+                _ => return Err(Error::new(ErrorKind::TimedOut, "Server did not send $DI")),
+            }
+        };
+        if let (Some(responder), Ok(PacketTypes::ServerIdentification(greeting))) =
+            (&self.responder, Parser::parse(&greeting))
+        {
+            responder.lock().initialize(&greeting.initial_challenge);
+        }
+
+        self.identification.from = self.login.callsign.clone();
+        self.identification.to = "SERVER".into();
+        write_line(&mut writer, &self.identification.to_fsd_string())?;
+        write_line(&mut writer, &self.login.to_fsd_string())?;
+
+        let writer = Arc::new(Mutex::new(writer));
+        let position = Arc::new(Mutex::new(self.position.take()));
+        let closed = Arc::new(AtomicBool::new(false));
+        let (sender, receiver) = mpsc::channel();
+        let logoff = DeleteClient {
+            client_type: self.login.client_type.clone(),
+            callsign: self.login.callsign.clone(),
+            cid: self.login.cid.clone(),
+        };
+
+        let thread = {
+            let session = SessionThread {
+                client: self,
+                writer: writer.clone(),
+                position: position.clone(),
+                closed: closed.clone(),
+                sender,
+                last_position: None,
+            };
+            thread::Builder::new()
+                .name(format!("fsd-client-{}", logoff.callsign))
+                .spawn(move || session.run(reader))?
+        };
+
+        Ok(ClientSession {
+            logoff,
+            writer,
+            position,
+            closed,
+            receiver,
+            thread: Some(thread),
+        })
+    }
+}
+
+// Answers queries and sends positions while the application reads packets
+struct SessionThread {
+    client: FsdClient,
+    writer: Arc<Mutex<TcpStream>>,
+    position: Arc<Mutex<Option<PacketTypes<'static>>>>,
+    closed: Arc<AtomicBool>,
+    sender: Sender<PacketTypes<'static>>,
+    last_position: Option<Instant>,
+}
+
+impl SessionThread {
+    fn run(mut self, mut reader: LineReader<TcpStream>) -> io::Result<()> {
+        while !self.closed.load(Ordering::SeqCst) {
+            self.send_position()?;
+            let line = match reader.next_line() {
+                Ok(Incoming::Line(line)) => line,
+                Ok(Incoming::Idle) => continue,
+                Ok(Incoming::Closed) => return Ok(()),
+                // Closing the session shuts the socket down under the reader
+                Err(_) if self.closed.load(Ordering::SeqCst) => return Ok(()),
+                Err(error) => return Err(error),
+            };
+
+            // Lines this crate does not know yet are left out
+            let packet = match Parser::parse(&line) {
+                Ok(packet) => packet.into_owned(),
+                Err(_) => continue,
+            };
+            match &packet {
+                PacketTypes::ClientQuery(query) => {
+                    if let Some(answer) = self.answer(query) {
+                        self.send(&PacketTypes::ClientQuery(answer))?;
+                    }
+                }
+                PacketTypes::Ping(ping)
+                    if !ping.is_response
+                        && ping.to.eq_ignore_ascii_case(&self.client.login.callsign) =>
+                {
+                    let pong = Ping {
+                        is_response: true,
+                        from: ping.to.to_string().into(),
+                        to: ping.from.to_string().into(),
+                        token: ping.token.to_string().into(),
+                    };
+                    self.send(&PacketTypes::Ping(pong))?;
+                }
+                PacketTypes::AuthChallenge(challenge) => {
+                    if let Some(response) = self.respond(challenge) {
+                        self.send(&PacketTypes::AuthResponse(response))?;
+                    }
+                }
+                _ => (),
+            }
+            // The application not listening is no reason to drop off the network
+            let _ = self.sender.send(packet);
+        }
+        Ok(())
+    }
+
+    fn send(&self, packet: &PacketTypes<'_>) -> io::Result<()> {
+        let mut writer = self
+            .writer
+            .lock()
+            .unwrap_or_else(|error| error.into_inner());
+        write_line(&mut *writer, &packet.to_fsd_string())
+    }
+
+    fn send_position(&mut self) -> io::Result<()> {
+        if let Some(last_position) = self.last_position {
+            if last_position.elapsed() < self.client.position_interval {
+                return Ok(());
+            }
+        }
+        let position = self
+            .position
+            .lock()
+            .unwrap_or_else(|error| error.into_inner())
+            .clone();
+        if let Some(position) = position {
+            self.send(&position)?;
+            self.last_position = Some(Instant::now());
+        }
+        Ok(())
+    }
+
+    fn respond(&self, challenge: &AuthChallenge<'_>) -> Option<AuthResponse<'static>> {
+        let callsign = &self.client.login.callsign;
+        if !challenge.to.eq_ignore_ascii_case(callsign) {
+            return None;
+        }
+        let response = self
+            .client
+            .responder
+            .as_ref()?
+            .lock()
+            .respond(&challenge.challenge);
+        Some(AuthResponse {
+            from: callsign.clone(),
+            to: challenge.from.to_string().into(),
+            response: response.into(),
+        })
+    }
+
+    fn answer(&self, query: &ClientQuery<'_>) -> Option<ClientQuery<'static>> {
+        let login = &self.client.login;
+        if query.is_response || !query.to.eq_ignore_ascii_case(&login.callsign) {
+            return None;
+        }
+
+        let payload = match (&query.query_type, &query.payload) {
+            (ClientQueryType::Capabilities, _) => {
+                ClientQueryPayload::CapabilitiesResponse(self.client.capabilities.clone())
+            }
+            (ClientQueryType::RealName, _) => ClientQueryPayload::RealName(RealNamePayload {
+                real_name: login.real_name.clone(),
+                facility_name: Cow::Borrowed(""),
+                rating: login.rating.clone(),
+            }),
+            (ClientQueryType::IsValidATC, ClientQueryPayload::IsValidATCQuery(callsign)) => {
+                let callsign = callsign.clone().unwrap_or_else(|| login.callsign.clone());
+                let is_valid = login.client_type == NetworkClientType::ATC
+                    && login.rating != NetworkRating::OBS
+                    && callsign.eq_ignore_ascii_case(&login.callsign);
+                ClientQueryPayload::IsValidATCResponse(is_valid, Some(callsign.into_owned().into()))
+            }
+            (ClientQueryType::INF, _) => {
+                let identification = &self.client.identification;
+                let info = self.client.info.clone().unwrap_or_else(|| {
+                    format!(
+                        "{} {}.{} CID={}",
+                        identification.client_name,
+                        identification.major_version,
+                        identification.minor_version,
+                        login.cid
+                    )
+                });
+                ClientQueryPayload::INFResponse(info.into())
+            }
+            _ => return None,
+        };
+
+        Some(ClientQuery {
+            is_response: true,
+            from: login.callsign.clone(),
+            to: query.from.to_string().into(),
+            query_type: query.query_type.clone().into_owned(),
+            payload,
+        })
+    }
+}
+
+/// A logged in connection to an FSD server, created with `FsdClient::connect`.
+///
+/// Iterating returns the packets from the server and blocks until the next one, ending once
+/// the connection is gone. Dropping the session closes the connection.
+pub struct ClientSession {
+    logoff: DeleteClient<'static>,
+    writer: Arc<Mutex<TcpStream>>,
+    position: Arc<Mutex<Option<PacketTypes<'static>>>>,
+    closed: Arc<AtomicBool>,
+    receiver: Receiver<PacketTypes<'static>>,
+    thread: Option<JoinHandle<io::Result<()>>>,
+}
+
+impl ClientSession {
+    pub fn callsign(&self) -> &str {
+        &self.logoff.callsign
+    }
+
+    /// Packets from the server, with queries already answered
+    pub fn receiver(&self) -> &Receiver<PacketTypes<'static>> {
+        &self.receiver
+    }
+
+    pub fn send(&self, packet: &PacketTypes<'_>) -> io::Result<()> {
+        let mut writer = self
+            .writer
+            .lock()
+            .unwrap_or_else(|error| error.into_inner());
+        write_line(&mut *writer, &packet.to_fsd_string())
+    }
+
+    /// The position sent from now on
+    pub fn set_pilot_position(&self, position: PilotPosition<'_>) {
+        self.set_position(PacketTypes::PilotPosition(position.into_owned()));
+    }
+
+    pub fn set_atc_position(&self, position: ATCPosition<'_>) {
+        self.set_position(PacketTypes::ATCPosition(position.into_owned()));
+    }
+
+    fn set_position(&self, position: PacketTypes<'static>) {
+        *self
+            .position
+            .lock()
+            .unwrap_or_else(|error| error.into_inner()) = Some(position);
+    }
+
+    /// Logs off with `#DP` or `#DA` and closes the connection. Returns the error that ended the
+    /// session early, if any.
+    pub fn disconnect(mut self) -> io::Result<()> {
+        // The server may already be gone, which is what the thread's error is for
+        let _ = self.send(&PacketTypes::DeleteClient(self.logoff.clone()));
+        self.close()
+    }
+
+    fn close(&mut self) -> io::Result<()> {
+        self.closed.store(true, Ordering::SeqCst);
+        let _ = self
+            .writer
+            .lock()
+            .unwrap_or_else(|error| error.into_inner())
+            .shutdown(Shutdown::Both);
+        match self.thread.take().map(JoinHandle::join) {
+            Some(Ok(result)) => result,
+            Some(Err(panic)) => std::panic::resume_unwind(panic),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Iterator for ClientSession {
+    type Item = PacketTypes<'static>;
+
+    fn next(&mut self) -> Option<PacketTypes<'static>> {
+        self.receiver.recv().ok()
+    }
+}
+
+impl Drop for ClientSession {
+    fn drop(&mut self) {
+        let _ = self.close();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+
+    fn identification() -> ClientIdentification<'static> {
+        ClientIdentification {
+            from: "".into(),
+            to: "".into(),
+            client_id: "de1e".into(),
+            client_name: "fsdparser".into(),
+            major_version: 1,
+            minor_version: 2,
+            cid: "1234567".into(),
+            sys_id: "abc".into(),
+            initial_challenge: "".into(),
+        }
+    }
+
+    fn pilot() -> NetworkClient<'static> {
+        NetworkClient {
+            client_type: NetworkClientType::Pilot,
+            callsign: "N123".into(),
+            to: "SERVER".into(),
+            real_name: "Jane Doe".into(),
+            cid: "1234567".into(),
+            password: "pass".into(),
+            rating: NetworkRating::OBS,
+            simulator_type: Some(SimulatorType::XPlane),
+            protocol_ver: 100,
+        }
+    }
+
+    fn position() -> PilotPosition<'static> {
+        PilotPosition {
+            callsign: "N123".into(),
+            squawk_code: 1200,
+            squawking: SquawkType::Charlie,
+            rating: NetworkRating::OBS,
+            lat: 42.5,
+            lon: -71.25,
+            true_alt: 3000,
+            pressure_alt: 3000,
+            ground_speed: 110,
+            pbh: FlightSurfaces {
+                pitch: 0.0,
+                bank: 0.0,
+                hdg: 90.0,
+                flags: 0,
+            },
+        }
+    }
+
+    #[test]
+    fn test_session() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut writer = stream.try_clone().unwrap();
+            let mut lines = BufReader::new(stream).lines();
+            let mut next = move || lines.next().unwrap().unwrap();
+
+            writer
+                .write_all(b"$DISERVER:CLIENT:VATSIM FSD V3.13:0123456789abcdef\r\n")
+                .unwrap();
+            let login = vec![next(), next()];
+            writer
+                .write_all(
+                    b"$CQBOS_APP:N123:CAPS\r\n$CQBOS_APP:N123:RN\r\n$CQBOS_APP:OTHER:RN\r\n\
+                      $CQBOS_APP:N123:INF\r\n$CQBOS_APP:N123:ATC:N123\r\n",
+                )
+                .unwrap();
+
+            let mut answers = Vec::new();
+            let mut positions = 0;
+            while answers.len() < 4 || positions < 2 {
+                match next() {
+                    line if line.starts_with('@') => positions += 1,
+                    line => answers.push(line),
+                }
+            }
+            writer.write_all(b"#TMSERVER:N123:bye\r\n").unwrap();
+            let logoff = loop {
+                match next() {
+                    line if line.starts_with('@') => (),
+                    line => break line,
+                }
+            };
+            (login, answers, logoff)
+        });
+
+        let mut client = FsdClient::new(identification(), pilot());
+        client.set_position_interval(Duration::from_millis(10));
+        client.set_pilot_position(position());
+        let mut session = client.connect(address).unwrap();
+        assert_eq!(session.callsign(), "N123");
+
+        let mut queries = 0;
+        let text = loop {
+            match session.next().unwrap() {
+                PacketTypes::ClientQuery(_) => queries += 1,
+                PacketTypes::TextMessage(message) => break message.text,
+                packet => panic!("Unexpected {:?}", packet),
+            }
+        };
+        assert_eq!((queries, text.as_ref()), (5, "bye"));
+        session.disconnect().unwrap();
+
+        let (login, answers, logoff) = server.join().unwrap();
+        assert_eq!(
+            login,
+            vec![
+                "$IDN123:SERVER:de1e:fsdparser:1:2:1234567:abc:",
+                "#APN123:SERVER:1234567:pass:1:100:6:Jane Doe"
+            ]
+        );
+        assert_eq!(
+            answers,
+            vec![
+                "$CRN123:BOS_APP:CAPS:VERSION=1",
+                "$CRN123:BOS_APP:RN:Jane Doe::1",
+                "$CRN123:BOS_APP:INF:fsdparser 1.2 CID=1234567",
+                "$CRN123:BOS_APP:ATC:N:N123"
+            ]
+        );
+        assert_eq!(logoff, "#DPN123:1234567");
+    }
+
+    // Not what any network uses, but it shows both the initial and the later challenge
+    struct TestResponder(String);
+
+    impl ChallengeResponder for TestResponder {
+        fn initialize(&mut self, initial_challenge: &str) {
+            self.0 = initial_challenge.to_string();
+        }
+
+        fn respond(&mut self, challenge: &str) -> String {
+            format!("{}{}", self.0, challenge.chars().rev().collect::<String>())
+        }
+    }
+
+    #[test]
+    fn test_challenge() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut writer = stream.try_clone().unwrap();
+            let mut lines = BufReader::new(stream).lines();
+
+            writer
+                .write_all(b"$DISERVER:CLIENT:VATSIM FSD V3.13:abc\r\n")
+                .unwrap();
+            lines.next();
+            lines.next();
+            writer
+                .write_all(b"$ZCSERVER:OTHER:999\r\n$ZCSERVER:N123:123\r\n")
+                .unwrap();
+            lines.next().unwrap().unwrap()
+        });
+
+        let mut client = FsdClient::new(identification(), pilot());
+        client.set_challenge_responder(TestResponder(String::new()));
+        let session = client.connect(address).unwrap();
+        assert_eq!(server.join().unwrap(), "$ZRN123:SERVER:abc321");
+        // The application still sees the challenges
+        assert!(matches!(
+            session.receiver().recv_timeout(Duration::from_secs(5)),
+            Ok(PacketTypes::AuthChallenge(_))
+        ));
+    }
+
+    #[test]
+    fn test_no_greeting() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = thread::spawn(move || drop(listener.accept().unwrap()));
+
+        let error = FsdClient::new(identification(), pilot())
+            .connect(address)
+            .err()
+            .unwrap();
+        assert_eq!(error.kind(), ErrorKind::ConnectionAborted);
+        server.join().unwrap();
+    }
+}
@@ -0,0 +1,270 @@
+#![cfg(feature = "tokio")]
+use crate::error::ParseError;
+use crate::lines::{from_latin1, to_latin1};
+use crate::parser::{PacketTypes, Parser};
+use bytes::{Buf, BufMut, BytesMut};
+use std::fmt::{Display, Formatter};
+use std::io;
+use tokio_util::codec::{Decoder, Encoder};
+
+/// Longest line accepted by `FsdCodec::new` not counting the `\n`, well above anything a
+/// client or server sends
+pub const DEFAULT_MAX_LINE_LENGTH: usize = 8192;
+
+/// Reasons an FSD stream could not be decoded or encoded
+#[derive(Debug)]
+pub enum FsdCodecError {
+    Io(io::Error),
+    /// A line went on for more than the maximum length, it is skipped up to its end
+    LineTooLong,
+    /// A complete line that did not parse
+    Parse {
+        line: String,
+        error: ParseError,
+    },
+}
+
+impl Display for FsdCodecError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FsdCodecError::Io(error) => write!(f, "{}", error),
+            FsdCodecError::LineTooLong => write!(f, "Line exceeds the maximum length"),
+            FsdCodecError::Parse { line, error } => write!(f, "{}: {}", error, line),
+        }
+    }
+}
+
+impl std::error::Error for FsdCodecError {}
+
+impl From<io::Error> for FsdCodecError {
+    fn from(error: io::Error) -> Self {
+        FsdCodecError::Io(error)
+    }
+}
+
+/// Frames a byte stream into FSD packets, for use with `tokio_util::codec::Framed`.
+///
+/// Lines end in `\r\n`, a bare `\n` is accepted too and empty lines are skipped. Bytes are
+/// read as Latin-1, like the sniffer does. A framed stream ends after its first error, so
+/// `set_skip_invalid` is useful when reading from servers that speak a newer protocol.
+#[derive(Debug, Clone)]
+pub struct FsdCodec {
+    max_length: usize,
+    skip_invalid: bool,
+    // Where to continue looking for the end of the line
+    next_index: usize,
+    // Set while throwing away the rest of an overlong line
+    discarding: bool,
+}
+
+impl FsdCodec {
+    pub fn new() -> Self {
+        Self::with_max_length(DEFAULT_MAX_LINE_LENGTH)
+    }
+
+    pub fn with_max_length(max_length: usize) -> Self {
+        FsdCodec {
+            max_length,
+            skip_invalid: false,
+            next_index: 0,
+            discarding: false,
+        }
+    }
+
+    pub fn max_length(&self) -> usize {
+        self.max_length
+    }
+
+    /// Silently drops lines that do not parse or are too long instead of returning an error
+    pub fn set_skip_invalid(&mut self, skip: bool) {
+        self.skip_invalid = skip;
+    }
+
+    // The next complete line without its terminator, or an error for an overlong one
+    fn next_line(&mut self, buffer: &mut BytesMut) -> Result<Option<String>, FsdCodecError> {
+        loop {
+            let end = buffer[self.next_index..]
+                .iter()
+                .position(|byte| *byte == b'\n')
+                .map(|offset| self.next_index + offset);
+
+            match (self.discarding, end) {
+                (true, Some(end)) => {
+                    buffer.advance(end + 1);
+                    self.next_index = 0;
+                    self.discarding = false;
+                }
+                (true, None) => {
+                    buffer.clear();
+                    self.next_index = 0;
+                    return Ok(None);
+                }
+                (false, Some(end)) if end <= self.max_length => {
+                    let line = buffer.split_to(end + 1);
+                    self.next_index = 0;
+                    let line = match line.strip_suffix(b"\r\n") {
+                        Some(line) => line,
+                        None => &line[..line.len() - 1],
+                    };
+                    return Ok(Some(from_latin1(line)));
+                }
+                (false, None) if buffer.len() <= self.max_length => {
+                    self.next_index = buffer.len();
+                    return Ok(None);
+                }
+                (false, _) => {
+                    self.discarding = true;
+                    self.next_index = 0;
+                    if !self.skip_invalid {
+                        return Err(FsdCodecError::LineTooLong);
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Default for FsdCodec {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Decoder for FsdCodec {
+    type Item = PacketTypes<'static>;
+    type Error = FsdCodecError;
+
+    fn decode(&mut self, buffer: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        while let Some(line) = self.next_line(buffer)? {
+            if line.is_empty() {
+                continue;
+            }
+            match Parser::parse(&line).map(PacketTypes::into_owned) {
+                Ok(packet) => return Ok(Some(packet)),
+                Err(_) if self.skip_invalid => (),
+                Err(error) => return Err(FsdCodecError::Parse { line, error }),
+            }
+        }
+        Ok(None)
+    }
+
+    fn decode_eof(&mut self, buffer: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        if let Some(packet) = self.decode(buffer)? {
+            return Ok(Some(packet));
+        }
+        // A final line without a terminator is still a line
+        if !buffer.is_empty() && !self.discarding {
+            buffer.put_u8(b'\n');
+            return self.decode(buffer);
+        }
+        buffer.clear();
+        Ok(None)
+    }
+}
+
+impl Encoder<PacketTypes<'_>> for FsdCodec {
+    type Error = FsdCodecError;
+
+    fn encode(
+        &mut self,
+        packet: PacketTypes<'_>,
+        buffer: &mut BytesMut,
+    ) -> Result<(), Self::Error> {
+        Encoder::<&PacketTypes<'_>>::encode(self, &packet, buffer)
+    }
+}
+
+impl Encoder<&PacketTypes<'_>> for FsdCodec {
+    type Error = FsdCodecError;
+
+    // Characters outside Latin-1 cannot be sent and become `?`
+    fn encode(
+        &mut self,
+        packet: &PacketTypes<'_>,
+        buffer: &mut BytesMut,
+    ) -> Result<(), Self::Error> {
+        let line = packet.to_fsd_string();
+        buffer.reserve(line.len() + 2);
+        buffer.extend(to_latin1(&line));
+        buffer.put_slice(b"\r\n");
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tokio_util::codec::FramedRead;
+
+    #[test]
+    fn test_decode() {
+        let mut codec = FsdCodec::new();
+        let mut buffer = BytesMut::from(&b"#TMA:B:one\r\n\r\n#TMA:B:t"[..]);
+
+        assert!(matches!(
+            codec.decode(&mut buffer).unwrap(),
+            Some(PacketTypes::TextMessage(_))
+        ));
+        assert!(codec.decode(&mut buffer).unwrap().is_none());
+        buffer.put_slice(b"wo\n");
+        match codec.decode(&mut buffer).unwrap() {
+            Some(PacketTypes::TextMessage(message)) => assert_eq!(message.text, "two"),
+            packet => panic!("Unexpected {:?}", packet),
+        }
+        assert!(buffer.is_empty());
+
+        buffer.put_slice(b"not fsd\r\n");
+        assert!(matches!(
+            codec.decode(&mut buffer),
+            Err(FsdCodecError::Parse { line, .. }) if line == "not fsd"
+        ));
+    }
+
+    #[test]
+    fn test_max_length() {
+        let mut codec = FsdCodec::with_max_length(12);
+        let mut buffer = BytesMut::from(&b"#TMA:B:too long"[..]);
+        assert!(matches!(
+            codec.decode(&mut buffer),
+            Err(FsdCodecError::LineTooLong)
+        ));
+        // The rest of the long line is thrown away
+        buffer.put_slice(b" still\r\n#TMA:B:ok\r\n");
+        match codec.decode(&mut buffer).unwrap() {
+            Some(PacketTypes::TextMessage(message)) => assert_eq!(message.text, "ok"),
+            packet => panic!("Unexpected {:?}", packet),
+        }
+    }
+
+    #[test]
+    fn test_encode() {
+        let packet: PacketTypes = "#TMA:B:caf\u{e9} \u{2708}".parse().unwrap();
+        let mut buffer = BytesMut::new();
+        FsdCodec::new().encode(&packet, &mut buffer).unwrap();
+        assert_eq!(&buffer[..], b"#TMA:B:caf\xe9 ?\r\n");
+
+        let expected = "#TMA:B:caf\u{e9} ?".parse().unwrap();
+        assert_eq!(FsdCodec::new().decode(&mut buffer).unwrap(), Some(expected));
+    }
+
+    #[tokio::test]
+    async fn test_framed() {
+        use futures_core::Stream;
+        use std::future::poll_fn;
+        use std::pin::Pin;
+
+        let data: &[u8] = b"#TMA:B:one\r\n$XXA:B:unknown\r\n#TMA:B:two";
+        let mut codec = FsdCodec::new();
+        codec.set_skip_invalid(true);
+        let mut framed = FramedRead::new(data, codec);
+
+        let mut texts = Vec::new();
+        while let Some(packet) = poll_fn(|cx| Pin::new(&mut framed).poll_next(cx)).await {
+            match packet.unwrap() {
+                PacketTypes::TextMessage(message) => texts.push(message.text.into_owned()),
+                packet => panic!("Unexpected {:?}", packet),
+            }
+        }
+        assert_eq!(texts, vec!["one", "two"]);
+    }
+}
@@ -0,0 +1,30 @@
+use std::fmt::{Display, Formatter, Result};
+
+/// Reasons a line could not be turned into a packet.
+///
+/// Field indices are zero-based and count the colon separated fields that follow
+/// the command prefix, e.g. for `#TMA:B:text` field 0 is `A`.
+#[derive(PartialEq, Debug, Clone)]
+pub enum ParseError {
+    UnknownCommand(String),
+    MissingField(usize),
+    InvalidNumber(usize),
+    InvalidEnumValue(usize),
+    InvalidJson(String),
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        match self {
+            ParseError::UnknownCommand(command) => write!(f, "Unknown command {:?}", command),
+            ParseError::MissingField(index) => write!(f, "Missing field {}", index),
+            ParseError::InvalidNumber(index) => write!(f, "Invalid number in field {}", index),
+            ParseError::InvalidEnumValue(index) => {
+                write!(f, "Invalid enum value in field {}", index)
+            }
+            ParseError::InvalidJson(error) => write!(f, "Malformed JSON: {}", error),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
@@ -0,0 +1,398 @@
+use crate::error::ParseError;
+use crate::parser::{PacketTypes, Parser};
+use crate::reassembly::Connection;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::SystemTime;
+
+// Lines that must parse before an unknown flow counts as FSD
+const REQUIRED_LINES: usize = 2;
+// Lines the parser does not know, but that look like FSD, tolerated before giving up
+const MAX_UNDECIDED_LINES: usize = 8;
+const MAX_FLOWS: usize = 4096;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Status {
+    Candidate { parsed: usize, undecided: usize },
+    Fsd,
+    NotFsd,
+}
+
+/// Which way a packet travelled
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Direction {
+    ServerToClient,
+    ClientToServer,
+}
+
+/// A packet seen on the wire, with when, where and how it was sent
+#[derive(Debug, Clone)]
+pub struct PacketSource {
+    /// Capture time of the frame that completed the line
+    pub timestamp: SystemTime,
+    /// Sender and receiver of the line
+    pub connection: Connection,
+    pub direction: Direction,
+    /// The line as it was sent, without the line ending
+    pub raw: String,
+    pub packet: PacketTypes<'static>,
+}
+
+impl PacketSource {
+    pub fn is_from_server(&self) -> bool {
+        self.direction == Direction::ServerToClient
+    }
+}
+
+#[derive(Debug)]
+struct Flow {
+    status: Status,
+    server: Option<SocketAddr>,
+    // Whether a line was seen yet, only the first one of a flow is taken for a handshake
+    started: bool,
+    // Packets seen while the flow was still being validated
+    pending: Vec<(Connection, SystemTime, String, PacketTypes<'static>)>,
+}
+
+/// Decides which TCP flows carry FSD and which end of them is the server.
+///
+/// Flows already matched by address or port are always FSD. Others are only considered with
+/// content detection on, in which case their first lines have to parse. The server side of
+/// flows the rules did not match is learned from the login handshake opening them: `$DI` is
+/// sent by the server, `$ID`, `#AP` and `#AA` by the client.
+#[derive(Debug, Default)]
+pub struct FlowClassifier {
+    flows: HashMap<(SocketAddr, SocketAddr), Flow>,
+    content_detection: bool,
+}
+
+// Both directions of a connection share one entry
+fn key(connection: &Connection) -> (SocketAddr, SocketAddr) {
+    if connection.source < connection.destination {
+        (connection.source, connection.destination)
+    } else {
+        (connection.destination, connection.source)
+    }
+}
+
+// Unknown commands of the usual `#XX` or `$XX` form neither prove nor disprove a flow
+fn looks_like_fsd(line: &str) -> bool {
+    let bytes = line.as_bytes();
+    bytes.len() > 3
+        && (bytes[0] == b'#' || bytes[0] == b'$')
+        && bytes[1..3]
+            .iter()
+            .all(|byte| byte.is_ascii_uppercase() || *byte == b'!')
+        && line.contains(':')
+}
+
+fn handshake_server(connection: &Connection, line: &str) -> Option<SocketAddr> {
+    if line.starts_with("$DI") {
+        Some(connection.source)
+    } else if line.starts_with("$ID") || line.starts_with("#AP") || line.starts_with("#AA") {
+        Some(connection.destination)
+    } else {
+        None
+    }
+}
+
+impl FlowClassifier {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Also track flows that match no server address or port and check their contents
+    pub fn set_content_detection(&mut self, enabled: bool) {
+        self.content_detection = enabled;
+    }
+
+    /// Whether the segments of a connection need to be reassembled at all
+    pub fn wants(&self, connection: &Connection, matched: bool) -> bool {
+        match self.flows.get(&key(connection)) {
+            Some(flow) => matched || flow.status != Status::NotFsd,
+            None => matched || (self.content_detection && self.flows.len() < MAX_FLOWS),
+        }
+    }
+
+    pub fn is_fsd(&self, connection: &Connection) -> bool {
+        matches!(
+            self.flows.get(&key(connection)),
+            Some(Flow {
+                status: Status::Fsd,
+                ..
+            })
+        )
+    }
+
+    pub fn is_rejected(&self, connection: &Connection) -> bool {
+        matches!(
+            self.flows.get(&key(connection)),
+            Some(Flow {
+                status: Status::NotFsd,
+                ..
+            })
+        )
+    }
+
+    /// The server end of a connection, if the handshake has been seen
+    pub fn server(&self, connection: &Connection) -> Option<SocketAddr> {
+        self.flows
+            .get(&key(connection))
+            .and_then(|flow| flow.server)
+    }
+
+    pub fn remove(&mut self, connection: &Connection) {
+        self.flows.remove(&key(connection));
+    }
+
+    /// Parses the reassembled lines of one direction of a flow.
+    ///
+    /// `matched` is whether the address or port rules recognised the flow, and if so whether
+    /// the sender is the server. `timestamp` is when the lines were captured.
+    pub fn process(
+        &mut self,
+        connection: &Connection,
+        timestamp: SystemTime,
+        lines: &[String],
+        matched: Option<bool>,
+    ) -> Vec<PacketSource> {
+        if matched.is_none() && !self.wants(connection, false) {
+            return Vec::new();
+        }
+
+        let flow = self.flows.entry(key(connection)).or_insert_with(|| Flow {
+            status: Status::Candidate {
+                parsed: 0,
+                undecided: 0,
+            },
+            server: None,
+            started: false,
+            pending: Vec::new(),
+        });
+        if matched.is_some() {
+            flow.status = Status::Fsd;
+        }
+
+        for line in lines {
+            // Servers relay the `#AP` and `#AA` of other clients, so a handshake only counts
+            // when it opens the flow and the capture did not start halfway through
+            if !flow.started {
+                flow.started = true;
+                flow.server = handshake_server(connection, line);
+            }
+
+            let parsed = Parser::parse(line).map(PacketTypes::into_owned);
+            flow.status = match (flow.status, &parsed) {
+                (Status::Candidate { parsed, undecided }, Ok(_)) => {
+                    match parsed + 1 >= REQUIRED_LINES {
+                        true => Status::Fsd,
+                        false => Status::Candidate {
+                            parsed: parsed + 1,
+                            undecided,
+                        },
+                    }
+                }
+                (Status::Candidate { parsed, undecided }, Err(ParseError::UnknownCommand(_)))
+                    if looks_like_fsd(line) && undecided < MAX_UNDECIDED_LINES =>
+                {
+                    Status::Candidate {
+                        parsed,
+                        undecided: undecided + 1,
+                    }
+                }
+                (Status::Candidate { .. }, Err(_)) => Status::NotFsd,
+                (status, _) => status,
+            };
+
+            if flow.status == Status::NotFsd {
+                flow.pending.clear();
+                break;
+            }
+            if let Ok(packet) = parsed {
+                flow.pending
+                    .push((*connection, timestamp, line.clone(), packet));
+            }
+        }
+
+        if flow.status != Status::Fsd {
+            return Vec::new();
+        }
+
+        let server = flow.server;
+        flow.pending
+            .drain(..)
+            .map(|(sent_on, timestamp, raw, packet)| {
+                let from_server = match (matched, server) {
+                    // Lines held back from the other direction flip what the rules said
+                    (Some(from_server), _) => from_server == (sent_on == *connection),
+                    (None, Some(server)) => sent_on.source == server,
+                    // Servers listen on a fixed port while clients get an ephemeral one
+                    (None, None) => sent_on.source.port() < sent_on.destination.port(),
+                };
+                PacketSource {
+                    timestamp,
+                    connection: sent_on,
+                    direction: match from_server {
+                        true => Direction::ServerToClient,
+                        false => Direction::ClientToServer,
+                    },
+                    raw,
+                    packet,
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn client_to_server() -> Connection {
+        Connection::new(
+            "10.0.0.2:50000".parse().unwrap(),
+            "10.0.0.1:7000".parse().unwrap(),
+        )
+    }
+
+    fn server_to_client() -> Connection {
+        Connection::new(
+            "10.0.0.1:7000".parse().unwrap(),
+            "10.0.0.2:50000".parse().unwrap(),
+        )
+    }
+
+    fn lines(lines: &[&str]) -> Vec<String> {
+        lines.iter().map(|line| line.to_string()).collect()
+    }
+
+    #[test]
+    fn test_content_detection() {
+        let mut classifier = FlowClassifier::new();
+        assert!(!classifier.wants(&client_to_server(), false));
+        classifier.set_content_detection(true);
+        assert!(classifier.wants(&client_to_server(), false));
+
+        // Held back until enough lines parse
+        assert!(classifier
+            .process(
+                &client_to_server(),
+                SystemTime::UNIX_EPOCH,
+                &lines(&["#TMA:B:one"]),
+                None
+            )
+            .is_empty());
+        let packets = classifier.process(
+            &client_to_server(),
+            SystemTime::UNIX_EPOCH,
+            &lines(&["$XXA:B:unknown", "#TMA:B:two"]),
+            None,
+        );
+        assert_eq!(packets.len(), 2);
+        assert!(classifier.is_fsd(&server_to_client()));
+        // No handshake, so the lower port is taken for the server
+        assert!(packets.iter().all(|packet| !packet.is_from_server()));
+        assert_eq!(packets[0].raw, "#TMA:B:one");
+        assert_eq!(packets[1].raw, "#TMA:B:two");
+    }
+
+    #[test]
+    fn test_rejects_other_protocols() {
+        let mut classifier = FlowClassifier::new();
+        classifier.set_content_detection(true);
+        classifier.process(
+            &client_to_server(),
+            SystemTime::UNIX_EPOCH,
+            &lines(&["GET / HTTP/1.1"]),
+            None,
+        );
+        assert!(classifier.is_rejected(&server_to_client()));
+        assert!(!classifier.wants(&server_to_client(), false));
+        assert!(classifier
+            .process(
+                &client_to_server(),
+                SystemTime::UNIX_EPOCH,
+                &lines(&["#TMA:B:x", "#TMA:B:y"]),
+                None
+            )
+            .is_empty());
+    }
+
+    #[test]
+    fn test_handshake_server() {
+        let mut classifier = FlowClassifier::new();
+        // Port rules would call the sender the server, the $DI says otherwise
+        let packets = classifier.process(
+            &client_to_server(),
+            SystemTime::UNIX_EPOCH,
+            &lines(&["$DISERVER:CLIENT:VATSIM FSD V3.13:abc", "#TMA:B:x"]),
+            Some(true),
+        );
+        assert_eq!(
+            classifier.server(&server_to_client()),
+            Some(client_to_server().source)
+        );
+        assert_eq!(packets.len(), 2);
+        assert!(packets.iter().all(PacketSource::is_from_server));
+        assert!(matches!(
+            packets[0].packet,
+            PacketTypes::ServerIdentification(_)
+        ));
+        assert_eq!(packets[1].raw, "#TMA:B:x");
+        assert_eq!(packets[1].connection, client_to_server());
+
+        let mut classifier = FlowClassifier::new();
+        classifier.set_content_detection(true);
+        classifier.process(
+            &client_to_server(),
+            SystemTime::UNIX_EPOCH,
+            &lines(&["#APN123:SERVER:1234567:pass:1:100:1:Name"]),
+            None,
+        );
+        assert_eq!(
+            classifier.server(&client_to_server()),
+            Some(client_to_server().destination)
+        );
+    }
+
+    #[test]
+    fn test_relayed_handshake() {
+        // The capture started halfway through the session, another pilot logging in is
+        // relayed by the server
+        let mut classifier = FlowClassifier::new();
+        let packets = classifier.process(
+            &server_to_client(),
+            SystemTime::UNIX_EPOCH,
+            &lines(&["#TMSERVER:N123:hello"]),
+            Some(true),
+        );
+        assert!(packets[0].is_from_server());
+        let packets = classifier.process(
+            &server_to_client(),
+            SystemTime::UNIX_EPOCH,
+            &lines(&["#APN456:SERVER:7654321::1:100:1:Other"]),
+            Some(true),
+        );
+        assert_eq!(classifier.server(&client_to_server()), None);
+        assert_eq!(packets.len(), 1);
+        assert!(packets[0].is_from_server());
+
+        // Even as the first line seen, the rules decide who sent it
+        let mut classifier = FlowClassifier::new();
+        let packets = classifier.process(
+            &server_to_client(),
+            SystemTime::UNIX_EPOCH,
+            &lines(&["#APN456:SERVER:7654321::1:100:1:Other"]),
+            Some(true),
+        );
+        assert_eq!(packets.len(), 1);
+        assert!(packets[0].is_from_server());
+        let packets = classifier.process(
+            &client_to_server(),
+            SystemTime::UNIX_EPOCH,
+            &lines(&["#TMN123:SERVER:hi"]),
+            Some(false),
+        );
+        assert!(!packets[0].is_from_server());
+    }
+}
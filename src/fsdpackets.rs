@@ -1,34 +1,76 @@
 use num_derive::FromPrimitive;
 use num_traits::FromPrimitive;
 
+use crate::error::ParseError;
 use crate::util::Frequency;
 use serde_json::Value;
-use std::fmt::{Formatter, Result};
-
-macro_rules! to_enum {
-    ($var:expr) => {
-        FromPrimitive::from_u8(force_parse!(u8, $var))
+use std::borrow::Cow;
+use std::fmt::{self, Formatter};
+
+macro_rules! get_field {
+    ($fields:expr, $index:expr) => {
+        *$fields
+            .get($index)
+            .ok_or(ParseError::MissingField($index))?
     };
 }
 
 macro_rules! force_parse {
-    ($to_type:ty, $var:expr) => {
-        $var.parse::<$to_type>().unwrap()
+    ($to_type:ty, $fields:expr, $index:expr) => {
+        get_field!($fields, $index)
+            .parse::<$to_type>()
+            .map_err(|_| ParseError::InvalidNumber($index))?
+    };
+}
+
+macro_rules! to_enum {
+    ($fields:expr, $index:expr) => {
+        FromPrimitive::from_u8(force_parse!(u8, $fields, $index))
+            .ok_or(ParseError::InvalidEnumValue($index))?
     };
 }
 
-pub trait Packet {
-    fn from_string(fields: &Vec<&str>) -> Self;
+fn owned(data: Cow<'_, str>) -> Cow<'static, str> {
+    Cow::Owned(data.into_owned())
+}
+
+// The last field of packets whose free text can contain the delimiter
+fn join_fields<'a>(fields: &[&'a str]) -> Cow<'a, str> {
+    match fields {
+        [] => Cow::Borrowed(""),
+        [field] => Cow::Borrowed(field),
+        _ => fields.join(":").into(),
+    }
+}
+
+pub trait Packet<'a>: Sized {
+    fn from_string(fields: &[&'a str]) -> Result<Self, ParseError>;
+    /// Encodes the packet as a single FSD line, including the command prefix but without the line terminator
+    fn to_fsd_string(&self) -> String;
 }
 
 #[derive(PartialEq, Debug, Clone)]
-pub enum TextMessageReceiver {
+pub enum TextMessageReceiver<'a> {
     Broadcast,
     Wallop,
     ATC,
-    PrivateMessage,
+    PrivateMessage(Cow<'a, str>), // Callsign
     Radio(Frequency),
 }
+
+impl<'a> TextMessageReceiver<'a> {
+    pub fn into_owned(self) -> TextMessageReceiver<'static> {
+        match self {
+            TextMessageReceiver::Broadcast => TextMessageReceiver::Broadcast,
+            TextMessageReceiver::Wallop => TextMessageReceiver::Wallop,
+            TextMessageReceiver::ATC => TextMessageReceiver::ATC,
+            TextMessageReceiver::PrivateMessage(callsign) => {
+                TextMessageReceiver::PrivateMessage(owned(callsign))
+            }
+            TextMessageReceiver::Radio(freq) => TextMessageReceiver::Radio(freq),
+        }
+    }
+}
 #[derive(FromPrimitive, PartialEq, Debug, Clone)]
 pub enum NetworkFacility {
     OBS,
@@ -43,13 +85,14 @@ pub enum NetworkFacility {
 
 impl NetworkFacility {
     fn from_string(data: &str) -> Self {
-        if data == "" {
-            return NetworkFacility::Undefined;
-        }
-        match to_enum!(data) {
-            Some(ok) => ok,
-            None => NetworkFacility::Undefined,
-        }
+        data.parse::<u8>()
+            .ok()
+            .and_then(FromPrimitive::from_u8)
+            .unwrap_or(NetworkFacility::Undefined)
+    }
+
+    fn to_packet_string(&self) -> String {
+        (self.clone() as u8).to_string()
     }
 }
 
@@ -71,34 +114,35 @@ pub enum NetworkRating {
 }
 
 impl std::fmt::Display for NetworkRating {
-    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         match self {
-            NetworkRating::Undefined => write!(f, "{}", "Undefined"),
-            NetworkRating::OBS => write!(f, "{}", "OBS"),
-            NetworkRating::S1 => write!(f, "{}", "S1"),
-            NetworkRating::S2 => write!(f, "{}", "S2"),
-            NetworkRating::S3 => write!(f, "{}", "S3"),
-            NetworkRating::C1 => write!(f, "{}", "C1"),
-            NetworkRating::C2 => write!(f, "{}", "C2"),
-            NetworkRating::C3 => write!(f, "{}", "C3"),
-            NetworkRating::I1 => write!(f, "{}", "I1"),
-            NetworkRating::I2 => write!(f, "{}", "I2"),
-            NetworkRating::I3 => write!(f, "{}", "I3"),
-            NetworkRating::SUP => write!(f, "{}", "SUP"),
-            NetworkRating::ADM => write!(f, "{}", "ADM"),
+            NetworkRating::Undefined => write!(f, "Undefined"),
+            NetworkRating::OBS => write!(f, "OBS"),
+            NetworkRating::S1 => write!(f, "S1"),
+            NetworkRating::S2 => write!(f, "S2"),
+            NetworkRating::S3 => write!(f, "S3"),
+            NetworkRating::C1 => write!(f, "C1"),
+            NetworkRating::C2 => write!(f, "C2"),
+            NetworkRating::C3 => write!(f, "C3"),
+            NetworkRating::I1 => write!(f, "I1"),
+            NetworkRating::I2 => write!(f, "I2"),
+            NetworkRating::I3 => write!(f, "I3"),
+            NetworkRating::SUP => write!(f, "SUP"),
+            NetworkRating::ADM => write!(f, "ADM"),
         }
     }
 }
 
 impl NetworkRating {
     fn from_string(data: &str) -> Self {
-        if data == "" {
-            return NetworkRating::Undefined;
-        }
-        match to_enum!(data) {
-            Some(ok) => ok,
-            None => NetworkRating::Undefined,
-        }
+        data.parse::<u8>()
+            .ok()
+            .and_then(FromPrimitive::from_u8)
+            .unwrap_or(NetworkRating::Undefined)
+    }
+
+    fn to_packet_string(&self) -> String {
+        (self.clone() as u8).to_string()
     }
 }
 
@@ -155,8 +199,9 @@ pub enum SquawkType {
 }
 
 #[derive(PartialEq, Debug, Clone)]
-pub enum ClientQueryType {
-    Unknown,
+pub enum ClientQueryType<'a> {
+    /// A query this crate does not know, with its command so it can be passed on
+    Unknown(Cow<'a, str>),
     IsValidATC,
     Capabilities,
     COM1Freq,
@@ -185,207 +230,524 @@ pub enum ClientQueryType {
     NewATIS,
 }
 
+impl<'a> ClientQueryType<'a> {
+    pub fn from_command(command: &'a str) -> Self {
+        match command {
+            "ATC" => ClientQueryType::IsValidATC,
+            "CAPS" => ClientQueryType::Capabilities,
+            "C?" => ClientQueryType::COM1Freq,
+            "RN" => ClientQueryType::RealName,
+            "SV" => ClientQueryType::Server,
+            "ATIS" => ClientQueryType::ATIS,
+            "IP" => ClientQueryType::PublicIP,
+            "INF" => ClientQueryType::INF,
+            "FP" => ClientQueryType::FlightPlan,
+            "IPC" => ClientQueryType::IPC,
+            "BY" => ClientQueryType::RequestRelief,
+            "HI" => ClientQueryType::CancelRequestRelief,
+            "HLP" => ClientQueryType::RequestHelp,
+            "NOHLP" => ClientQueryType::CancelRequestHelp,
+            "WH" => ClientQueryType::WhoHas,
+            "IT" => ClientQueryType::InitiateTrack,
+            "HT" => ClientQueryType::AcceptHandoff,
+            "DR" => ClientQueryType::DropTrack,
+            "FA" => ClientQueryType::SetFinalAltitude,
+            "TA" => ClientQueryType::SetTempAltitude,
+            "BC" => ClientQueryType::SetBeaconCode,
+            "SC" => ClientQueryType::SetScratchpad,
+            "VT" => ClientQueryType::SetVoiceType,
+            "ACC" => ClientQueryType::AircraftConfiguration,
+            "NEWINFO" => ClientQueryType::NewInfo,
+            "NEWATIS" => ClientQueryType::NewATIS,
+            _ => ClientQueryType::Unknown(command.into()),
+        }
+    }
+
+    pub fn into_owned(self) -> ClientQueryType<'static> {
+        match self {
+            ClientQueryType::Unknown(command) => ClientQueryType::Unknown(owned(command)),
+            ClientQueryType::IsValidATC => ClientQueryType::IsValidATC,
+            ClientQueryType::Capabilities => ClientQueryType::Capabilities,
+            ClientQueryType::COM1Freq => ClientQueryType::COM1Freq,
+            ClientQueryType::RealName => ClientQueryType::RealName,
+            ClientQueryType::Server => ClientQueryType::Server,
+            ClientQueryType::ATIS => ClientQueryType::ATIS,
+            ClientQueryType::PublicIP => ClientQueryType::PublicIP,
+            ClientQueryType::INF => ClientQueryType::INF,
+            ClientQueryType::FlightPlan => ClientQueryType::FlightPlan,
+            ClientQueryType::IPC => ClientQueryType::IPC,
+            ClientQueryType::RequestRelief => ClientQueryType::RequestRelief,
+            ClientQueryType::CancelRequestRelief => ClientQueryType::CancelRequestRelief,
+            ClientQueryType::RequestHelp => ClientQueryType::RequestHelp,
+            ClientQueryType::CancelRequestHelp => ClientQueryType::CancelRequestHelp,
+            ClientQueryType::WhoHas => ClientQueryType::WhoHas,
+            ClientQueryType::InitiateTrack => ClientQueryType::InitiateTrack,
+            ClientQueryType::AcceptHandoff => ClientQueryType::AcceptHandoff,
+            ClientQueryType::DropTrack => ClientQueryType::DropTrack,
+            ClientQueryType::SetFinalAltitude => ClientQueryType::SetFinalAltitude,
+            ClientQueryType::SetTempAltitude => ClientQueryType::SetTempAltitude,
+            ClientQueryType::SetBeaconCode => ClientQueryType::SetBeaconCode,
+            ClientQueryType::SetScratchpad => ClientQueryType::SetScratchpad,
+            ClientQueryType::SetVoiceType => ClientQueryType::SetVoiceType,
+            ClientQueryType::AircraftConfiguration => ClientQueryType::AircraftConfiguration,
+            ClientQueryType::NewInfo => ClientQueryType::NewInfo,
+            ClientQueryType::NewATIS => ClientQueryType::NewATIS,
+        }
+    }
+
+    pub fn to_command(&self) -> &str {
+        match self {
+            ClientQueryType::Unknown(command) => command,
+            ClientQueryType::IsValidATC => "ATC",
+            ClientQueryType::Capabilities => "CAPS",
+            ClientQueryType::COM1Freq => "C?",
+            ClientQueryType::RealName => "RN",
+            ClientQueryType::Server => "SV",
+            ClientQueryType::ATIS => "ATIS",
+            ClientQueryType::PublicIP => "IP",
+            ClientQueryType::INF => "INF",
+            ClientQueryType::FlightPlan => "FP",
+            ClientQueryType::IPC => "IPC",
+            ClientQueryType::RequestRelief => "BY",
+            ClientQueryType::CancelRequestRelief => "HI",
+            ClientQueryType::RequestHelp => "HLP",
+            ClientQueryType::CancelRequestHelp => "NOHLP",
+            ClientQueryType::WhoHas => "WH",
+            ClientQueryType::InitiateTrack => "IT",
+            ClientQueryType::AcceptHandoff => "HT",
+            ClientQueryType::DropTrack => "DR",
+            ClientQueryType::SetFinalAltitude => "FA",
+            ClientQueryType::SetTempAltitude => "TA",
+            ClientQueryType::SetBeaconCode => "BC",
+            ClientQueryType::SetScratchpad => "SC",
+            ClientQueryType::SetVoiceType => "VT",
+            ClientQueryType::AircraftConfiguration => "ACC",
+            ClientQueryType::NewInfo => "NEWINFO",
+            ClientQueryType::NewATIS => "NEWATIS",
+        }
+    }
+}
+
 #[derive(PartialEq, Debug, Clone)]
-pub struct TextMessage {
-    pub sender: String,
-    pub receiver: TextMessageReceiver,
-    pub text: String,
+pub struct TextMessage<'a> {
+    pub sender: Cow<'a, str>,
+    pub receiver: TextMessageReceiver<'a>,
+    pub text: Cow<'a, str>,
+}
+
+impl<'a> TextMessage<'a> {
+    pub fn into_owned(self) -> TextMessage<'static> {
+        TextMessage {
+            sender: owned(self.sender),
+            receiver: self.receiver.into_owned(),
+            text: owned(self.text),
+        }
+    }
 }
 
-impl Packet for TextMessage {
-    fn from_string(fields: &Vec<&str>) -> Self {
-        let receiver_str = fields[1];
-        let message = fields[2];
+impl<'a> Packet<'a> for TextMessage<'a> {
+    fn from_string(fields: &[&'a str]) -> Result<Self, ParseError> {
+        let receiver_str = get_field!(fields, 1);
+        // Messages can contain the delimiter
+        if fields.len() < 3 {
+            return Err(ParseError::MissingField(2));
+        }
+        let message = join_fields(&fields[2..]);
 
         let receiver = match receiver_str {
             "*" => TextMessageReceiver::Broadcast,
             "*S" => TextMessageReceiver::Wallop,
             "@49999" => TextMessageReceiver::ATC,
-            _ => match &receiver_str[0..1] {
-                "@" => {
-                    TextMessageReceiver::Radio(Frequency::from_packet_string(&receiver_str[1..]))
-                }
-                _ => TextMessageReceiver::PrivateMessage,
+            _ => match receiver_str.strip_prefix('@') {
+                Some(freq) => TextMessageReceiver::Radio(
+                    Frequency::from_packet_string(freq).ok_or(ParseError::InvalidNumber(1))?,
+                ),
+                None => TextMessageReceiver::PrivateMessage(receiver_str.into()),
             },
         };
 
-        return TextMessage {
-            text: message.to_string(),
-            receiver: receiver,
-            sender: fields[0].to_string(),
+        Ok(TextMessage {
+            text: message,
+            receiver,
+            sender: get_field!(fields, 0).into(),
+        })
+    }
+
+    fn to_fsd_string(&self) -> String {
+        let receiver = match &self.receiver {
+            TextMessageReceiver::Broadcast => "*".to_string(),
+            TextMessageReceiver::Wallop => "*S".to_string(),
+            TextMessageReceiver::ATC => "@49999".to_string(),
+            TextMessageReceiver::PrivateMessage(callsign) => callsign.to_string(),
+            TextMessageReceiver::Radio(freq) => format!("@{}", freq.to_packet_string()),
         };
+
+        format!("#TM{}:{}:{}", self.sender, receiver, self.text)
     }
 }
 
 #[derive(PartialEq, Debug, Clone)]
-pub struct NetworkClient {
+pub struct NetworkClient<'a> {
     pub client_type: NetworkClientType,
-    pub callsign: String,
-    pub real_name: String,
-    pub cid: String,
-    pub password: String,
+    pub callsign: Cow<'a, str>,
+    /// Who the login is sent to, normally `SERVER`
+    pub to: Cow<'a, str>,
+    pub real_name: Cow<'a, str>,
+    pub cid: Cow<'a, str>,
+    pub password: Cow<'a, str>,
     pub rating: NetworkRating,
     pub simulator_type: Option<SimulatorType>,
     pub protocol_ver: u8,
 }
 
-impl Packet for NetworkClient {
-    fn from_string(fields: &Vec<&str>) -> Self {
-        return NetworkClient::new(fields, NetworkClientType::Undefined);
+impl<'a> Packet<'a> for NetworkClient<'a> {
+    fn from_string(fields: &[&'a str]) -> Result<Self, ParseError> {
+        NetworkClient::new(fields, NetworkClientType::Undefined)
+    }
+
+    fn to_fsd_string(&self) -> String {
+        match self.client_type {
+            NetworkClientType::ATC => format!(
+                "#AA{}:{}:{}:{}:{}:{}:{}",
+                self.callsign,
+                self.to,
+                self.real_name,
+                self.cid,
+                self.password,
+                self.rating.to_packet_string(),
+                self.protocol_ver
+            ),
+            _ => format!(
+                "#AP{}:{}:{}:{}:{}:{}:{}:{}",
+                self.callsign,
+                self.to,
+                self.cid,
+                self.password,
+                self.rating.to_packet_string(),
+                self.protocol_ver,
+                self.simulator_type
+                    .clone()
+                    .unwrap_or(SimulatorType::Unknown) as u8,
+                self.real_name
+            ),
+        }
     }
 }
 
-impl NetworkClient {
-    pub fn new(fields: &Vec<&str>, client: NetworkClientType) -> Self {
-        return match client {
+impl<'a> NetworkClient<'a> {
+    pub fn into_owned(self) -> NetworkClient<'static> {
+        NetworkClient {
+            client_type: self.client_type,
+            callsign: owned(self.callsign),
+            to: owned(self.to),
+            real_name: owned(self.real_name),
+            cid: owned(self.cid),
+            password: owned(self.password),
+            rating: self.rating,
+            simulator_type: self.simulator_type,
+            protocol_ver: self.protocol_ver,
+        }
+    }
+
+    pub fn new(fields: &[&'a str], client: NetworkClientType) -> Result<Self, ParseError> {
+        Ok(match client {
             NetworkClientType::ATC => Self {
-                callsign: fields[0].to_string(),
-                real_name: fields[2].to_string(),
-                cid: fields[3].to_string(),
-                password: fields[4].to_string(),
-                rating: NetworkRating::from_string(fields[5]),
+                callsign: get_field!(fields, 0).into(),
+                to: get_field!(fields, 1).into(),
+                real_name: get_field!(fields, 2).into(),
+                cid: get_field!(fields, 3).into(),
+                password: get_field!(fields, 4).into(),
+                rating: NetworkRating::from_string(get_field!(fields, 5)),
                 simulator_type: None,
-                protocol_ver: 0,
+                protocol_ver: force_parse!(u8, fields, 6),
                 client_type: client,
             },
             _ => Self {
-                callsign: fields[0].to_string(),
-                cid: fields[2].to_string(),
-                password: fields[3].to_string(),
-                rating: NetworkRating::from_string(fields[4]),
-                protocol_ver: force_parse!(u8, fields[5]),
-                simulator_type: Some(to_enum!(fields[6]).unwrap_or(SimulatorType::Unknown)),
-                real_name: fields[7].to_string(),
+                callsign: get_field!(fields, 0).into(),
+                to: get_field!(fields, 1).into(),
+                cid: get_field!(fields, 2).into(),
+                password: get_field!(fields, 3).into(),
+                rating: NetworkRating::from_string(get_field!(fields, 4)),
+                protocol_ver: force_parse!(u8, fields, 5),
+                simulator_type: Some(
+                    FromPrimitive::from_u8(force_parse!(u8, fields, 6))
+                        .unwrap_or(SimulatorType::Unknown),
+                ),
+                real_name: get_field!(fields, 7).into(),
                 client_type: client,
             },
-        };
+        })
     }
 }
 
 #[derive(PartialEq, Debug, Clone)]
-pub enum SharedStateType {
+pub enum SharedStateType<'a> {
     Scratchpad,
     BeaconCode,
     VoiceType,
     TempAlt,
-    Unknown,
+    /// A shared state this crate does not know, with its command so it can be passed on
+    Unknown(Cow<'a, str>),
+}
+
+impl<'a> SharedStateType<'a> {
+    pub fn from_command(command: &'a str) -> Self {
+        match command {
+            "SC" => SharedStateType::Scratchpad,
+            "BC" => SharedStateType::BeaconCode,
+            "VT" => SharedStateType::VoiceType,
+            "TA" => SharedStateType::TempAlt,
+            _ => SharedStateType::Unknown(command.into()),
+        }
+    }
+
+    pub fn into_owned(self) -> SharedStateType<'static> {
+        match self {
+            SharedStateType::Scratchpad => SharedStateType::Scratchpad,
+            SharedStateType::BeaconCode => SharedStateType::BeaconCode,
+            SharedStateType::VoiceType => SharedStateType::VoiceType,
+            SharedStateType::TempAlt => SharedStateType::TempAlt,
+            SharedStateType::Unknown(command) => SharedStateType::Unknown(owned(command)),
+        }
+    }
+
+    pub fn to_command(&self) -> &str {
+        match self {
+            SharedStateType::Scratchpad => "SC",
+            SharedStateType::BeaconCode => "BC",
+            SharedStateType::VoiceType => "VT",
+            SharedStateType::TempAlt => "TA",
+            SharedStateType::Unknown(command) => command,
+        }
+    }
 }
 
 #[derive(PartialEq, Debug, Clone)]
-pub struct SharedState {
-    pub from: String,
-    pub to: String,
-    pub target: String,
-    pub value: String,
+pub struct SharedState<'a> {
+    pub from: Cow<'a, str>,
+    pub to: Cow<'a, str>,
+    pub target: Cow<'a, str>,
+    pub value: Cow<'a, str>,
 
-    pub shared_type: SharedStateType,
+    pub shared_type: SharedStateType<'a>,
 }
 
-impl Packet for SharedState {
-    fn from_string(fields: &Vec<&str>) -> Self {
-        return Self::new(fields, SharedStateType::Unknown);
+impl<'a> Packet<'a> for SharedState<'a> {
+    fn from_string(fields: &[&'a str]) -> Result<Self, ParseError> {
+        Self::new(fields, SharedStateType::from_command(get_field!(fields, 3)))
+    }
+
+    fn to_fsd_string(&self) -> String {
+        format!(
+            "#PC{}:{}:CCP:{}:{}:{}",
+            self.from,
+            self.to,
+            self.shared_type.to_command(),
+            self.target,
+            self.value
+        )
     }
 }
 
-impl SharedState {
-    pub fn new(fields: &Vec<&str>, shared_type: SharedStateType) -> Self {
-        return Self {
-            from: fields[0].to_string(),
-            to: fields[1].to_string(),
-            target: fields[4].to_string(),
-            value: fields[5].to_string(),
-            shared_type: shared_type,
-        };
+impl<'a> SharedState<'a> {
+    pub fn into_owned(self) -> SharedState<'static> {
+        SharedState {
+            from: owned(self.from),
+            to: owned(self.to),
+            target: owned(self.target),
+            value: owned(self.value),
+            shared_type: self.shared_type.into_owned(),
+        }
+    }
+
+    pub fn new(fields: &[&'a str], shared_type: SharedStateType<'a>) -> Result<Self, ParseError> {
+        Ok(Self {
+            from: get_field!(fields, 0).into(),
+            to: get_field!(fields, 1).into(),
+            target: get_field!(fields, 4).into(),
+            value: get_field!(fields, 5).into(),
+            shared_type,
+        })
     }
 }
 
 #[derive(PartialEq, Debug, Clone)]
-pub struct FlightStrip {
-    pub from: String,
-    pub to: String,
-    pub target: String,
-    pub format_id: String,
-    pub annotations: Vec<String>,
+pub struct FlightStrip<'a> {
+    pub from: Cow<'a, str>,
+    pub to: Cow<'a, str>,
+    pub target: Cow<'a, str>,
+    pub format_id: Cow<'a, str>,
+    pub annotations: Vec<Cow<'a, str>>,
 }
 
-impl Packet for FlightStrip {
-    fn from_string(fields: &Vec<&str>) -> Self {
-        let mut annotations: Vec<String> = vec![];
-
-        if fields.len() > 6 {
-            for i in 6..fields.len() {
-                annotations.push(fields[i].to_string());
-            }
+impl<'a> FlightStrip<'a> {
+    pub fn into_owned(self) -> FlightStrip<'static> {
+        FlightStrip {
+            from: owned(self.from),
+            to: owned(self.to),
+            target: owned(self.target),
+            format_id: owned(self.format_id),
+            annotations: self.annotations.into_iter().map(owned).collect(),
         }
+    }
+}
 
-        return Self {
-            from: fields[0].to_string(),
-            to: fields[1].to_string(),
-            target: fields[4].to_string(),
-            format_id: if fields.len() > 5 {
-                fields[5].to_string()
-            } else {
-                "".to_string()
-            },
-            annotations: annotations,
-        };
+impl<'a> Packet<'a> for FlightStrip<'a> {
+    fn from_string(fields: &[&'a str]) -> Result<Self, ParseError> {
+        let annotations: Vec<Cow<'a, str>> = fields.iter().skip(6).map(|x| (*x).into()).collect();
+
+        Ok(Self {
+            from: get_field!(fields, 0).into(),
+            to: get_field!(fields, 1).into(),
+            target: get_field!(fields, 4).into(),
+            format_id: fields.get(5).map(|x| (*x).into()).unwrap_or_default(),
+            annotations,
+        })
+    }
+
+    fn to_fsd_string(&self) -> String {
+        let mut data = format!(
+            "#PC{}:{}:CCP:ST:{}:{}",
+            self.from, self.to, self.target, self.format_id
+        );
+        for annotation in &self.annotations {
+            data.push(':');
+            data.push_str(annotation);
+        }
+        data
     }
 }
 
 #[derive(PartialEq, Debug, Clone)]
-pub struct DeleteClient {
+pub struct DeleteClient<'a> {
     pub client_type: NetworkClientType,
-    pub callsign: String,
-    pub cid: String,
+    pub callsign: Cow<'a, str>,
+    pub cid: Cow<'a, str>,
 }
 
-impl Packet for DeleteClient {
-    fn from_string(fields: &Vec<&str>) -> Self {
-        return DeleteClient::new(fields, NetworkClientType::Undefined);
+impl<'a> Packet<'a> for DeleteClient<'a> {
+    fn from_string(fields: &[&'a str]) -> Result<Self, ParseError> {
+        DeleteClient::new(fields, NetworkClientType::Undefined)
+    }
+
+    fn to_fsd_string(&self) -> String {
+        let command = match self.client_type {
+            NetworkClientType::ATC => "#DA",
+            _ => "#DP",
+        };
+
+        format!("{}{}:{}", command, self.callsign, self.cid)
     }
 }
 
-impl DeleteClient {
-    pub fn new(fields: &Vec<&str>, client: NetworkClientType) -> Self {
-        return DeleteClient {
-            callsign: fields[0].to_string(),
-            cid: fields[1].to_string(),
+impl<'a> DeleteClient<'a> {
+    pub fn into_owned(self) -> DeleteClient<'static> {
+        DeleteClient {
+            client_type: self.client_type,
+            callsign: owned(self.callsign),
+            cid: owned(self.cid),
+        }
+    }
+
+    pub fn new(fields: &[&'a str], client: NetworkClientType) -> Result<Self, ParseError> {
+        Ok(DeleteClient {
+            callsign: get_field!(fields, 0).into(),
+            cid: get_field!(fields, 1).into(),
             client_type: client,
-        };
+        })
     }
 }
 
 #[derive(PartialEq, Debug, Clone)]
-pub struct FlightPlan {
-    pub callsign: String,
+pub struct FlightPlan<'a> {
+    pub callsign: Cow<'a, str>,
+    /// Who the flight plan is sent to, `*A` for every controller
+    pub to: Cow<'a, str>,
     pub rule: FlightRules,
-    pub equipment: String,
-    pub tas: String,
-    pub origin: String,
-    pub dep_time: String,
-    pub actual_dep_time: String,
-    pub cruise_alt: String,
-    pub dest: String,
-    pub hours_enroute: String,
-    pub minutes_enroute: String,
-    pub fuel_avail_hours: String,
-    pub fuel_avail_minutes: String,
-    pub alternate: String,
-    pub remarks: String,
-    pub route: String,
-
-    pub amended_by: Option<String>,
+    pub equipment: Cow<'a, str>,
+    pub tas: Cow<'a, str>,
+    pub origin: Cow<'a, str>,
+    pub dep_time: Cow<'a, str>,
+    pub actual_dep_time: Cow<'a, str>,
+    pub cruise_alt: Cow<'a, str>,
+    pub dest: Cow<'a, str>,
+    pub hours_enroute: Cow<'a, str>,
+    pub minutes_enroute: Cow<'a, str>,
+    pub fuel_avail_hours: Cow<'a, str>,
+    pub fuel_avail_minutes: Cow<'a, str>,
+    pub alternate: Cow<'a, str>,
+    pub remarks: Cow<'a, str>,
+    pub route: Cow<'a, str>,
+
+    pub amended_by: Option<Cow<'a, str>>,
 }
 
-impl Packet for FlightPlan {
-    fn from_string(fields: &Vec<&str>) -> Self {
-        return Self::new(fields, None);
+impl<'a> Packet<'a> for FlightPlan<'a> {
+    fn from_string(fields: &[&'a str]) -> Result<Self, ParseError> {
+        Self::new(fields, None)
+    }
+
+    fn to_fsd_string(&self) -> String {
+        let rule = match self.rule {
+            FlightRules::IFR => "I",
+            FlightRules::VFR => "V",
+            FlightRules::DVFR => "D",
+            FlightRules::SVFR => "S",
+            FlightRules::Undefined => "",
+        };
+
+        let data = [
+            rule,
+            &self.equipment,
+            &self.tas,
+            &self.origin,
+            &self.dep_time,
+            &self.actual_dep_time,
+            &self.cruise_alt,
+            &self.dest,
+            &self.hours_enroute,
+            &self.minutes_enroute,
+            &self.fuel_avail_hours,
+            &self.fuel_avail_minutes,
+            &self.alternate,
+            &self.remarks,
+            &self.route,
+        ]
+        .join(":");
+
+        match &self.amended_by {
+            Some(amended_by) => format!("$AM{}:{}:{}:{}", self.callsign, self.to, data, amended_by),
+            None => format!("$FP{}:{}:{}", self.callsign, self.to, data),
+        }
     }
 }
 
-impl FlightPlan {
-    pub fn new(fields: &Vec<&str>, amended: Option<&str>) -> Self {
-        let rule = match fields[2] {
+impl<'a> FlightPlan<'a> {
+    pub fn into_owned(self) -> FlightPlan<'static> {
+        FlightPlan {
+            callsign: owned(self.callsign),
+            to: owned(self.to),
+            rule: self.rule,
+            equipment: owned(self.equipment),
+            tas: owned(self.tas),
+            origin: owned(self.origin),
+            dep_time: owned(self.dep_time),
+            actual_dep_time: owned(self.actual_dep_time),
+            cruise_alt: owned(self.cruise_alt),
+            dest: owned(self.dest),
+            hours_enroute: owned(self.hours_enroute),
+            minutes_enroute: owned(self.minutes_enroute),
+            fuel_avail_hours: owned(self.fuel_avail_hours),
+            fuel_avail_minutes: owned(self.fuel_avail_minutes),
+            alternate: owned(self.alternate),
+            remarks: owned(self.remarks),
+            route: owned(self.route),
+            amended_by: self.amended_by.map(owned),
+        }
+    }
+
+    pub fn new(fields: &[&'a str], amended: Option<&'a str>) -> Result<Self, ParseError> {
+        let rule = match get_field!(fields, 2) {
             "I" | "IFR" => FlightRules::IFR,
             "V" | "VFR" => FlightRules::VFR,
             "D" | "DVFR" => FlightRules::DVFR,
@@ -393,30 +755,26 @@ impl FlightPlan {
             _ => FlightRules::Undefined,
         };
 
-        let amended_by = match amended {
-            Some(callsign) => Some(callsign.to_string()),
-            None => None,
-        };
-
-        return Self {
-            callsign: fields[0].to_string(),
-            rule: rule,
-            equipment: fields[3].to_string(),
-            tas: fields[4].to_string(),
-            origin: fields[5].to_string(),
-            dep_time: fields[6].to_string(),
-            actual_dep_time: fields[7].to_string(),
-            cruise_alt: fields[8].to_string(),
-            dest: fields[9].to_string(),
-            hours_enroute: fields[10].to_string(),
-            minutes_enroute: fields[11].to_string(),
-            fuel_avail_hours: fields[12].to_string(),
-            fuel_avail_minutes: fields[13].to_string(),
-            alternate: fields[14].to_string(),
-            remarks: fields[15].to_string(),
-            route: fields[16].to_string(),
-            amended_by: amended_by,
-        };
+        Ok(Self {
+            callsign: get_field!(fields, 0).into(),
+            to: get_field!(fields, 1).into(),
+            rule,
+            equipment: get_field!(fields, 3).into(),
+            tas: get_field!(fields, 4).into(),
+            origin: get_field!(fields, 5).into(),
+            dep_time: get_field!(fields, 6).into(),
+            actual_dep_time: get_field!(fields, 7).into(),
+            cruise_alt: get_field!(fields, 8).into(),
+            dest: get_field!(fields, 9).into(),
+            hours_enroute: get_field!(fields, 10).into(),
+            minutes_enroute: get_field!(fields, 11).into(),
+            fuel_avail_hours: get_field!(fields, 12).into(),
+            fuel_avail_minutes: get_field!(fields, 13).into(),
+            alternate: get_field!(fields, 14).into(),
+            remarks: get_field!(fields, 15).into(),
+            route: get_field!(fields, 16).into(),
+            amended_by: amended.map(|callsign| callsign.into()),
+        })
     }
 }
 
@@ -431,59 +789,119 @@ pub enum TransferControlType {
 }
 
 #[derive(PartialEq, Debug, Clone)]
-pub struct TransferControl {
-    pub from: String,
-    pub to: String,
-    pub target: String,
+pub struct TransferControl<'a> {
+    pub from: Cow<'a, str>,
+    pub to: Cow<'a, str>,
+    pub target: Cow<'a, str>,
 
     pub transfer_type: TransferControlType,
 }
 
-impl Packet for TransferControl {
-    fn from_string(fields: &Vec<&str>) -> Self {
-        return Self::new(fields, TransferControlType::Received);
+impl<'a> Packet<'a> for TransferControl<'a> {
+    fn from_string(fields: &[&'a str]) -> Result<Self, ParseError> {
+        Self::new(fields, TransferControlType::Received)
+    }
+
+    fn to_fsd_string(&self) -> String {
+        let command = match self.transfer_type {
+            TransferControlType::Received => "$HO",
+            TransferControlType::Accepted => "$HA",
+            TransferControlType::Cancelled => "HC",
+            TransferControlType::IHaveControl => "IH",
+            TransferControlType::Pointout => "PT",
+            TransferControlType::PushToDepartures => "DP",
+        };
+
+        match self.transfer_type {
+            TransferControlType::Accepted | TransferControlType::Received => {
+                format!("{}{}:{}:{}", command, self.from, self.to, self.target)
+            }
+            _ => format!(
+                "#PC{}:{}:CCP:{}:{}",
+                self.from, self.to, command, self.target
+            ),
+        }
     }
 }
 
-impl TransferControl {
-    pub fn new(fields: &Vec<&str>, transfer_type: TransferControlType) -> Self {
+impl<'a> TransferControl<'a> {
+    pub fn into_owned(self) -> TransferControl<'static> {
+        TransferControl {
+            from: owned(self.from),
+            to: owned(self.to),
+            target: owned(self.target),
+            transfer_type: self.transfer_type,
+        }
+    }
+
+    pub fn new(fields: &[&'a str], transfer_type: TransferControlType) -> Result<Self, ParseError> {
         let target = match transfer_type {
-            TransferControlType::Accepted | TransferControlType::Received => fields[2],
-            _ => fields[4],
+            TransferControlType::Accepted | TransferControlType::Received => {
+                get_field!(fields, 2)
+            }
+            _ => get_field!(fields, 4),
         };
 
-        return Self {
-            from: fields[0].to_string(),
-            to: fields[1].to_string(),
-            target: target.to_string(),
+        Ok(Self {
+            from: get_field!(fields, 0).into(),
+            to: get_field!(fields, 1).into(),
+            target: target.into(),
 
-            transfer_type: transfer_type,
-        };
+            transfer_type,
+        })
     }
 }
 
 #[derive(PartialEq, Debug, Clone)]
-pub struct ATCPosition {
+pub struct ATCPosition<'a> {
     pub freq: Frequency,
     pub facility: NetworkFacility,
     pub vis_range: u16,
     pub rating: NetworkRating,
     pub lat: f32,
     pub lon: f32,
-    pub callsign: String,
-}
-
-impl Packet for ATCPosition {
-    fn from_string(fields: &Vec<&str>) -> Self {
-        return ATCPosition {
-            callsign: fields[0].to_string(),
-            freq: Frequency::from_packet_string(&fields[1]),
-            facility: NetworkFacility::from_string(fields[2]),
-            vis_range: force_parse!(u16, fields[3]),
-            rating: NetworkRating::from_string(fields[4]),
-            lat: fields[5].parse::<f32>().unwrap(),
-            lon: fields[6].parse::<f32>().unwrap(),
-        };
+    pub callsign: Cow<'a, str>,
+}
+
+impl<'a> ATCPosition<'a> {
+    pub fn into_owned(self) -> ATCPosition<'static> {
+        ATCPosition {
+            freq: self.freq,
+            facility: self.facility,
+            vis_range: self.vis_range,
+            rating: self.rating,
+            lat: self.lat,
+            lon: self.lon,
+            callsign: owned(self.callsign),
+        }
+    }
+}
+
+impl<'a> Packet<'a> for ATCPosition<'a> {
+    fn from_string(fields: &[&'a str]) -> Result<Self, ParseError> {
+        Ok(ATCPosition {
+            callsign: get_field!(fields, 0).into(),
+            freq: Frequency::from_packet_string(get_field!(fields, 1))
+                .ok_or(ParseError::InvalidNumber(1))?,
+            facility: NetworkFacility::from_string(get_field!(fields, 2)),
+            vis_range: force_parse!(u16, fields, 3),
+            rating: NetworkRating::from_string(get_field!(fields, 4)),
+            lat: force_parse!(f32, fields, 5),
+            lon: force_parse!(f32, fields, 6),
+        })
+    }
+
+    fn to_fsd_string(&self) -> String {
+        format!(
+            "%{}:{}:{}:{}:{}:{}:{}:0",
+            self.callsign,
+            self.freq.to_packet_string(),
+            self.facility.to_packet_string(),
+            self.vis_range,
+            self.rating.to_packet_string(),
+            self.lat,
+            self.lon
+        )
     }
 }
 
@@ -492,6 +910,8 @@ pub struct FlightSurfaces {
     pub pitch: f64,
     pub bank: f64,
     pub hdg: f64,
+    /// The two lowest bits of the packed value, bit 1 is set while on the ground
+    pub flags: u8,
 }
 
 impl FlightSurfaces {
@@ -515,17 +935,26 @@ impl FlightSurfaces {
             hdg_dbl -= 360.0;
         }
 
-        return FlightSurfaces {
+        FlightSurfaces {
             hdg: hdg_dbl,
             bank: bank_dbl,
             pitch: pitch_dbl,
-        };
+            flags: (data & 0x3) as u8,
+        }
+    }
+
+    pub fn to_encoded(&self) -> i64 {
+        let pitch = (self.pitch / -360.0 * 1024.0).round() as i64 & 0x3FF;
+        let bank = (self.bank / -360.0 * 1024.0).round() as i64 & 0x3FF;
+        let hdg = (self.hdg / 360.0 * 1024.0).round() as i64 & 0x3FF;
+
+        (pitch << 22) | (bank << 12) | (hdg << 2) | (self.flags & 0x3) as i64
     }
 }
 
 #[derive(PartialEq, Debug, Clone)]
-pub struct PilotPosition {
-    pub callsign: String,
+pub struct PilotPosition<'a> {
+    pub callsign: Cow<'a, str>,
     pub squawk_code: u16,
     pub squawking: SquawkType,
     pub rating: NetworkRating,
@@ -537,319 +966,1755 @@ pub struct PilotPosition {
     pub pbh: FlightSurfaces,
 }
 
-impl Packet for PilotPosition {
-    fn from_string(fields: &Vec<&str>) -> Self {
-        let squawk_type = match fields[0] {
+impl<'a> PilotPosition<'a> {
+    pub fn into_owned(self) -> PilotPosition<'static> {
+        PilotPosition {
+            callsign: owned(self.callsign),
+            squawk_code: self.squawk_code,
+            squawking: self.squawking,
+            rating: self.rating,
+            lat: self.lat,
+            lon: self.lon,
+            true_alt: self.true_alt,
+            pressure_alt: self.pressure_alt,
+            ground_speed: self.ground_speed,
+            pbh: self.pbh,
+        }
+    }
+}
+
+impl<'a> Packet<'a> for PilotPosition<'a> {
+    fn from_string(fields: &[&'a str]) -> Result<Self, ParseError> {
+        let squawk_type = match get_field!(fields, 0) {
             "S" => SquawkType::Standby,
             "N" => SquawkType::Charlie,
             "Y" => SquawkType::Ident,
             _ => SquawkType::Undefined,
         };
 
-        let alt = force_parse!(i32, fields[6]);
+        let alt = force_parse!(i32, fields, 6);
 
-        return Self {
-            callsign: fields[1].to_string(),
-            squawk_code: force_parse!(u16, fields[2]),
+        Ok(Self {
+            callsign: get_field!(fields, 1).into(),
+            squawk_code: force_parse!(u16, fields, 2),
             squawking: squawk_type,
-            rating: NetworkRating::from_string(fields[3]),
-            lat: force_parse!(f32, fields[4]),
-            lon: force_parse!(f32, fields[5]),
+            rating: NetworkRating::from_string(get_field!(fields, 3)),
+            lat: force_parse!(f32, fields, 4),
+            lon: force_parse!(f32, fields, 5),
             true_alt: alt,
-            pressure_alt: alt + force_parse!(i32, fields[9]),
-            ground_speed: force_parse!(i32, fields[7]),
-            pbh: FlightSurfaces::from_encoded(force_parse!(i64, fields[8])),
+            pressure_alt: alt + force_parse!(i32, fields, 9),
+            ground_speed: force_parse!(i32, fields, 7),
+            pbh: FlightSurfaces::from_encoded(force_parse!(i64, fields, 8)),
+        })
+    }
+
+    fn to_fsd_string(&self) -> String {
+        let squawk_type = match self.squawking {
+            SquawkType::Standby => "S",
+            SquawkType::Charlie => "N",
+            SquawkType::Ident => "Y",
+            SquawkType::Undefined => "",
         };
+
+        format!(
+            "@{}:{}:{:04}:{}:{}:{}:{}:{}:{}:{}",
+            squawk_type,
+            self.callsign,
+            self.squawk_code,
+            self.rating.to_packet_string(),
+            self.lat,
+            self.lon,
+            self.true_alt,
+            self.ground_speed,
+            self.pbh.to_encoded(),
+            self.pressure_alt - self.true_alt
+        )
     }
 }
 
-#[derive(Debug, PartialEq, Clone)]
-pub enum ClientQueryPayload {
-    AcceptHandoff(String, String), // Aircraft Callsign, From ATC
-    AircraftConfiguration(Value),
-    DropTrack(String),                        // Callsign target
-    FlightPlan(String),                       // Callsign of target
-    InitiateTrack(String),                    // Callsign
-    IsValidATCQuery(Option<String>),          // Callsign target
-    IsValidATCResponse(bool, Option<String>), // IsValid, Callsign target
-    NewATIS(String),                          // ATIS
-    NewInfo(String),                          // Controller info
-    RealName(RealNamePayload),
-    SetFinalAltitude(String, String), // Callsign, final altitude
-    SetBeaconCode(String, String),    // Callsign, Data
-    SetScratchpad(String, String),    // Callsign, Data
-    SetTempAltitude(String, String),  // Callsign, Altitude
-    SetVoiceType(String, String),     // Callsign target, data
-    Unknown(Vec<String>),
-    WhoHas(String),
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum FastPositionType {
+    /// `^`, sent several times a second while moving
+    Fast,
+    /// `#SL`, sent instead of fast updates to clients further away
+    Slow,
+    /// `#ST`, sent once the aircraft stops and carries no velocities
+    Stopped,
 }
 
-#[derive(Debug, PartialEq, Clone)]
-pub struct RealNamePayload {
-    pub real_name: String,
-    pub facility_name: String,
-    pub rating: NetworkRating,
+/// Speed along each axis in metres per second
+#[derive(PartialEq, Debug, Clone, Default)]
+pub struct PositionalVelocity {
+    /// Towards the east
+    pub lon: f64,
+    /// Upwards
+    pub alt: f64,
+    /// Towards the north
+    pub lat: f64,
 }
 
-impl RealNamePayload {
-    fn from_payload(payload: &Vec<String>) -> Self {
-        Self {
-            real_name: payload[0].to_string(),
-            facility_name: payload[1].to_string(),
-            rating: to_enum!(payload[2]).unwrap(),
-        }
-    }
+/// Rate of rotation around each axis in radians per second
+#[derive(PartialEq, Debug, Clone, Default)]
+pub struct RotationalVelocity {
+    pub pitch: f64,
+    pub heading: f64,
+    pub bank: f64,
 }
 
-#[derive(Debug, PartialEq, Clone)]
-pub struct ClientQuery {
-    pub is_response: bool,
-    pub from: String,
-    pub to: String,
-    pub query_type: ClientQueryType,
-    pub payload: ClientQueryPayload,
+/// High rate position updates from the velocity protocol, used by clients to move aircraft
+/// smoothly between regular `PilotPosition` updates. Positions are kept as `f64` since they
+/// are sent with more precision than regular ones.
+#[derive(PartialEq, Debug, Clone)]
+pub struct FastPilotPosition<'a> {
+    pub position_type: FastPositionType,
+    pub callsign: Cow<'a, str>,
+    pub lat: f64,
+    pub lon: f64,
+    pub true_alt: f64,
+    pub agl_alt: f64,
+    pub pbh: FlightSurfaces,
+    /// Zero for stopped updates
+    pub positional_velocity: PositionalVelocity,
+    /// Zero for stopped updates
+    pub rotational_velocity: RotationalVelocity,
+    /// Older clients leave it out
+    pub nose_gear_angle: Option<f64>,
 }
 
-impl Packet for ClientQuery {
-    fn from_string(fields: &Vec<&str>) -> Self {
-        return Self::new(fields, ClientQueryType::Unknown, false);
+impl<'a> FastPilotPosition<'a> {
+    pub fn into_owned(self) -> FastPilotPosition<'static> {
+        FastPilotPosition {
+            position_type: self.position_type,
+            callsign: owned(self.callsign),
+            lat: self.lat,
+            lon: self.lon,
+            true_alt: self.true_alt,
+            agl_alt: self.agl_alt,
+            pbh: self.pbh,
+            positional_velocity: self.positional_velocity,
+            rotational_velocity: self.rotational_velocity,
+            nose_gear_angle: self.nose_gear_angle,
+        }
     }
-}
 
-impl ClientQuery {
-    pub fn new(fields: &Vec<&str>, query_type: ClientQueryType, is_response: bool) -> Self {
-        let mut payload: Vec<String> = vec![];
-        if fields.len() > 3 {
-            for i in 3..fields.len() {
-                payload.push(fields[i].to_string());
-            }
-        }
-        // Determine payload
-        let payload = if payload.len() >= 2 {
-            match query_type {
-                ClientQueryType::AircraftConfiguration => {
-                    ClientQueryPayload::AircraftConfiguration(
-                        serde_json::from_str(payload.join(":").as_str()).unwrap(),
-                    )
-                }
-                ClientQueryType::SetBeaconCode => {
-                    ClientQueryPayload::SetBeaconCode(payload.remove(0), payload.remove(0))
-                }
-                ClientQueryType::SetFinalAltitude => {
-                    ClientQueryPayload::SetFinalAltitude(payload.remove(0), payload.remove(0))
-                }
-                ClientQueryType::SetScratchpad => {
-                    ClientQueryPayload::SetScratchpad(payload.remove(0), payload.remove(0))
-                }
-                ClientQueryType::SetTempAltitude => {
-                    ClientQueryPayload::SetTempAltitude(payload.remove(0), payload.remove(0))
-                }
-                ClientQueryType::SetVoiceType => {
-                    ClientQueryPayload::SetVoiceType(payload.remove(0), payload.remove(0))
-                }
-                ClientQueryType::AcceptHandoff => {
-                    ClientQueryPayload::AcceptHandoff(payload.remove(0), payload.remove(0))
-                }
-                _ => ClientQueryPayload::Unknown(payload),
-            }
-        } else if payload.len() == 1 {
-            match query_type {
-                ClientQueryType::DropTrack => ClientQueryPayload::DropTrack(payload.remove(0)),
-                ClientQueryType::FlightPlan => ClientQueryPayload::FlightPlan(payload.remove(0)),
-                ClientQueryType::InitiateTrack => {
-                    ClientQueryPayload::InitiateTrack(payload.remove(0))
-                }
-                ClientQueryType::IsValidATC => match is_response {
-                    false => {
-                        if payload.len() > 0 {
-                            ClientQueryPayload::IsValidATCQuery(Some(payload.remove(0)))
-                        } else {
-                            ClientQueryPayload::IsValidATCQuery(None)
-                        }
-                    }
-                    true => ClientQueryPayload::IsValidATCResponse(
-                        if payload[0] == "Y" { true } else { false },
-                        if payload.len() > 1 {
-                            Some(payload.remove(0))
-                        } else {
-                            None
-                        },
-                    ),
+    pub fn new(fields: &[&'a str], position_type: FastPositionType) -> Result<Self, ParseError> {
+        let (positional_velocity, rotational_velocity, nose_gear) = match position_type {
+            FastPositionType::Stopped => (
+                PositionalVelocity::default(),
+                RotationalVelocity::default(),
+                6,
+            ),
+            _ => (
+                PositionalVelocity {
+                    lon: force_parse!(f64, fields, 6),
+                    alt: force_parse!(f64, fields, 7),
+                    lat: force_parse!(f64, fields, 8),
                 },
-                ClientQueryType::NewATIS => ClientQueryPayload::NewATIS(payload.remove(0)),
-                ClientQueryType::NewInfo => ClientQueryPayload::NewInfo(payload.remove(0)),
-                ClientQueryType::RealName => match is_response {
-                    false => ClientQueryPayload::Unknown(payload),
-                    true => ClientQueryPayload::RealName(RealNamePayload::from_payload(&payload)),
+                RotationalVelocity {
+                    pitch: force_parse!(f64, fields, 9),
+                    heading: force_parse!(f64, fields, 10),
+                    bank: force_parse!(f64, fields, 11),
                 },
-                ClientQueryType::WhoHas => ClientQueryPayload::WhoHas(payload.remove(0)),
-                _ => ClientQueryPayload::Unknown(payload),
-            }
-        } else {
-            ClientQueryPayload::Unknown(payload)
+                12,
+            ),
+        };
+        let nose_gear_angle = match fields.get(nose_gear) {
+            Some(_) => Some(force_parse!(f64, fields, nose_gear)),
+            None => None,
         };
 
-        return Self {
-            is_response: is_response,
-            from: fields[0].to_string(),
-            to: fields[1].to_string(),
-            query_type: query_type,
-            payload: payload,
+        Ok(Self {
+            position_type,
+            callsign: get_field!(fields, 0).into(),
+            lat: force_parse!(f64, fields, 1),
+            lon: force_parse!(f64, fields, 2),
+            true_alt: force_parse!(f64, fields, 3),
+            agl_alt: force_parse!(f64, fields, 4),
+            pbh: FlightSurfaces::from_encoded(force_parse!(i64, fields, 5)),
+            positional_velocity,
+            rotational_velocity,
+            nose_gear_angle,
+        })
+    }
+}
+
+impl<'a> Packet<'a> for FastPilotPosition<'a> {
+    fn from_string(fields: &[&'a str]) -> Result<Self, ParseError> {
+        Self::new(fields, FastPositionType::Fast)
+    }
+
+    fn to_fsd_string(&self) -> String {
+        let command = match self.position_type {
+            FastPositionType::Fast => "^",
+            FastPositionType::Slow => "#SL",
+            FastPositionType::Stopped => "#ST",
         };
+        let mut line = format!(
+            "{}{}:{}:{}:{}:{}:{}",
+            command,
+            self.callsign,
+            self.lat,
+            self.lon,
+            self.true_alt,
+            self.agl_alt,
+            self.pbh.to_encoded()
+        );
+        if self.position_type != FastPositionType::Stopped {
+            let (position, rotation) = (&self.positional_velocity, &self.rotational_velocity);
+            line += &format!(
+                ":{}:{}:{}:{}:{}:{}",
+                position.lon,
+                position.alt,
+                position.lat,
+                rotation.pitch,
+                rotation.heading,
+                rotation.bank
+            );
+        }
+        if let Some(angle) = self.nose_gear_angle {
+            line += &format!(":{}", angle);
+        }
+        line
     }
 }
 
-#[derive(Debug, PartialEq, Clone)]
-pub struct PlaneInfoRequest {
-    pub from: String,
-    pub to: String,
+/// Asks a pilot client to start or stop sending fast position updates
+#[derive(PartialEq, Debug, Clone)]
+pub struct SendFast<'a> {
+    pub from: Cow<'a, str>,
+    pub to: Cow<'a, str>,
+    pub enabled: bool,
 }
 
-impl PlaneInfoRequest {
-    pub fn new(fields: &Vec<&str>) -> Self {
-        Self {
-            from: fields[0].to_string(),
-            to: fields[1].to_string(),
+impl<'a> SendFast<'a> {
+    pub fn into_owned(self) -> SendFast<'static> {
+        SendFast {
+            from: owned(self.from),
+            to: owned(self.to),
+            enabled: self.enabled,
         }
     }
 }
 
-impl Packet for PlaneInfoRequest {
-    fn from_string(fields: &Vec<&str>) -> Self {
-        Self::new(fields)
+impl<'a> Packet<'a> for SendFast<'a> {
+    fn from_string(fields: &[&'a str]) -> Result<Self, ParseError> {
+        Ok(Self {
+            from: get_field!(fields, 0).into(),
+            to: get_field!(fields, 1).into(),
+            enabled: force_parse!(u8, fields, 2) != 0,
+        })
+    }
+
+    fn to_fsd_string(&self) -> String {
+        format!("$SF{}:{}:{}", self.from, self.to, self.enabled as u8)
     }
 }
 
+/// What follows the query type in a `$CQ` or `$CR`. Queries and responses of the same type
+/// that carry different data have a variant each.
 #[derive(Debug, PartialEq, Clone)]
-pub enum PlaneInfoResponse {
-    Legacy {
-        csl: String,
-        engine_type: EngineType,
-    },
-    Regular {
-        equipment: Option<String>,
-        airline: Option<String>,
-        livery: Option<String>,
-        csl: Option<String>,
-    },
+pub enum ClientQueryPayload<'a> {
+    AcceptHandoff(Cow<'a, str>, Cow<'a, str>), // Aircraft Callsign, From ATC
+    AircraftConfiguration(Value),
+    ATISQuery,
+    ATISResponse(ATISLine<'a>),
+    CancelRequestHelp(Option<Cow<'a, str>>), // Message
+    CancelRequestRelief,
+    CapabilitiesQuery,
+    CapabilitiesResponse(ClientCapabilities),
+    COM1FreqQuery,
+    COM1FreqResponse(Cow<'a, str>), // Frequency, e.g. 122.800
+    DropTrack(Cow<'a, str>),        // Callsign target
+    FlightPlan(Cow<'a, str>),       // Callsign of target
+    INFQuery,
+    INFResponse(Cow<'a, str>),                      // Client information
+    InitiateTrack(Cow<'a, str>),                    // Callsign
+    IPC(Cow<'a, str>, Cow<'a, str>, Cow<'a, str>),  // Command, register, value
+    IsValidATCQuery(Option<Cow<'a, str>>),          // Callsign target
+    IsValidATCResponse(bool, Option<Cow<'a, str>>), // IsValid, Callsign target
+    NewATIS(Cow<'a, str>),                          // ATIS
+    NewInfo(Cow<'a, str>),                          // Controller info
+    PublicIPQuery,
+    PublicIPResponse(Cow<'a, str>), // IP address
+    RealNameQuery,
+    RealName(RealNamePayload<'a>),
+    RequestHelp(Option<Cow<'a, str>>), // Message
+    RequestRelief,
+    ServerQuery,
+    ServerResponse(Cow<'a, str>), // Server the client is connected to
+    SetFinalAltitude(Cow<'a, str>, Cow<'a, str>), // Callsign, final altitude
+    SetBeaconCode(Cow<'a, str>, Cow<'a, str>), // Callsign, Data
+    SetScratchpad(Cow<'a, str>, Cow<'a, str>), // Callsign, Data
+    SetTempAltitude(Cow<'a, str>, Cow<'a, str>), // Callsign, Altitude
+    SetVoiceType(Cow<'a, str>, Cow<'a, str>), // Callsign target, data
+    Unknown(Vec<Cow<'a, str>>),
+    WhoHas(Cow<'a, str>),
 }
 
-fn find_value(fields: &Vec<&str>, key: &str) -> Option<String> {
-    for &field in fields {
-        if field.to_uppercase().starts_with(key) {
-            return Some(field[key.len() + 1..].to_string());
+/// One line of a controller's answer to an `ATIS` query
+#[derive(Debug, PartialEq, Clone)]
+pub enum ATISLine<'a> {
+    /// `V`, where to listen to the voice ATIS
+    VoiceServer(Cow<'a, str>),
+    /// `T`, a line of the text ATIS
+    Text(Cow<'a, str>),
+    /// `Z`, when the controller expects to log off, e.g. `2200z`
+    LogoffTime(Cow<'a, str>),
+    /// `E`, the end of the ATIS with the number of lines sent
+    End(u32),
+}
+
+impl<'a> ATISLine<'a> {
+    pub fn into_owned(self) -> ATISLine<'static> {
+        match self {
+            ATISLine::VoiceServer(a) => ATISLine::VoiceServer(owned(a)),
+            ATISLine::Text(a) => ATISLine::Text(owned(a)),
+            ATISLine::LogoffTime(a) => ATISLine::LogoffTime(owned(a)),
+            ATISLine::End(lines) => ATISLine::End(lines),
         }
     }
-    None
+
+    fn from_fields(fields: &[&'a str]) -> Result<Self, ParseError> {
+        // Lines of text can contain the delimiter
+        let text = join_fields(fields.get(4..).unwrap_or_default());
+        match get_field!(fields, 3) {
+            "V" => Ok(ATISLine::VoiceServer(text)),
+            "T" => Ok(ATISLine::Text(text)),
+            "Z" => Ok(ATISLine::LogoffTime(text)),
+            "E" => Ok(ATISLine::End(force_parse!(u32, fields, 4))),
+            _ => Err(ParseError::InvalidEnumValue(3)),
+        }
+    }
+
+    fn to_payload(&self) -> Vec<String> {
+        let (kind, value) = match self {
+            ATISLine::VoiceServer(a) => ("V", a.to_string()),
+            ATISLine::Text(a) => ("T", a.to_string()),
+            ATISLine::LogoffTime(a) => ("Z", a.to_string()),
+            ATISLine::End(lines) => ("E", lines.to_string()),
+        };
+        vec![kind.to_string(), value]
+    }
 }
 
-#[derive(Debug, PartialEq, Clone)]
-pub struct PlaneInfo {
-    pub from: String,
-    pub to: String,
-    pub payload: PlaneInfoResponse,
+/// A feature a client announces in its answer to a `CAPS` query
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+pub enum Capability {
+    Version,
+    /// Sends controller info lines in answer to `INF`
+    ATCInfo,
+    /// Sends its aircraft model in answer to `PIR`
+    ModelDesc,
+    /// Sends and understands `ACC` aircraft configurations
+    AircraftConfig,
+    /// Sends visual updates for controller clients
+    VisualUpdate,
+    /// Sends and understands `^` fast positions
+    FastPosition,
+    SecondaryPosition,
+    InterimPosition,
+    OngoingCoordination,
+    NewInfo,
+    TeamSpeak,
+    IcaoEquipment,
+    Stealth,
 }
 
-impl Packet for PlaneInfo {
-    fn from_string(fields: &Vec<&str>) -> Self {
-        Self::new(fields)
+impl Capability {
+    pub const ALL: [Capability; 13] = [
+        Capability::Version,
+        Capability::ATCInfo,
+        Capability::ModelDesc,
+        Capability::AircraftConfig,
+        Capability::VisualUpdate,
+        Capability::FastPosition,
+        Capability::SecondaryPosition,
+        Capability::InterimPosition,
+        Capability::OngoingCoordination,
+        Capability::NewInfo,
+        Capability::TeamSpeak,
+        Capability::IcaoEquipment,
+        Capability::Stealth,
+    ];
+
+    pub fn from_flag(flag: &str) -> Option<Self> {
+        Capability::ALL
+            .iter()
+            .find(|capability| capability.flag() == flag)
+            .copied()
+    }
+
+    pub fn flag(&self) -> &'static str {
+        match self {
+            Capability::Version => "VERSION",
+            Capability::ATCInfo => "ATCINFO",
+            Capability::ModelDesc => "MODELDESC",
+            Capability::AircraftConfig => "ACCONFIG",
+            Capability::VisualUpdate => "VISUPDATE",
+            Capability::FastPosition => "FASTPOS",
+            Capability::SecondaryPosition => "SECPOS",
+            Capability::InterimPosition => "INTERIMPOS",
+            Capability::OngoingCoordination => "ONGOINGCOORD",
+            Capability::NewInfo => "NEWINFO",
+            Capability::TeamSpeak => "TEAMSPEAK",
+            Capability::IcaoEquipment => "ICAOEQ",
+            Capability::Stealth => "STEALTH",
+        }
+    }
+
+    fn bit(&self) -> u32 {
+        1 << *self as u32
     }
 }
 
-impl PlaneInfo {
-    pub fn new(fields: &Vec<&str>) -> Self {
-        let is_legacy = fields[3] == "X";
+/// The flags of a `CAPS` response, like `VERSION=1:ATCINFO=1:ACCONFIG=1`.
+///
+/// Known flags are kept as a set, flags this crate does not know are kept as they were sent
+/// so they survive being passed on.
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct ClientCapabilities {
+    flags: u32,
+    unknown: Vec<String>,
+}
+
+impl ClientCapabilities {
+    pub fn new() -> Self {
+        Self::default()
+    }
 
-        let payload = match is_legacy {
-            true => PlaneInfoResponse::Legacy {
-                csl: fields[6].to_string(),
-                engine_type: to_enum!(fields[5]).unwrap(),
-            },
-            false => PlaneInfoResponse::Regular {
-                equipment: find_value(fields, "EQUIPMENT"),
-                airline: find_value(fields, "AIRLINE"),
-                livery: find_value(fields, "LIVERY"),
-                csl: find_value(fields, "CSL"),
-            },
-        };
+    pub fn contains(&self, capability: Capability) -> bool {
+        self.flags & capability.bit() != 0
+    }
 
-        Self {
-            from: fields[0].to_string(),
-            to: fields[1].to_string(),
-            payload,
+    pub fn insert(&mut self, capability: Capability) {
+        self.flags |= capability.bit();
+    }
+
+    pub fn remove(&mut self, capability: Capability) {
+        self.flags &= !capability.bit();
+    }
+
+    /// The known capabilities that are set
+    pub fn iter(&self) -> impl Iterator<Item = Capability> + '_ {
+        Capability::ALL
+            .iter()
+            .copied()
+            .filter(move |capability| self.contains(*capability))
+    }
+
+    /// Flags that were sent but are not a `Capability`, e.g. `NEWFLAG=1`
+    pub fn unknown(&self) -> &[String] {
+        &self.unknown
+    }
+
+    fn from_fields(fields: &[&str]) -> Self {
+        let mut capabilities = ClientCapabilities::new();
+        for field in fields {
+            let mut parts = field.splitn(2, '=');
+            let flag = parts.next().unwrap_or_default();
+            match (Capability::from_flag(flag), parts.next()) {
+                (Some(_), Some("0")) => (),
+                (Some(capability), Some(_)) => capabilities.insert(capability),
+                _ => capabilities.unknown.push(field.to_string()),
+            }
         }
+        capabilities
     }
-}
 
-#[derive(Debug, PartialEq, Clone)]
-pub struct ClientIdentification {
-    pub from: String,
-    pub client_id: String,
-    pub client_name: String,
-    pub major_version: u32,
-    pub minor_version: u32,
-    pub cid: String,
-    pub sys_id: String,
-    pub initial_challenge: String,
+    fn to_payload(&self) -> Vec<String> {
+        self.iter()
+            .map(|capability| format!("{}=1", capability.flag()))
+            .chain(self.unknown.iter().cloned())
+            .collect()
+    }
 }
 
-impl Packet for ClientIdentification {
-    fn from_string(fields: &Vec<&str>) -> Self {
-        Self {
-            from: fields[0].to_string(),
-            client_id: fields[2].to_string(),
-            client_name: fields[3].to_string(),
-            major_version: force_parse!(u32, fields[4]),
-            minor_version: force_parse!(u32, fields[5]),
-            cid: fields[6].to_string(),
-            sys_id: fields[7].to_string(),
-            initial_challenge: fields.get(8).map(|x| x.to_string()).unwrap_or_default(),
+impl std::iter::FromIterator<Capability> for ClientCapabilities {
+    fn from_iter<I: IntoIterator<Item = Capability>>(iter: I) -> Self {
+        let mut capabilities = ClientCapabilities::new();
+        for capability in iter {
+            capabilities.insert(capability);
         }
+        capabilities
     }
 }
 
 #[derive(Debug, PartialEq, Clone)]
-pub struct Metar {
-    pub is_response: bool,
-    pub from: String,
-    pub to: String,
-    pub payload: String,
+pub struct RealNamePayload<'a> {
+    pub real_name: Cow<'a, str>,
+    pub facility_name: Cow<'a, str>,
+    pub rating: NetworkRating,
 }
 
-impl Packet for Metar {
-    fn from_string(fields: &Vec<&str>) -> Self {
-        return Self::new(fields, false);
+impl<'a> RealNamePayload<'a> {
+    pub fn into_owned(self) -> RealNamePayload<'static> {
+        RealNamePayload {
+            real_name: owned(self.real_name),
+            facility_name: owned(self.facility_name),
+            rating: self.rating,
+        }
     }
-}
 
-impl Metar {
-    pub fn new(fields: &Vec<&str>, is_response: bool) -> Self {
-        Self {
-            is_response,
-            from: fields[0].to_string(),
-            to: fields[1].to_string(),
-            payload: fields[3].to_string(),
-        }
+    fn from_fields(fields: &[&'a str]) -> Result<Self, ParseError> {
+        Ok(Self {
+            real_name: get_field!(fields, 3).into(),
+            facility_name: get_field!(fields, 4).into(),
+            rating: NetworkRating::from_string(get_field!(fields, 5)),
+        })
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    #[test]
-    fn test_rating_convert() {
-        assert_eq!(NetworkRating::from_string(""), NetworkRating::Undefined);
-        assert_eq!(NetworkRating::from_string("30"), NetworkRating::Undefined);
-        assert_eq!(NetworkRating::from_string("1"), NetworkRating::OBS);
+    fn to_payload(&self) -> Vec<String> {
+        vec![
+            self.real_name.to_string(),
+            self.facility_name.to_string(),
+            self.rating.to_packet_string(),
+        ]
     }
+}
 
-    #[test]
-    fn test_facility_convert() {
-        assert_eq!(NetworkFacility::from_string(""), NetworkFacility::Undefined);
-        assert_eq!(
+fn owned_all(data: Vec<Cow<'_, str>>) -> Vec<Cow<'static, str>> {
+    data.into_iter().map(owned).collect()
+}
+
+impl<'a> ClientQueryPayload<'a> {
+    pub fn into_owned(self) -> ClientQueryPayload<'static> {
+        match self {
+            ClientQueryPayload::AcceptHandoff(a, b) => {
+                ClientQueryPayload::AcceptHandoff(owned(a), owned(b))
+            }
+            ClientQueryPayload::AircraftConfiguration(value) => {
+                ClientQueryPayload::AircraftConfiguration(value)
+            }
+            ClientQueryPayload::ATISQuery => ClientQueryPayload::ATISQuery,
+            ClientQueryPayload::ATISResponse(a) => ClientQueryPayload::ATISResponse(a.into_owned()),
+            ClientQueryPayload::CancelRequestHelp(a) => {
+                ClientQueryPayload::CancelRequestHelp(a.map(owned))
+            }
+            ClientQueryPayload::CancelRequestRelief => ClientQueryPayload::CancelRequestRelief,
+            ClientQueryPayload::CapabilitiesQuery => ClientQueryPayload::CapabilitiesQuery,
+            ClientQueryPayload::CapabilitiesResponse(capabilities) => {
+                ClientQueryPayload::CapabilitiesResponse(capabilities)
+            }
+            ClientQueryPayload::COM1FreqQuery => ClientQueryPayload::COM1FreqQuery,
+            ClientQueryPayload::COM1FreqResponse(a) => {
+                ClientQueryPayload::COM1FreqResponse(owned(a))
+            }
+            ClientQueryPayload::DropTrack(a) => ClientQueryPayload::DropTrack(owned(a)),
+            ClientQueryPayload::FlightPlan(a) => ClientQueryPayload::FlightPlan(owned(a)),
+            ClientQueryPayload::INFQuery => ClientQueryPayload::INFQuery,
+            ClientQueryPayload::INFResponse(a) => ClientQueryPayload::INFResponse(owned(a)),
+            ClientQueryPayload::InitiateTrack(a) => ClientQueryPayload::InitiateTrack(owned(a)),
+            ClientQueryPayload::IPC(a, b, c) => {
+                ClientQueryPayload::IPC(owned(a), owned(b), owned(c))
+            }
+            ClientQueryPayload::IsValidATCQuery(a) => {
+                ClientQueryPayload::IsValidATCQuery(a.map(owned))
+            }
+            ClientQueryPayload::IsValidATCResponse(is_valid, a) => {
+                ClientQueryPayload::IsValidATCResponse(is_valid, a.map(owned))
+            }
+            ClientQueryPayload::NewATIS(a) => ClientQueryPayload::NewATIS(owned(a)),
+            ClientQueryPayload::NewInfo(a) => ClientQueryPayload::NewInfo(owned(a)),
+            ClientQueryPayload::PublicIPQuery => ClientQueryPayload::PublicIPQuery,
+            ClientQueryPayload::PublicIPResponse(a) => {
+                ClientQueryPayload::PublicIPResponse(owned(a))
+            }
+            ClientQueryPayload::RealNameQuery => ClientQueryPayload::RealNameQuery,
+            ClientQueryPayload::RealName(a) => ClientQueryPayload::RealName(a.into_owned()),
+            ClientQueryPayload::RequestHelp(a) => ClientQueryPayload::RequestHelp(a.map(owned)),
+            ClientQueryPayload::RequestRelief => ClientQueryPayload::RequestRelief,
+            ClientQueryPayload::ServerQuery => ClientQueryPayload::ServerQuery,
+            ClientQueryPayload::ServerResponse(a) => ClientQueryPayload::ServerResponse(owned(a)),
+            ClientQueryPayload::SetFinalAltitude(a, b) => {
+                ClientQueryPayload::SetFinalAltitude(owned(a), owned(b))
+            }
+            ClientQueryPayload::SetBeaconCode(a, b) => {
+                ClientQueryPayload::SetBeaconCode(owned(a), owned(b))
+            }
+            ClientQueryPayload::SetScratchpad(a, b) => {
+                ClientQueryPayload::SetScratchpad(owned(a), owned(b))
+            }
+            ClientQueryPayload::SetTempAltitude(a, b) => {
+                ClientQueryPayload::SetTempAltitude(owned(a), owned(b))
+            }
+            ClientQueryPayload::SetVoiceType(a, b) => {
+                ClientQueryPayload::SetVoiceType(owned(a), owned(b))
+            }
+            ClientQueryPayload::Unknown(a) => ClientQueryPayload::Unknown(owned_all(a)),
+            ClientQueryPayload::WhoHas(a) => ClientQueryPayload::WhoHas(owned(a)),
+        }
+    }
+
+    fn to_payload(&self) -> Vec<String> {
+        match self {
+            ClientQueryPayload::AircraftConfiguration(value) => vec![value.to_string()],
+            ClientQueryPayload::ATISQuery
+            | ClientQueryPayload::CancelRequestRelief
+            | ClientQueryPayload::CapabilitiesQuery
+            | ClientQueryPayload::COM1FreqQuery
+            | ClientQueryPayload::INFQuery
+            | ClientQueryPayload::PublicIPQuery
+            | ClientQueryPayload::RealNameQuery
+            | ClientQueryPayload::RequestRelief
+            | ClientQueryPayload::ServerQuery => vec![],
+            ClientQueryPayload::ATISResponse(line) => line.to_payload(),
+            ClientQueryPayload::AcceptHandoff(a, b)
+            | ClientQueryPayload::SetFinalAltitude(a, b)
+            | ClientQueryPayload::SetBeaconCode(a, b)
+            | ClientQueryPayload::SetScratchpad(a, b)
+            | ClientQueryPayload::SetTempAltitude(a, b)
+            | ClientQueryPayload::SetVoiceType(a, b) => vec![a.to_string(), b.to_string()],
+            ClientQueryPayload::COM1FreqResponse(a)
+            | ClientQueryPayload::DropTrack(a)
+            | ClientQueryPayload::FlightPlan(a)
+            | ClientQueryPayload::INFResponse(a)
+            | ClientQueryPayload::InitiateTrack(a)
+            | ClientQueryPayload::NewATIS(a)
+            | ClientQueryPayload::NewInfo(a)
+            | ClientQueryPayload::PublicIPResponse(a)
+            | ClientQueryPayload::ServerResponse(a)
+            | ClientQueryPayload::WhoHas(a) => vec![a.to_string()],
+            ClientQueryPayload::IPC(a, b, c) => vec![a.to_string(), b.to_string(), c.to_string()],
+            ClientQueryPayload::CancelRequestHelp(message)
+            | ClientQueryPayload::RequestHelp(message)
+            | ClientQueryPayload::IsValidATCQuery(message) => {
+                message.iter().map(|x| x.to_string()).collect()
+            }
+            ClientQueryPayload::IsValidATCResponse(is_valid, callsign) => {
+                let mut payload = vec![if *is_valid { "Y" } else { "N" }.to_string()];
+                payload.extend(callsign.iter().map(|x| x.to_string()));
+                payload
+            }
+            ClientQueryPayload::RealName(real_name) => real_name.to_payload(),
+            ClientQueryPayload::CapabilitiesResponse(capabilities) => capabilities.to_payload(),
+            ClientQueryPayload::Unknown(payload) => payload.iter().map(|x| x.to_string()).collect(),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct ClientQuery<'a> {
+    pub is_response: bool,
+    pub from: Cow<'a, str>,
+    pub to: Cow<'a, str>,
+    pub query_type: ClientQueryType<'a>,
+    pub payload: ClientQueryPayload<'a>,
+}
+
+impl<'a> Packet<'a> for ClientQuery<'a> {
+    fn from_string(fields: &[&'a str]) -> Result<Self, ParseError> {
+        Self::new(
+            fields,
+            ClientQueryType::from_command(get_field!(fields, 2)),
+            false,
+        )
+    }
+
+    fn to_fsd_string(&self) -> String {
+        let mut data = format!(
+            "{}{}:{}:{}",
+            if self.is_response { "$CR" } else { "$CQ" },
+            self.from,
+            self.to,
+            self.query_type.to_command()
+        );
+        for field in self.payload.to_payload() {
+            data.push(':');
+            data.push_str(&field);
+        }
+        data
+    }
+}
+
+impl<'a> ClientQuery<'a> {
+    pub fn into_owned(self) -> ClientQuery<'static> {
+        ClientQuery {
+            is_response: self.is_response,
+            from: owned(self.from),
+            to: owned(self.to),
+            query_type: self.query_type.into_owned(),
+            payload: self.payload.into_owned(),
+        }
+    }
+
+    pub fn new(
+        fields: &[&'a str],
+        query_type: ClientQueryType<'a>,
+        is_response: bool,
+    ) -> Result<Self, ParseError> {
+        // The payload starts after the query type
+        let payload = fields.get(3..).unwrap_or_default();
+        let optional = || match payload {
+            [] => None,
+            _ => Some(join_fields(payload)),
+        };
+        let all = || payload.iter().map(|x| (*x).into()).collect();
+
+        let payload = match (&query_type, is_response) {
+            (ClientQueryType::AcceptHandoff, _) => ClientQueryPayload::AcceptHandoff(
+                get_field!(fields, 3).into(),
+                get_field!(fields, 4).into(),
+            ),
+            (ClientQueryType::AircraftConfiguration, _) => {
+                ClientQueryPayload::AircraftConfiguration(
+                    serde_json::from_str(payload.join(":").as_str())
+                        .map_err(|e| ParseError::InvalidJson(e.to_string()))?,
+                )
+            }
+            (ClientQueryType::ATIS, false) => ClientQueryPayload::ATISQuery,
+            (ClientQueryType::ATIS, true) => {
+                ClientQueryPayload::ATISResponse(ATISLine::from_fields(fields)?)
+            }
+            (ClientQueryType::CancelRequestHelp, _) => {
+                ClientQueryPayload::CancelRequestHelp(optional())
+            }
+            (ClientQueryType::CancelRequestRelief, _) => ClientQueryPayload::CancelRequestRelief,
+            (ClientQueryType::Capabilities, false) => ClientQueryPayload::CapabilitiesQuery,
+            (ClientQueryType::Capabilities, true) => {
+                ClientQueryPayload::CapabilitiesResponse(ClientCapabilities::from_fields(payload))
+            }
+            (ClientQueryType::COM1Freq, false) => ClientQueryPayload::COM1FreqQuery,
+            (ClientQueryType::COM1Freq, true) => {
+                ClientQueryPayload::COM1FreqResponse(get_field!(fields, 3).into())
+            }
+            (ClientQueryType::DropTrack, _) => {
+                ClientQueryPayload::DropTrack(get_field!(fields, 3).into())
+            }
+            (ClientQueryType::FlightPlan, _) => {
+                ClientQueryPayload::FlightPlan(get_field!(fields, 3).into())
+            }
+            (ClientQueryType::INF, false) => ClientQueryPayload::INFQuery,
+            (ClientQueryType::INF, true) => ClientQueryPayload::INFResponse(join_fields(payload)),
+            (ClientQueryType::InitiateTrack, _) => {
+                ClientQueryPayload::InitiateTrack(get_field!(fields, 3).into())
+            }
+            (ClientQueryType::IPC, _) => ClientQueryPayload::IPC(
+                get_field!(fields, 3).into(),
+                get_field!(fields, 4).into(),
+                get_field!(fields, 5).into(),
+            ),
+            (ClientQueryType::IsValidATC, false) => {
+                ClientQueryPayload::IsValidATCQuery(fields.get(3).map(|x| (*x).into()))
+            }
+            (ClientQueryType::IsValidATC, true) => ClientQueryPayload::IsValidATCResponse(
+                get_field!(fields, 3) == "Y",
+                fields.get(4).map(|x| (*x).into()),
+            ),
+            (ClientQueryType::NewATIS, _) => ClientQueryPayload::NewATIS(join_fields(payload)),
+            (ClientQueryType::NewInfo, _) => ClientQueryPayload::NewInfo(join_fields(payload)),
+            (ClientQueryType::PublicIP, false) => ClientQueryPayload::PublicIPQuery,
+            (ClientQueryType::PublicIP, true) => {
+                ClientQueryPayload::PublicIPResponse(get_field!(fields, 3).into())
+            }
+            (ClientQueryType::RealName, false) => ClientQueryPayload::RealNameQuery,
+            (ClientQueryType::RealName, true) => {
+                ClientQueryPayload::RealName(RealNamePayload::from_fields(fields)?)
+            }
+            (ClientQueryType::RequestHelp, _) => ClientQueryPayload::RequestHelp(optional()),
+            (ClientQueryType::RequestRelief, _) => ClientQueryPayload::RequestRelief,
+            (ClientQueryType::Server, false) => ClientQueryPayload::ServerQuery,
+            (ClientQueryType::Server, true) => {
+                ClientQueryPayload::ServerResponse(join_fields(payload))
+            }
+            (ClientQueryType::SetBeaconCode, _) => ClientQueryPayload::SetBeaconCode(
+                get_field!(fields, 3).into(),
+                get_field!(fields, 4).into(),
+            ),
+            (ClientQueryType::SetFinalAltitude, _) => ClientQueryPayload::SetFinalAltitude(
+                get_field!(fields, 3).into(),
+                get_field!(fields, 4).into(),
+            ),
+            (ClientQueryType::SetScratchpad, _) => ClientQueryPayload::SetScratchpad(
+                get_field!(fields, 3).into(),
+                get_field!(fields, 4).into(),
+            ),
+            (ClientQueryType::SetTempAltitude, _) => ClientQueryPayload::SetTempAltitude(
+                get_field!(fields, 3).into(),
+                get_field!(fields, 4).into(),
+            ),
+            (ClientQueryType::SetVoiceType, _) => ClientQueryPayload::SetVoiceType(
+                get_field!(fields, 3).into(),
+                get_field!(fields, 4).into(),
+            ),
+            (ClientQueryType::WhoHas, _) => {
+                ClientQueryPayload::WhoHas(get_field!(fields, 3).into())
+            }
+            (ClientQueryType::Unknown(_), _) => ClientQueryPayload::Unknown(all()),
+        };
+
+        Ok(Self {
+            is_response,
+            from: get_field!(fields, 0).into(),
+            to: get_field!(fields, 1).into(),
+            query_type,
+            payload,
+        })
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct PlaneInfoRequest<'a> {
+    pub from: Cow<'a, str>,
+    pub to: Cow<'a, str>,
+}
+
+impl<'a> PlaneInfoRequest<'a> {
+    pub fn into_owned(self) -> PlaneInfoRequest<'static> {
+        PlaneInfoRequest {
+            from: owned(self.from),
+            to: owned(self.to),
+        }
+    }
+
+    pub fn new(fields: &[&'a str]) -> Result<Self, ParseError> {
+        Ok(Self {
+            from: get_field!(fields, 0).into(),
+            to: get_field!(fields, 1).into(),
+        })
+    }
+}
+
+impl<'a> Packet<'a> for PlaneInfoRequest<'a> {
+    fn from_string(fields: &[&'a str]) -> Result<Self, ParseError> {
+        Self::new(fields)
+    }
+
+    fn to_fsd_string(&self) -> String {
+        format!("#SB{}:{}:PIR", self.from, self.to)
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum PlaneInfoResponse<'a> {
+    Legacy {
+        csl: Cow<'a, str>,
+        engine_type: EngineType,
+    },
+    Regular {
+        equipment: Option<Cow<'a, str>>,
+        airline: Option<Cow<'a, str>>,
+        livery: Option<Cow<'a, str>>,
+        csl: Option<Cow<'a, str>>,
+    },
+}
+
+impl<'a> PlaneInfoResponse<'a> {
+    pub fn into_owned(self) -> PlaneInfoResponse<'static> {
+        match self {
+            PlaneInfoResponse::Legacy { csl, engine_type } => PlaneInfoResponse::Legacy {
+                csl: owned(csl),
+                engine_type,
+            },
+            PlaneInfoResponse::Regular {
+                equipment,
+                airline,
+                livery,
+                csl,
+            } => PlaneInfoResponse::Regular {
+                equipment: equipment.map(owned),
+                airline: airline.map(owned),
+                livery: livery.map(owned),
+                csl: csl.map(owned),
+            },
+        }
+    }
+}
+
+fn find_value<'a>(fields: &[&'a str], key: &str) -> Option<Cow<'a, str>> {
+    for &field in fields {
+        if field.to_uppercase().starts_with(key) {
+            return field.get(key.len() + 1..).map(|x| x.into());
+        }
+    }
+    None
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct PlaneInfo<'a> {
+    pub from: Cow<'a, str>,
+    pub to: Cow<'a, str>,
+    pub payload: PlaneInfoResponse<'a>,
+}
+
+impl<'a> Packet<'a> for PlaneInfo<'a> {
+    fn from_string(fields: &[&'a str]) -> Result<Self, ParseError> {
+        Self::new(fields)
+    }
+
+    fn to_fsd_string(&self) -> String {
+        match &self.payload {
+            PlaneInfoResponse::Legacy { csl, engine_type } => format!(
+                "#SB{}:{}:PI:X:0:{}:{}",
+                self.from,
+                self.to,
+                engine_type.clone() as u8,
+                csl
+            ),
+            PlaneInfoResponse::Regular {
+                equipment,
+                airline,
+                livery,
+                csl,
+            } => {
+                let mut data = format!("#SB{}:{}:PI:GEN", self.from, self.to);
+                let values = [
+                    ("EQUIPMENT", equipment),
+                    ("AIRLINE", airline),
+                    ("LIVERY", livery),
+                    ("CSL", csl),
+                ];
+                for (key, value) in values.iter() {
+                    if let Some(value) = value {
+                        data.push_str(&format!(":{}={}", key, value));
+                    }
+                }
+                data
+            }
+        }
+    }
+}
+
+impl<'a> PlaneInfo<'a> {
+    pub fn into_owned(self) -> PlaneInfo<'static> {
+        PlaneInfo {
+            from: owned(self.from),
+            to: owned(self.to),
+            payload: self.payload.into_owned(),
+        }
+    }
+
+    pub fn new(fields: &[&'a str]) -> Result<Self, ParseError> {
+        let is_legacy = get_field!(fields, 3) == "X";
+
+        let payload = match is_legacy {
+            true => PlaneInfoResponse::Legacy {
+                csl: get_field!(fields, 6).into(),
+                engine_type: to_enum!(fields, 5),
+            },
+            false => PlaneInfoResponse::Regular {
+                equipment: find_value(fields, "EQUIPMENT"),
+                airline: find_value(fields, "AIRLINE"),
+                livery: find_value(fields, "LIVERY"),
+                csl: find_value(fields, "CSL"),
+            },
+        };
+
+        Ok(Self {
+            from: get_field!(fields, 0).into(),
+            to: get_field!(fields, 1).into(),
+            payload,
+        })
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct ClientIdentification<'a> {
+    pub from: Cow<'a, str>,
+    pub to: Cow<'a, str>,
+    pub client_id: Cow<'a, str>,
+    pub client_name: Cow<'a, str>,
+    pub major_version: u32,
+    pub minor_version: u32,
+    pub cid: Cow<'a, str>,
+    pub sys_id: Cow<'a, str>,
+    pub initial_challenge: Cow<'a, str>,
+}
+
+impl<'a> ClientIdentification<'a> {
+    pub fn into_owned(self) -> ClientIdentification<'static> {
+        ClientIdentification {
+            from: owned(self.from),
+            to: owned(self.to),
+            client_id: owned(self.client_id),
+            client_name: owned(self.client_name),
+            major_version: self.major_version,
+            minor_version: self.minor_version,
+            cid: owned(self.cid),
+            sys_id: owned(self.sys_id),
+            initial_challenge: owned(self.initial_challenge),
+        }
+    }
+}
+
+impl<'a> Packet<'a> for ClientIdentification<'a> {
+    fn from_string(fields: &[&'a str]) -> Result<Self, ParseError> {
+        Ok(Self {
+            from: get_field!(fields, 0).into(),
+            to: get_field!(fields, 1).into(),
+            client_id: get_field!(fields, 2).into(),
+            client_name: get_field!(fields, 3).into(),
+            major_version: force_parse!(u32, fields, 4),
+            minor_version: force_parse!(u32, fields, 5),
+            cid: get_field!(fields, 6).into(),
+            sys_id: get_field!(fields, 7).into(),
+            initial_challenge: fields.get(8).map(|x| (*x).into()).unwrap_or_default(),
+        })
+    }
+
+    fn to_fsd_string(&self) -> String {
+        format!(
+            "$ID{}:{}:{}:{}:{}:{}:{}:{}:{}",
+            self.from,
+            self.to,
+            self.client_id,
+            self.client_name,
+            self.major_version,
+            self.minor_version,
+            self.cid,
+            self.sys_id,
+            self.initial_challenge
+        )
+    }
+}
+
+/// The greeting a server sends as soon as a client connects, before the client logs in
+#[derive(Debug, PartialEq, Clone)]
+pub struct ServerIdentification<'a> {
+    pub from: Cow<'a, str>,
+    pub to: Cow<'a, str>,
+    /// Name and version of the server software
+    pub version: Cow<'a, str>,
+    pub initial_challenge: Cow<'a, str>,
+}
+
+impl<'a> ServerIdentification<'a> {
+    pub fn into_owned(self) -> ServerIdentification<'static> {
+        ServerIdentification {
+            from: owned(self.from),
+            to: owned(self.to),
+            version: owned(self.version),
+            initial_challenge: owned(self.initial_challenge),
+        }
+    }
+}
+
+impl<'a> Packet<'a> for ServerIdentification<'a> {
+    fn from_string(fields: &[&'a str]) -> Result<Self, ParseError> {
+        Ok(Self {
+            from: get_field!(fields, 0).into(),
+            to: get_field!(fields, 1).into(),
+            version: get_field!(fields, 2).into(),
+            initial_challenge: get_field!(fields, 3).into(),
+        })
+    }
+
+    fn to_fsd_string(&self) -> String {
+        format!(
+            "$DI{}:{}:{}:{}",
+            self.from, self.to, self.version, self.initial_challenge
+        )
+    }
+}
+
+/// `$ZC`, either side asking the other to prove it is running authorized software
+#[derive(Debug, PartialEq, Clone)]
+pub struct AuthChallenge<'a> {
+    pub from: Cow<'a, str>,
+    pub to: Cow<'a, str>,
+    pub challenge: Cow<'a, str>,
+}
+
+impl<'a> AuthChallenge<'a> {
+    pub fn into_owned(self) -> AuthChallenge<'static> {
+        AuthChallenge {
+            from: owned(self.from),
+            to: owned(self.to),
+            challenge: owned(self.challenge),
+        }
+    }
+}
+
+impl<'a> Packet<'a> for AuthChallenge<'a> {
+    fn from_string(fields: &[&'a str]) -> Result<Self, ParseError> {
+        Ok(Self {
+            from: get_field!(fields, 0).into(),
+            to: get_field!(fields, 1).into(),
+            challenge: get_field!(fields, 2).into(),
+        })
+    }
+
+    fn to_fsd_string(&self) -> String {
+        format!("$ZC{}:{}:{}", self.from, self.to, self.challenge)
+    }
+}
+
+/// `$ZR`, the answer to an `AuthChallenge`
+#[derive(Debug, PartialEq, Clone)]
+pub struct AuthResponse<'a> {
+    pub from: Cow<'a, str>,
+    pub to: Cow<'a, str>,
+    pub response: Cow<'a, str>,
+}
+
+impl<'a> AuthResponse<'a> {
+    pub fn into_owned(self) -> AuthResponse<'static> {
+        AuthResponse {
+            from: owned(self.from),
+            to: owned(self.to),
+            response: owned(self.response),
+        }
+    }
+}
+
+impl<'a> Packet<'a> for AuthResponse<'a> {
+    fn from_string(fields: &[&'a str]) -> Result<Self, ParseError> {
+        Ok(Self {
+            from: get_field!(fields, 0).into(),
+            to: get_field!(fields, 1).into(),
+            response: get_field!(fields, 2).into(),
+        })
+    }
+
+    fn to_fsd_string(&self) -> String {
+        format!("$ZR{}:{}:{}", self.from, self.to, self.response)
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Metar<'a> {
+    pub is_response: bool,
+    pub from: Cow<'a, str>,
+    pub to: Cow<'a, str>,
+    pub payload: Cow<'a, str>,
+}
+
+impl<'a> Packet<'a> for Metar<'a> {
+    fn from_string(fields: &[&'a str]) -> Result<Self, ParseError> {
+        Self::new(fields, false)
+    }
+
+    fn to_fsd_string(&self) -> String {
+        format!(
+            "{}{}:{}:METAR:{}",
+            if self.is_response { "$AR" } else { "$AX" },
+            self.from,
+            self.to,
+            self.payload
+        )
+    }
+}
+
+impl<'a> Metar<'a> {
+    pub fn into_owned(self) -> Metar<'static> {
+        Metar {
+            is_response: self.is_response,
+            from: owned(self.from),
+            to: owned(self.to),
+            payload: owned(self.payload),
+        }
+    }
+
+    pub fn new(fields: &[&'a str], is_response: bool) -> Result<Self, ParseError> {
+        Ok(Self {
+            is_response,
+            from: get_field!(fields, 0).into(),
+            to: get_field!(fields, 1).into(),
+            payload: get_field!(fields, 3).into(),
+        })
+    }
+}
+
+/// Reasons a server gives in `$ER`, most of them end the connection
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum ServerErrorCode {
+    Ok,
+    CallsignInUse,
+    InvalidCallsign,
+    AlreadyRegistered,
+    SyntaxError,
+    InvalidSourceCallsign,
+    InvalidCidPassword,
+    NoSuchCallsign,
+    NoFlightPlan,
+    NoWeatherProfile,
+    InvalidProtocolRevision,
+    RequestedLevelTooHigh,
+    ServerFull,
+    CidSuspended,
+    InvalidControl,
+    InvalidPositionForRating,
+    UnauthorizedSoftware,
+    AuthenticationTimeout,
+    Unknown(u16),
+}
+
+impl ServerErrorCode {
+    pub fn from_code(code: u16) -> Self {
+        match code {
+            0 => ServerErrorCode::Ok,
+            1 => ServerErrorCode::CallsignInUse,
+            2 => ServerErrorCode::InvalidCallsign,
+            3 => ServerErrorCode::AlreadyRegistered,
+            4 => ServerErrorCode::SyntaxError,
+            5 => ServerErrorCode::InvalidSourceCallsign,
+            6 => ServerErrorCode::InvalidCidPassword,
+            7 => ServerErrorCode::NoSuchCallsign,
+            8 => ServerErrorCode::NoFlightPlan,
+            9 => ServerErrorCode::NoWeatherProfile,
+            10 => ServerErrorCode::InvalidProtocolRevision,
+            11 => ServerErrorCode::RequestedLevelTooHigh,
+            12 => ServerErrorCode::ServerFull,
+            13 => ServerErrorCode::CidSuspended,
+            14 => ServerErrorCode::InvalidControl,
+            15 => ServerErrorCode::InvalidPositionForRating,
+            16 => ServerErrorCode::UnauthorizedSoftware,
+            17 => ServerErrorCode::AuthenticationTimeout,
+            code => ServerErrorCode::Unknown(code),
+        }
+    }
+
+    pub fn code(&self) -> u16 {
+        match self {
+            ServerErrorCode::Ok => 0,
+            ServerErrorCode::CallsignInUse => 1,
+            ServerErrorCode::InvalidCallsign => 2,
+            ServerErrorCode::AlreadyRegistered => 3,
+            ServerErrorCode::SyntaxError => 4,
+            ServerErrorCode::InvalidSourceCallsign => 5,
+            ServerErrorCode::InvalidCidPassword => 6,
+            ServerErrorCode::NoSuchCallsign => 7,
+            ServerErrorCode::NoFlightPlan => 8,
+            ServerErrorCode::NoWeatherProfile => 9,
+            ServerErrorCode::InvalidProtocolRevision => 10,
+            ServerErrorCode::RequestedLevelTooHigh => 11,
+            ServerErrorCode::ServerFull => 12,
+            ServerErrorCode::CidSuspended => 13,
+            ServerErrorCode::InvalidControl => 14,
+            ServerErrorCode::InvalidPositionForRating => 15,
+            ServerErrorCode::UnauthorizedSoftware => 16,
+            ServerErrorCode::AuthenticationTimeout => 17,
+            ServerErrorCode::Unknown(code) => *code,
+        }
+    }
+
+    /// Whether the server drops the client after sending it
+    pub fn is_fatal(&self) -> bool {
+        !matches!(
+            self,
+            ServerErrorCode::Ok
+                | ServerErrorCode::SyntaxError
+                | ServerErrorCode::InvalidSourceCallsign
+                | ServerErrorCode::NoSuchCallsign
+                | ServerErrorCode::NoFlightPlan
+                | ServerErrorCode::NoWeatherProfile
+                | ServerErrorCode::InvalidControl
+        )
+    }
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct ServerError<'a> {
+    pub from: Cow<'a, str>,
+    pub to: Cow<'a, str>,
+    pub code: ServerErrorCode,
+    /// What the error is about, e.g. the callsign that was not found
+    pub parameter: Cow<'a, str>,
+    pub message: Cow<'a, str>,
+}
+
+impl<'a> ServerError<'a> {
+    pub fn into_owned(self) -> ServerError<'static> {
+        ServerError {
+            from: owned(self.from),
+            to: owned(self.to),
+            code: self.code,
+            parameter: owned(self.parameter),
+            message: owned(self.message),
+        }
+    }
+}
+
+impl<'a> Packet<'a> for ServerError<'a> {
+    fn from_string(fields: &[&'a str]) -> Result<Self, ParseError> {
+        // The message can contain the delimiter
+        if fields.len() < 5 {
+            return Err(ParseError::MissingField(4));
+        }
+
+        Ok(Self {
+            from: get_field!(fields, 0).into(),
+            to: get_field!(fields, 1).into(),
+            code: ServerErrorCode::from_code(force_parse!(u16, fields, 2)),
+            parameter: get_field!(fields, 3).into(),
+            message: join_fields(&fields[4..]),
+        })
+    }
+
+    fn to_fsd_string(&self) -> String {
+        format!(
+            "$ER{}:{}:{:03}:{}:{}",
+            self.from,
+            self.to,
+            self.code.code(),
+            self.parameter,
+            self.message
+        )
+    }
+}
+
+/// Sent by a supervisor or the server right before disconnecting a client
+#[derive(PartialEq, Debug, Clone)]
+pub struct KillRequest<'a> {
+    pub from: Cow<'a, str>,
+    pub to: Cow<'a, str>,
+    pub reason: Cow<'a, str>,
+}
+
+impl<'a> KillRequest<'a> {
+    pub fn into_owned(self) -> KillRequest<'static> {
+        KillRequest {
+            from: owned(self.from),
+            to: owned(self.to),
+            reason: owned(self.reason),
+        }
+    }
+}
+
+impl<'a> Packet<'a> for KillRequest<'a> {
+    fn from_string(fields: &[&'a str]) -> Result<Self, ParseError> {
+        if fields.len() < 2 {
+            return Err(ParseError::MissingField(1));
+        }
+
+        Ok(Self {
+            from: fields[0].into(),
+            to: fields[1].into(),
+            // The reason is optional and can contain the delimiter
+            reason: join_fields(&fields[2..]),
+        })
+    }
+
+    fn to_fsd_string(&self) -> String {
+        match self.reason.is_empty() {
+            true => format!("$!!{}:{}", self.from, self.to),
+            false => format!("$!!{}:{}:{}", self.from, self.to, self.reason),
+        }
+    }
+}
+
+/// `$PI` asks the other side to echo `token` back in a `$PO`, which is how clients and
+/// servers measure round trip times
+#[derive(Debug, PartialEq, Clone)]
+pub struct Ping<'a> {
+    pub is_response: bool,
+    pub from: Cow<'a, str>,
+    pub to: Cow<'a, str>,
+    /// Usually the sender's clock in seconds, but anything the other side echoes will do
+    pub token: Cow<'a, str>,
+}
+
+impl<'a> Ping<'a> {
+    pub fn into_owned(self) -> Ping<'static> {
+        Ping {
+            is_response: self.is_response,
+            from: owned(self.from),
+            to: owned(self.to),
+            token: owned(self.token),
+        }
+    }
+
+    pub fn new(fields: &[&'a str], is_response: bool) -> Result<Self, ParseError> {
+        Ok(Self {
+            is_response,
+            from: get_field!(fields, 0).into(),
+            to: get_field!(fields, 1).into(),
+            token: get_field!(fields, 2).into(),
+        })
+    }
+}
+
+impl<'a> Packet<'a> for Ping<'a> {
+    fn from_string(fields: &[&'a str]) -> Result<Self, ParseError> {
+        Self::new(fields, false)
+    }
+
+    fn to_fsd_string(&self) -> String {
+        format!(
+            "{}{}:{}:{}",
+            if self.is_response { "$PO" } else { "$PI" },
+            self.from,
+            self.to,
+            self.token
+        )
+    }
+}
+
+/// `#DL`, sent by servers every now and then to keep connections from going idle
+#[derive(Debug, PartialEq, Clone)]
+pub struct Heartbeat<'a> {
+    pub from: Cow<'a, str>,
+    pub to: Cow<'a, str>,
+    /// Whatever else the server sent along, servers differ on it
+    pub payload: Cow<'a, str>,
+}
+
+impl<'a> Heartbeat<'a> {
+    pub fn into_owned(self) -> Heartbeat<'static> {
+        Heartbeat {
+            from: owned(self.from),
+            to: owned(self.to),
+            payload: owned(self.payload),
+        }
+    }
+}
+
+impl<'a> Packet<'a> for Heartbeat<'a> {
+    fn from_string(fields: &[&'a str]) -> Result<Self, ParseError> {
+        if fields.len() < 2 {
+            return Err(ParseError::MissingField(1));
+        }
+
+        Ok(Self {
+            from: fields[0].into(),
+            to: fields[1].into(),
+            payload: join_fields(&fields[2..]),
+        })
+    }
+
+    fn to_fsd_string(&self) -> String {
+        match self.payload.is_empty() {
+            true => format!("#DL{}:{}", self.from, self.to),
+            false => format!("#DL{}:{}:{}", self.from, self.to, self.payload),
+        }
+    }
+}
+
+/// `#WX`, a client asking a classic FSD server for the weather profile of a station
+#[derive(Debug, PartialEq, Clone)]
+pub struct WeatherRequest<'a> {
+    pub from: Cow<'a, str>,
+    pub to: Cow<'a, str>,
+    pub station: Cow<'a, str>,
+}
+
+impl<'a> WeatherRequest<'a> {
+    pub fn into_owned(self) -> WeatherRequest<'static> {
+        WeatherRequest {
+            from: owned(self.from),
+            to: owned(self.to),
+            station: owned(self.station),
+        }
+    }
+}
+
+impl<'a> Packet<'a> for WeatherRequest<'a> {
+    fn from_string(fields: &[&'a str]) -> Result<Self, ParseError> {
+        Ok(Self {
+            from: get_field!(fields, 0).into(),
+            to: get_field!(fields, 1).into(),
+            station: get_field!(fields, 2).into(),
+        })
+    }
+
+    fn to_fsd_string(&self) -> String {
+        format!("#WX{}:{}:{}", self.from, self.to, self.station)
+    }
+}
+
+/// Temperature up to `ceiling` feet, in degrees Celsius
+#[derive(Debug, PartialEq, Clone)]
+pub struct TemperatureLayer {
+    pub ceiling: i32,
+    pub temperature: i32,
+}
+
+/// Wind between `floor` and `ceiling` feet
+#[derive(Debug, PartialEq, Clone)]
+pub struct WindLayer {
+    pub ceiling: i32,
+    pub floor: i32,
+    /// Where the wind comes from in degrees
+    pub direction: i32,
+    /// In knots
+    pub speed: i32,
+    pub gusting: bool,
+    pub turbulence: i32,
+}
+
+/// Clouds between `floor` and `ceiling` feet
+#[derive(Debug, PartialEq, Clone)]
+pub struct CloudLayer {
+    pub ceiling: i32,
+    pub floor: i32,
+    /// In eighths of the sky
+    pub coverage: i32,
+    pub icing: bool,
+    pub turbulence: i32,
+}
+
+// Layers in the weather profiles classic FSD servers send, the thunderstorm layer not counted
+const TEMPERATURE_LAYERS: usize = 4;
+const WIND_LAYERS: usize = 4;
+const CLOUD_LAYERS: usize = 2;
+
+// Parses the `count` layers of `size` fields that start at `start`, reporting errors with
+// their index in the whole packet
+fn parse_layers<'a, T, F>(
+    fields: &[&'a str],
+    start: usize,
+    size: usize,
+    count: usize,
+    parse: F,
+) -> Result<Vec<T>, ParseError>
+where
+    F: Fn(&[&'a str]) -> Result<T, ParseError>,
+{
+    (0..count)
+        .map(|layer| {
+            let offset = start + layer * size;
+            parse(&fields[offset..offset + size]).map_err(|error| match error {
+                ParseError::MissingField(index) => ParseError::MissingField(offset + index),
+                ParseError::InvalidNumber(index) => ParseError::InvalidNumber(offset + index),
+                error => error,
+            })
+        })
+        .collect()
+}
+
+fn layer_string<T, F: Fn(&T) -> String>(layers: &[T], encode: F) -> String {
+    layers
+        .iter()
+        .map(|layer| format!(":{}", encode(layer)))
+        .collect()
+}
+
+/// `#TD`, the temperature layers of a weather profile from a classic FSD server
+#[derive(Debug, PartialEq, Clone)]
+pub struct TemperatureData<'a> {
+    pub from: Cow<'a, str>,
+    pub to: Cow<'a, str>,
+    /// The four layers from the lowest up
+    pub layers: Vec<TemperatureLayer>,
+    /// Barometric pressure in hundredths of inches of mercury
+    pub pressure: i32,
+}
+
+impl<'a> TemperatureData<'a> {
+    pub fn into_owned(self) -> TemperatureData<'static> {
+        TemperatureData {
+            from: owned(self.from),
+            to: owned(self.to),
+            layers: self.layers,
+            pressure: self.pressure,
+        }
+    }
+}
+
+impl<'a> Packet<'a> for TemperatureData<'a> {
+    fn from_string(fields: &[&'a str]) -> Result<Self, ParseError> {
+        // Pairs of ceiling and temperature, then the pressure
+        let pressure = 2 + TEMPERATURE_LAYERS * 2;
+        if fields.len() <= pressure {
+            return Err(ParseError::MissingField(fields.len().max(2)));
+        }
+
+        Ok(Self {
+            from: get_field!(fields, 0).into(),
+            to: get_field!(fields, 1).into(),
+            layers: parse_layers(fields, 2, 2, TEMPERATURE_LAYERS, |layer| {
+                Ok(TemperatureLayer {
+                    ceiling: force_parse!(i32, layer, 0),
+                    temperature: force_parse!(i32, layer, 1),
+                })
+            })?,
+            pressure: force_parse!(i32, fields, pressure),
+        })
+    }
+
+    fn to_fsd_string(&self) -> String {
+        format!(
+            "#TD{}:{}{}:{}",
+            self.from,
+            self.to,
+            layer_string(&self.layers, |layer| format!(
+                "{}:{}",
+                layer.ceiling, layer.temperature
+            )),
+            self.pressure
+        )
+    }
+}
+
+/// `#WD`, the wind layers of a weather profile from a classic FSD server
+#[derive(Debug, PartialEq, Clone)]
+pub struct WindData<'a> {
+    pub from: Cow<'a, str>,
+    pub to: Cow<'a, str>,
+    /// The four layers from the lowest up
+    pub layers: Vec<WindLayer>,
+}
+
+impl<'a> WindData<'a> {
+    pub fn into_owned(self) -> WindData<'static> {
+        WindData {
+            from: owned(self.from),
+            to: owned(self.to),
+            layers: self.layers,
+        }
+    }
+}
+
+impl<'a> Packet<'a> for WindData<'a> {
+    fn from_string(fields: &[&'a str]) -> Result<Self, ParseError> {
+        if fields.len() < 2 + WIND_LAYERS * 6 {
+            return Err(ParseError::MissingField(fields.len().max(2)));
+        }
+
+        Ok(Self {
+            from: get_field!(fields, 0).into(),
+            to: get_field!(fields, 1).into(),
+            layers: parse_layers(fields, 2, 6, WIND_LAYERS, |layer| {
+                Ok(WindLayer {
+                    ceiling: force_parse!(i32, layer, 0),
+                    floor: force_parse!(i32, layer, 1),
+                    direction: force_parse!(i32, layer, 2),
+                    speed: force_parse!(i32, layer, 3),
+                    gusting: force_parse!(u8, layer, 4) != 0,
+                    turbulence: force_parse!(i32, layer, 5),
+                })
+            })?,
+        })
+    }
+
+    fn to_fsd_string(&self) -> String {
+        format!(
+            "#WD{}:{}{}",
+            self.from,
+            self.to,
+            layer_string(&self.layers, |layer| format!(
+                "{}:{}:{}:{}:{}:{}",
+                layer.ceiling,
+                layer.floor,
+                layer.direction,
+                layer.speed,
+                layer.gusting as u8,
+                layer.turbulence
+            ))
+        )
+    }
+}
+
+/// `#CD`, the cloud layers and visibility of a weather profile from a classic FSD server
+#[derive(Debug, PartialEq, Clone)]
+pub struct CloudData<'a> {
+    pub from: Cow<'a, str>,
+    pub to: Cow<'a, str>,
+    /// The two layers from the lowest up
+    pub layers: Vec<CloudLayer>,
+    pub thunderstorm: CloudLayer,
+    /// In statute miles
+    pub visibility: f32,
+}
+
+impl<'a> CloudData<'a> {
+    pub fn into_owned(self) -> CloudData<'static> {
+        CloudData {
+            from: owned(self.from),
+            to: owned(self.to),
+            layers: self.layers,
+            thunderstorm: self.thunderstorm,
+            visibility: self.visibility,
+        }
+    }
+}
+
+fn cloud_string(layer: &CloudLayer) -> String {
+    format!(
+        "{}:{}:{}:{}:{}",
+        layer.ceiling, layer.floor, layer.coverage, layer.icing as u8, layer.turbulence
+    )
+}
+
+impl<'a> Packet<'a> for CloudData<'a> {
+    fn from_string(fields: &[&'a str]) -> Result<Self, ParseError> {
+        // Cloud layers, the thunderstorm layer last, then the visibility
+        let visibility = 2 + (CLOUD_LAYERS + 1) * 5;
+        if fields.len() <= visibility {
+            return Err(ParseError::MissingField(fields.len().max(2)));
+        }
+        let mut layers = parse_layers(fields, 2, 5, CLOUD_LAYERS + 1, |layer| {
+            Ok(CloudLayer {
+                ceiling: force_parse!(i32, layer, 0),
+                floor: force_parse!(i32, layer, 1),
+                coverage: force_parse!(i32, layer, 2),
+                icing: force_parse!(u8, layer, 3) != 0,
+                turbulence: force_parse!(i32, layer, 4),
+            })
+        })?;
+        let thunderstorm = layers.pop().ok_or(ParseError::MissingField(2))?;
+
+        Ok(Self {
+            from: get_field!(fields, 0).into(),
+            to: get_field!(fields, 1).into(),
+            layers,
+            thunderstorm,
+            visibility: force_parse!(f32, fields, visibility),
+        })
+    }
+
+    fn to_fsd_string(&self) -> String {
+        format!(
+            "#CD{}:{}{}:{}:{}",
+            self.from,
+            self.to,
+            layer_string(&self.layers, cloud_string),
+            cloud_string(&self.thunderstorm),
+            self.visibility
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_rating_convert() {
+        assert_eq!(NetworkRating::from_string(""), NetworkRating::Undefined);
+        assert_eq!(NetworkRating::from_string("30"), NetworkRating::Undefined);
+        assert_eq!(NetworkRating::from_string("1"), NetworkRating::OBS);
+    }
+
+    #[test]
+    fn test_facility_convert() {
+        assert_eq!(NetworkFacility::from_string(""), NetworkFacility::Undefined);
+        assert_eq!(
             NetworkFacility::from_string("30"),
             NetworkFacility::Undefined
         );
         assert_eq!(NetworkFacility::from_string("1"), NetworkFacility::FSS);
     }
+
+    #[test]
+    fn test_missing_field() {
+        assert_eq!(
+            PilotPosition::from_string(&[
+                "S",
+                "N513PW",
+                "4717",
+                "1",
+                "41.9",
+                "-72.6",
+                "174",
+                "0",
+                "4282386784"
+            ]),
+            Err(ParseError::MissingField(9))
+        );
+    }
+
+    #[test]
+    fn test_invalid_number() {
+        assert_eq!(
+            ATCPosition::from_string(&["BOS_APP", "33000", "5", "far", "5", "42.3", "-70.9"]),
+            Err(ParseError::InvalidNumber(3))
+        );
+    }
+
+    #[test]
+    fn test_invalid_enum() {
+        assert_eq!(
+            PlaneInfo::from_string(&["DW033", "TOWER", "PI", "X", "0", "9", "B738"]),
+            Err(ParseError::InvalidEnumValue(5))
+        );
+    }
 }
@@ -0,0 +1,632 @@
+use crate::fsdpackets::*;
+use crate::lines::{accept_connections, write_line, AcceptThread, Incoming, LineReader, TICK};
+use crate::parser::{PacketTypes, Parser};
+use std::collections::HashMap;
+use std::io;
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// How far pilots see and are seen, in nautical miles. Controllers use their `vis_range`.
+pub const PILOT_RANGE: u16 = 50;
+
+// Broadcast address of queries meant for every controller, e.g. handoffs
+const ATC_BROADCAST: &str = "@94835";
+const EARTH_RADIUS_NM: f64 = 3440.065;
+
+fn distance_nm(a: (f64, f64), b: (f64, f64)) -> f64 {
+    let (lat_a, lat_b) = (a.0.to_radians(), b.0.to_radians());
+    let half_lat = (lat_b - lat_a) / 2.0;
+    let half_lon = (b.1 - a.1).to_radians() / 2.0;
+    let h = half_lat.sin().powi(2) + lat_a.cos() * lat_b.cos() * half_lon.sin().powi(2);
+    2.0 * EARTH_RADIUS_NM * h.sqrt().min(1.0).asin()
+}
+
+// Sender and recipient callsigns of a line, without parsing all of it
+fn addresses(line: &str) -> (Option<&str>, Option<&str>) {
+    let body = match line.as_bytes().first() {
+        // Pilot positions start with the transponder mode
+        Some(b'@') => line[1..].split_once(':').map_or("", |(_, rest)| rest),
+        Some(b'%') | Some(b'^') => &line[1..],
+        Some(b'#') | Some(b'$') => line.get(3..).unwrap_or(""),
+        _ => return (None, None),
+    };
+    let mut fields = body.split(':');
+    (fields.next(), fields.next())
+}
+
+struct Client {
+    outgoing: Sender<String>,
+    client_type: NetworkClientType,
+    rating: NetworkRating,
+    position: Option<(f64, f64)>,
+    range: f64,
+}
+
+impl Client {
+    fn is_atc(&self) -> bool {
+        self.client_type == NetworkClientType::ATC
+    }
+
+    fn in_range(&self, other: &Client) -> bool {
+        match (self.position, other.position) {
+            (Some(a), Some(b)) => distance_nm(a, b) <= self.range.max(other.range),
+            _ => false,
+        }
+    }
+}
+
+// Clients by upper case callsign, and the flight plans they filed
+#[derive(Default)]
+struct State {
+    clients: HashMap<String, Client>,
+    flight_plans: HashMap<String, String>,
+}
+
+impl State {
+    fn send_to(&self, callsign: &str, line: &str) {
+        if let Some(client) = self.clients.get(&callsign.to_uppercase()) {
+            let _ = client.outgoing.send(line.to_string());
+        }
+    }
+
+    // Sends to every client but the sender that `filter` accepts
+    fn send_where<F: Fn(&Client, &Client) -> bool>(&self, from: &str, line: &str, filter: F) {
+        let sender = match self.clients.get(from) {
+            Some(sender) => sender,
+            None => return,
+        };
+        for (callsign, client) in &self.clients {
+            if callsign != from && filter(sender, client) {
+                let _ = client.outgoing.send(line.to_string());
+            }
+        }
+    }
+}
+
+/// A small FSD server for testing clients offline.
+///
+/// Clients log in with `#AP` or `#AA` and off with `#DP` or `#DA`. Positions and radio
+/// messages go to clients in range of each other, other traffic to the callsign it is
+/// addressed to, `*` for everyone or `@94835` for all controllers. There are no passwords,
+/// no ratings checks and no flight plan validation.
+pub struct FsdServer {
+    listener: TcpListener,
+    state: Arc<Mutex<State>>,
+    stop: Arc<AtomicBool>,
+}
+
+impl FsdServer {
+    pub fn bind<A: ToSocketAddrs>(address: A) -> io::Result<Self> {
+        Ok(FsdServer {
+            listener: TcpListener::bind(address)?,
+            state: Arc::new(Mutex::new(State::default())),
+            stop: Arc::new(AtomicBool::new(false)),
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Accepts clients until the server is stopped, each on its own thread
+    pub fn run(self) -> io::Result<()> {
+        accept_connections(&self.listener, &self.stop, |stream| {
+            let session = Session {
+                state: self.state.clone(),
+                stop: self.stop.clone(),
+                callsign: None,
+            };
+            thread::Builder::new()
+                .name("fsd-server-client".to_string())
+                .spawn(move || session.serve(stream))?;
+            Ok(())
+        })
+    }
+
+    /// Runs the server on a background thread
+    pub fn spawn(self) -> io::Result<ServerHandle> {
+        let address = self.local_addr()?;
+        let state = self.state.clone();
+        let stop = self.stop.clone();
+        let thread = AcceptThread::spawn("fsd-server", address, stop, move || self.run())?;
+        Ok(ServerHandle { state, thread })
+    }
+}
+
+/// A server running on a background thread, created with `FsdServer::spawn`. Dropping it stops
+/// the server.
+pub struct ServerHandle {
+    state: Arc<Mutex<State>>,
+    thread: AcceptThread,
+}
+
+impl ServerHandle {
+    pub fn local_addr(&self) -> SocketAddr {
+        self.thread.local_addr()
+    }
+
+    /// Callsigns of the clients logged in
+    pub fn clients(&self) -> Vec<String> {
+        lock(&self.state).clients.keys().cloned().collect()
+    }
+
+    /// Stops accepting and disconnects every client
+    pub fn shutdown(mut self) -> io::Result<()> {
+        self.thread.stop()
+    }
+}
+
+fn lock(state: &Mutex<State>) -> MutexGuard<'_, State> {
+    state.lock().unwrap_or_else(|error| error.into_inner())
+}
+
+// One client connection
+struct Session {
+    state: Arc<Mutex<State>>,
+    stop: Arc<AtomicBool>,
+    callsign: Option<String>,
+}
+
+impl Session {
+    fn serve(mut self, stream: TcpStream) -> io::Result<()> {
+        stream.set_nodelay(true)?;
+        stream.set_read_timeout(Some(TICK))?;
+
+        // A thread of its own for writing, so a slow client does not hold up the others
+        let (outgoing, lines) = mpsc::channel::<String>();
+        let mut writer = stream.try_clone()?;
+        thread::Builder::new()
+            .name("fsd-server-writer".to_string())
+            .spawn(move || {
+                for line in lines {
+                    if write_line(&mut writer, &line).is_err() {
+                        break;
+                    }
+                }
+                let _ = writer.shutdown(Shutdown::Both);
+            })?;
+
+        let challenge = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .subsec_nanos();
+        let greeting = ServerIdentification {
+            from: "SERVER".into(),
+            to: "CLIENT".into(),
+            version: "fsdparser".into(),
+            initial_challenge: format!("{:08x}", challenge).into(),
+        };
+        let _ = outgoing.send(greeting.to_fsd_string());
+
+        // The writer closes the connection once everything queued for the client is sent
+        let result = self.read_lines(&mut LineReader::new(stream), &outgoing);
+        self.log_off();
+        result
+    }
+
+    fn read_lines(
+        &mut self,
+        reader: &mut LineReader<TcpStream>,
+        outgoing: &Sender<String>,
+    ) -> io::Result<()> {
+        while !self.stop.load(Ordering::SeqCst) {
+            let line = match reader.next_line()? {
+                Incoming::Line(line) => line,
+                Incoming::Idle => continue,
+                Incoming::Closed => break,
+            };
+            let packet = Parser::parse(&line).ok();
+
+            let callsign = match &self.callsign {
+                Some(callsign) => callsign.clone(),
+                None => {
+                    if let Some(PacketTypes::NetworkClient(login)) = packet {
+                        if !self.log_in(&login, outgoing) {
+                            break;
+                        }
+                    }
+                    continue;
+                }
+            };
+
+            // Nobody gets to speak for someone else
+            match addresses(&line) {
+                (Some(sender), _) if sender.eq_ignore_ascii_case(&callsign) => (),
+                _ => continue,
+            }
+            if let Some(PacketTypes::DeleteClient(_)) = packet {
+                break;
+            }
+            self.route(&callsign, &line, packet);
+        }
+        Ok(())
+    }
+
+    // False if the callsign is taken
+    fn log_in(&mut self, login: &NetworkClient<'_>, outgoing: &Sender<String>) -> bool {
+        let callsign = login.callsign.to_uppercase();
+        let mut state = lock(&self.state);
+        if callsign.is_empty() || state.clients.contains_key(&callsign) {
+            let error = ServerError {
+                from: "server".into(),
+                to: login.callsign.clone(),
+                code: ServerErrorCode::CallsignInUse,
+                parameter: "".into(),
+                message: "Callsign in use".into(),
+            };
+            let _ = outgoing.send(error.to_fsd_string());
+            return false;
+        }
+
+        state.clients.insert(
+            callsign.clone(),
+            Client {
+                outgoing: outgoing.clone(),
+                client_type: login.client_type.clone(),
+                rating: login.rating.clone(),
+                position: None,
+                range: f64::from(PILOT_RANGE),
+            },
+        );
+        // Others learn about the new client, but not its password
+        let announcement = NetworkClient {
+            password: "".into(),
+            ..login.clone()
+        };
+        state.send_where(&callsign, &announcement.to_fsd_string(), |_, _| true);
+        self.callsign = Some(callsign);
+        true
+    }
+
+    fn log_off(&mut self) {
+        let callsign = match self.callsign.take() {
+            Some(callsign) => callsign,
+            None => return,
+        };
+        let mut state = lock(&self.state);
+        let client = match state.clients.remove(&callsign) {
+            Some(client) => client,
+            None => return,
+        };
+        state.flight_plans.remove(&callsign);
+        let logoff = DeleteClient {
+            client_type: client.client_type,
+            callsign: callsign.into(),
+            cid: "".into(),
+        };
+        for other in state.clients.values() {
+            let _ = other.outgoing.send(logoff.to_fsd_string());
+        }
+    }
+
+    fn route(&self, callsign: &str, line: &str, packet: Option<PacketTypes<'_>>) {
+        let mut state = lock(&self.state);
+        match packet {
+            Some(PacketTypes::PilotPosition(position)) => {
+                if let Some(client) = state.clients.get_mut(callsign) {
+                    client.position = Some((f64::from(position.lat), f64::from(position.lon)));
+                }
+                state.send_where(callsign, line, Client::in_range);
+            }
+            Some(PacketTypes::FastPilotPosition(position)) => {
+                if let Some(client) = state.clients.get_mut(callsign) {
+                    client.position = Some((position.lat, position.lon));
+                }
+                state.send_where(callsign, line, Client::in_range);
+            }
+            Some(PacketTypes::ATCPosition(position)) => {
+                if let Some(client) = state.clients.get_mut(callsign) {
+                    client.position = Some((f64::from(position.lat), f64::from(position.lon)));
+                    client.range = f64::from(position.vis_range);
+                }
+                state.send_where(callsign, line, Client::in_range);
+            }
+            Some(PacketTypes::TextMessage(message)) => match message.receiver {
+                TextMessageReceiver::Broadcast => state.send_where(callsign, line, |_, _| true),
+                TextMessageReceiver::Wallop => state.send_where(callsign, line, |_, client| {
+                    matches!(client.rating, NetworkRating::SUP | NetworkRating::ADM)
+                }),
+                TextMessageReceiver::ATC => {
+                    state.send_where(callsign, line, |_, client| client.is_atc())
+                }
+                // Clients listen to whatever they are tuned to, the server only knows who is near
+                TextMessageReceiver::Radio(_) => state.send_where(callsign, line, Client::in_range),
+                TextMessageReceiver::PrivateMessage(to) => state.send_to(&to, line),
+            },
+            Some(PacketTypes::FlightPlan(plan)) => {
+                state
+                    .flight_plans
+                    .insert(plan.callsign.to_uppercase(), line.to_string());
+                state.send_where(callsign, line, |_, client| client.is_atc());
+            }
+            Some(PacketTypes::Ping(ping)) if !ping.is_response && ping.to == "SERVER" => {
+                let pong = Ping {
+                    is_response: true,
+                    to: ping.from,
+                    from: ping.to,
+                    token: ping.token,
+                };
+                state.send_to(callsign, &pong.to_fsd_string());
+            }
+            Some(PacketTypes::ClientQuery(query)) if query.to == "SERVER" => {
+                if let Some(answer) = Self::answer(&state, callsign, &query) {
+                    state.send_to(callsign, &answer);
+                }
+            }
+            _ => match addresses(line).1 {
+                Some("*") => state.send_where(callsign, line, |_, _| true),
+                Some(ATC_BROADCAST) => {
+                    state.send_where(callsign, line, |_, client| client.is_atc())
+                }
+                Some(to) => state.send_to(to, line),
+                None => (),
+            },
+        }
+    }
+
+    fn answer(state: &State, callsign: &str, query: &ClientQuery<'_>) -> Option<String> {
+        match (&query.query_type, &query.payload) {
+            (ClientQueryType::IsValidATC, ClientQueryPayload::IsValidATCQuery(Some(target))) => {
+                let is_valid = state
+                    .clients
+                    .get(&target.to_uppercase())
+                    .is_some_and(|client| client.is_atc() && client.rating != NetworkRating::OBS);
+                let answer = ClientQuery {
+                    is_response: true,
+                    from: "SERVER".into(),
+                    to: callsign.into(),
+                    query_type: ClientQueryType::IsValidATC,
+                    payload: ClientQueryPayload::IsValidATCResponse(is_valid, Some(target.clone())),
+                };
+                Some(answer.to_fsd_string())
+            }
+            (ClientQueryType::FlightPlan, ClientQueryPayload::FlightPlan(target)) => {
+                state.flight_plans.get(&target.to_uppercase()).cloned()
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::client::{ClientSession, FsdClient};
+    use crate::util::Frequency;
+    use std::time::{Duration, Instant};
+
+    fn connect(
+        server: &ServerHandle,
+        callsign: &str,
+        atc: bool,
+        lat: f32,
+        lon: f32,
+    ) -> ClientSession {
+        let identification = ClientIdentification {
+            from: "".into(),
+            to: "".into(),
+            client_id: "de1e".into(),
+            client_name: "test".into(),
+            major_version: 1,
+            minor_version: 0,
+            cid: "1234567".into(),
+            sys_id: "".into(),
+            initial_challenge: "".into(),
+        };
+        let login = NetworkClient {
+            client_type: match atc {
+                true => NetworkClientType::ATC,
+                false => NetworkClientType::Pilot,
+            },
+            callsign: callsign.to_string().into(),
+            to: "SERVER".into(),
+            real_name: "Test".into(),
+            cid: "1234567".into(),
+            password: "secret".into(),
+            rating: NetworkRating::C1,
+            simulator_type: None,
+            protocol_ver: 100,
+        };
+
+        let mut client = FsdClient::new(identification, login);
+        client.set_position_interval(Duration::from_millis(20));
+        if atc {
+            client.set_atc_position(ATCPosition {
+                freq: Frequency::from_packet_string("24500").unwrap(),
+                facility: NetworkFacility::APP,
+                vis_range: 100,
+                rating: NetworkRating::C1,
+                lat,
+                lon,
+                callsign: callsign.to_string().into(),
+            });
+        } else {
+            client.set_pilot_position(PilotPosition {
+                callsign: callsign.to_string().into(),
+                squawk_code: 1200,
+                squawking: SquawkType::Charlie,
+                rating: NetworkRating::OBS,
+                lat,
+                lon,
+                true_alt: 3000,
+                pressure_alt: 3000,
+                ground_speed: 120,
+                pbh: FlightSurfaces {
+                    pitch: 0.0,
+                    bank: 0.0,
+                    hdg: 0.0,
+                    flags: 0,
+                },
+            });
+        }
+        client.connect(server.local_addr()).unwrap()
+    }
+
+    // Skips packets until one matches, failing after a while
+    fn wait_for<F: Fn(&PacketTypes<'static>) -> bool>(
+        session: &ClientSession,
+        matches: F,
+    ) -> Vec<PacketTypes<'static>> {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let mut skipped = Vec::new();
+        loop {
+            // Positions keep coming, so a timeout per packet would never run out
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            let packet = session
+                .receiver()
+                .recv_timeout(timeout)
+                .expect("Packet did not arrive");
+            if matches(&packet) {
+                return skipped;
+            }
+            skipped.push(packet);
+        }
+    }
+
+    fn text(packet: &PacketTypes<'_>, expected: &str) -> bool {
+        matches!(packet, PacketTypes::TextMessage(message) if message.text == expected)
+    }
+
+    fn is_position_of(packet: &PacketTypes<'_>, callsign: &str) -> bool {
+        match packet {
+            PacketTypes::PilotPosition(position) => position.callsign == callsign,
+            PacketTypes::ATCPosition(position) => position.callsign == callsign,
+            _ => false,
+        }
+    }
+
+    fn send(session: &ClientSession, line: &str) {
+        session.send(&line.parse().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_routing() {
+        let server = FsdServer::bind("127.0.0.1:0").unwrap().spawn().unwrap();
+        let atc = connect(&server, "BOS_APP", true, 42.36, -71.0);
+        let pilot = connect(&server, "N123", false, 42.5, -71.2);
+        // Both have been announced and placed once the pilot sees the controller
+        wait_for(&pilot, |packet| is_position_of(packet, "BOS_APP"));
+        let far = connect(&server, "FAR1", false, 34.0, -118.0);
+        while server.clients().len() < 3 {
+            thread::sleep(TICK);
+        }
+        let mut clients = server.clients();
+        clients.sort();
+        assert_eq!(clients, vec!["BOS_APP", "FAR1", "N123"]);
+
+        send(&atc, "#TMBOS_APP:N123:private");
+        wait_for(&pilot, |packet| text(packet, "private"));
+        send(&pilot, "#TMN123:@24500:on frequency");
+        wait_for(&atc, |packet| text(packet, "on frequency"));
+        send(&atc, "#TMBOS_APP:*:everyone");
+        // Too far away for positions and radio
+        let skipped = wait_for(&far, |packet| text(packet, "everyone"));
+        assert!(!skipped.iter().any(|packet| text(packet, "on frequency")
+            || is_position_of(packet, "BOS_APP")
+            || is_position_of(packet, "N123")));
+
+        // The pilot's session answers the query itself
+        send(&atc, "$CQBOS_APP:N123:RN");
+        wait_for(&atc, |packet| {
+            matches!(
+                packet,
+                PacketTypes::ClientQuery(ClientQuery {
+                    is_response: true,
+                    query_type: ClientQueryType::RealName,
+                    ..
+                })
+            )
+        });
+        send(&atc, "$CQBOS_APP:SERVER:ATC:BOS_APP");
+        wait_for(&atc, |packet| {
+            matches!(
+                packet,
+                PacketTypes::ClientQuery(ClientQuery {
+                    payload: ClientQueryPayload::IsValidATCResponse(true, _),
+                    ..
+                })
+            )
+        });
+
+        // Pings are answered by the server, or by the session they are addressed to
+        send(&pilot, "$PIN123:SERVER:42");
+        wait_for(
+            &pilot,
+            |packet| matches!(packet, PacketTypes::Ping(pong) if pong.is_response && pong.token == "42"),
+        );
+        send(&atc, "$PIBOS_APP:N123:43");
+        wait_for(
+            &atc,
+            |packet| matches!(packet, PacketTypes::Ping(pong) if pong.from == "N123" && pong.token == "43"),
+        );
+
+        send(
+            &pilot,
+            "$FPN123:*A:I:H/B738/L:420:KBOS:1200:0:FL350:KJFK:1:0:3:0:KEWR:none:DCT",
+        );
+        wait_for(&atc, |packet| matches!(packet, PacketTypes::FlightPlan(_)));
+        send(&far, "$CQFAR1:SERVER:FP:N123");
+        wait_for(
+            &far,
+            |packet| matches!(packet, PacketTypes::FlightPlan(plan) if plan.callsign == "N123"),
+        );
+
+        // Nobody can send as someone else
+        send(&far, "#TMN123:BOS_APP:spoofed");
+        send(&far, "#TMFAR1:BOS_APP:genuine");
+        let skipped = wait_for(&atc, |packet| text(packet, "genuine"));
+        assert!(!skipped.iter().any(|packet| text(packet, "spoofed")));
+
+        pilot.disconnect().unwrap();
+        wait_for(
+            &atc,
+            |packet| matches!(packet, PacketTypes::DeleteClient(client) if client.callsign == "N123"),
+        );
+        drop(far);
+        drop(atc);
+        server.shutdown().unwrap();
+    }
+
+    #[test]
+    fn test_shutdown() {
+        // Nothing can connect to the unspecified address, stopping must not depend on it
+        let server = FsdServer::bind("0.0.0.0:0").unwrap().spawn().unwrap();
+        let started = Instant::now();
+        server.shutdown().unwrap();
+        assert!(started.elapsed() < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_callsign_in_use() {
+        let server = FsdServer::bind("127.0.0.1:0").unwrap().spawn().unwrap();
+        let _first = connect(&server, "N123", false, 0.0, 0.0);
+        let second = connect(&server, "N123", false, 0.0, 0.0);
+        assert!(matches!(
+            second.receiver().recv_timeout(Duration::from_secs(5)),
+            Ok(PacketTypes::ServerError(ServerError {
+                code: ServerErrorCode::CallsignInUse,
+                ..
+            }))
+        ));
+        // Turned away, so the session ends
+        assert!(second
+            .receiver()
+            .recv_timeout(Duration::from_secs(5))
+            .is_err());
+        assert_eq!(server.clients(), vec!["N123"]);
+    }
+
+    #[test]
+    fn test_distance() {
+        // Boston to New York
+        let distance = distance_nm((42.36, -71.01), (40.64, -73.78));
+        assert!((distance - 162.0).abs() < 2.0);
+        assert_eq!(addresses("@N:N123:1200:1"), (Some("N123"), Some("1200")));
+        assert_eq!(addresses("$CQA:B:RN"), (Some("A"), Some("B")));
+    }
+}
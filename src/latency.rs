@@ -0,0 +1,184 @@
+use crate::flows::{Direction, PacketSource};
+use crate::pairing::PendingRequests;
+use crate::parser::PacketTypes;
+use crate::reassembly::Connection;
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime};
+
+/// A `$PI` and the `$PO` that echoed its token
+#[derive(Debug, Clone, PartialEq)]
+pub struct RoundTrip {
+    /// The connection from the client to the server, whichever side pinged
+    pub connection: Connection,
+    /// Which way the ping went
+    pub direction: Direction,
+    pub token: String,
+    pub sent_at: SystemTime,
+    pub answered_at: SystemTime,
+}
+
+impl RoundTrip {
+    pub fn duration(&self) -> Duration {
+        self.answered_at
+            .duration_since(self.sent_at)
+            .unwrap_or_default()
+    }
+}
+
+/// Round trip times seen on one connection
+#[derive(Debug, Clone, PartialEq)]
+pub struct LatencyStats {
+    pub samples: u32,
+    pub last: Duration,
+    pub min: Duration,
+    pub max: Duration,
+    total: Duration,
+}
+
+impl LatencyStats {
+    fn new(first: Duration) -> Self {
+        LatencyStats {
+            samples: 1,
+            last: first,
+            min: first,
+            max: first,
+            total: first,
+        }
+    }
+
+    fn add(&mut self, sample: Duration) {
+        self.samples += 1;
+        self.last = sample;
+        self.min = self.min.min(sample);
+        self.max = self.max.max(sample);
+        self.total += sample;
+    }
+
+    pub fn average(&self) -> Duration {
+        self.total / self.samples
+    }
+}
+
+#[derive(Debug)]
+struct PendingPing {
+    from: String,
+    token: String,
+    timestamp: SystemTime,
+}
+
+/// Pairs pings with the pongs echoing their token to measure round trip times per
+/// connection, for packets from a `Sniffer` or anything else that produces `PacketSource`s.
+#[derive(Debug, Default)]
+pub struct LatencyTracker {
+    pending: PendingRequests<PendingPing>,
+    stats: HashMap<Connection, LatencyStats>,
+}
+
+impl LatencyTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the round trip a pong completes, other packets are ignored
+    pub fn process(&mut self, packet: &PacketSource) -> Option<RoundTrip> {
+        let ping = match &packet.packet {
+            PacketTypes::Ping(ping) => ping,
+            _ => return None,
+        };
+
+        if !ping.is_response {
+            self.pending.request(
+                packet,
+                PendingPing {
+                    from: ping.from.to_string(),
+                    token: ping.token.to_string(),
+                    timestamp: packet.timestamp,
+                },
+            );
+            return None;
+        }
+
+        let (connection, direction, sent) = self.pending.respond(packet, |pending| {
+            pending.token == ping.token && pending.from.eq_ignore_ascii_case(&ping.to)
+        })?;
+        let round_trip = RoundTrip {
+            connection,
+            direction,
+            token: sent.token,
+            sent_at: sent.timestamp,
+            answered_at: packet.timestamp,
+        };
+        let duration = round_trip.duration();
+        self.stats
+            .entry(connection)
+            .and_modify(|stats| stats.add(duration))
+            .or_insert_with(|| LatencyStats::new(duration));
+        Some(round_trip)
+    }
+
+    /// Round trip times of a connection, given either way round
+    pub fn stats(&self, connection: &Connection) -> Option<&LatencyStats> {
+        self.stats
+            .get(connection)
+            .or_else(|| self.stats.get(&connection.reversed()))
+    }
+
+    /// Every connection with at least one round trip, keyed from the client to the server
+    pub fn connections(&self) -> impl Iterator<Item = (&Connection, &LatencyStats)> {
+        self.stats.iter()
+    }
+
+    /// Drops everything known about a connection, e.g. once the client logged off
+    pub fn forget(&mut self, connection: &Connection) {
+        self.pending.forget(connection);
+        self.stats.remove(connection);
+        self.stats.remove(&connection.reversed());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::pairing::fixtures::{client_to_server, packet};
+
+    #[test]
+    fn test_round_trips() {
+        let mut tracker = LatencyTracker::new();
+        let from_client = Direction::ClientToServer;
+        let from_server = Direction::ServerToClient;
+        tracker.process(&packet(from_client, 1000, "$PIN123:SERVER:1"));
+        tracker.process(&packet(from_client, 1100, "$PIN123:SERVER:2"));
+        tracker.process(&packet(from_server, 1500, "$PISERVER:N123:1"));
+
+        // Answered out of order, and a pong the other way does not count
+        let round_trip = tracker
+            .process(&packet(from_server, 1180, "$POSERVER:N123:2"))
+            .unwrap();
+        assert_eq!(round_trip.direction, from_client);
+        assert_eq!(round_trip.duration(), Duration::from_millis(80));
+        let round_trip = tracker
+            .process(&packet(from_server, 1200, "$POSERVER:N123:1"))
+            .unwrap();
+        assert_eq!(round_trip.duration(), Duration::from_millis(200));
+        assert_eq!(
+            tracker.process(&packet(from_server, 1300, "$POSERVER:N123:1")),
+            None
+        );
+        let round_trip = tracker
+            .process(&packet(from_client, 1540, "$PON123:SERVER:1"))
+            .unwrap();
+        assert_eq!(round_trip.direction, from_server);
+        assert_eq!(round_trip.connection, client_to_server());
+
+        let stats = tracker.stats(&client_to_server().reversed()).unwrap();
+        assert_eq!(stats.samples, 3);
+        assert_eq!(stats.last, Duration::from_millis(40));
+        assert_eq!(stats.min, Duration::from_millis(40));
+        assert_eq!(stats.max, Duration::from_millis(200));
+        assert_eq!(stats.average(), Duration::from_millis(320) / 3);
+        assert_eq!(tracker.connections().count(), 1);
+
+        tracker.forget(&client_to_server());
+        assert_eq!(tracker.stats(&client_to_server()), None);
+    }
+}
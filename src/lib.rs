@@ -1,13 +1,52 @@
+mod capture;
+mod challenges;
+mod client;
+mod codec;
+mod error;
+mod flows;
 mod fsdpackets;
+mod fsdserver;
+mod latency;
+mod lines;
+#[cfg(feature = "sniffer")]
 mod managers;
+mod pairing;
 mod parser;
+mod pcap;
+mod proxy;
+mod reassembly;
+mod servers;
 mod sniffer;
+mod stream;
 mod util;
 
+pub use challenges::{ChallengeExchange, ChallengeTracker};
+pub use client::{ChallengeResponder, ClientSession, FsdClient, DEFAULT_POSITION_INTERVAL};
+pub use error::ParseError;
+pub use flows::{Direction, FlowClassifier, PacketSource};
 pub use fsdpackets::*;
+pub use fsdserver::{FsdServer, ServerHandle, PILOT_RANGE};
+pub use latency::{LatencyStats, LatencyTracker, RoundTrip};
 pub use parser::{PacketTypes, Parser};
+pub use pcap::{PcapFrame, PcapReader, PcapWriter, LINKTYPE_ETHERNET, LINKTYPE_LINUX_SLL};
+pub use proxy::{FsdProxy, ProxiedLine, ProxyAction, ProxyHandle, ProxyHook};
+pub use reassembly::{Connection, StreamReassembler, TcpSegment};
+pub use servers::{
+    parse_data_feed, DataFeedFile, HostsFile, PortDetection, Resolver, ServerList, ServerListError,
+    ServerProvider, SystemResolver, TextFile, DEFAULT_FSD_PORT,
+};
+#[cfg(feature = "http")]
+pub use servers::{DataFeedUrl, VATSIM_SERVER_FEED};
+pub use util::{AircraftConfiguration, Frequency};
+
+#[cfg(feature = "tokio")]
+pub use codec::{FsdCodec, FsdCodecError, DEFAULT_MAX_LINE_LENGTH};
+#[cfg(all(feature = "tokio", feature = "sniffer"))]
+pub use stream::SnifferStream;
 
+#[cfg(feature = "sniffer")]
+pub use capture::{CaptureSource, MemoryCapture, PacketSniffer};
 #[cfg(feature = "sniffer")]
 pub use managers::*;
 #[cfg(feature = "sniffer")]
-pub use sniffer::{PacketSource, Sniffer};
+pub use sniffer::{EthernetIpTcpPacket, LinkType, Sniffer, SnifferThread, StopHandle};
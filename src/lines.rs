@@ -0,0 +1,212 @@
+use std::convert::TryFrom;
+use std::io;
+use std::io::{BufRead, BufReader, ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+// Longer lines are cut off rather than buffered without end
+const MAX_LINE_LENGTH: usize = 65536;
+/// Connection threads of servers and proxies wake up this often to notice being stopped
+pub(crate) const TICK: Duration = Duration::from_millis(50);
+// Wait after a failed accept, e.g. when out of file descriptors, before trying again
+const ACCEPT_RETRY: Duration = Duration::from_millis(500);
+
+// FSD text is Latin-1, characters it cannot carry are sent as `?`
+pub(crate) fn to_latin1(line: &str) -> impl Iterator<Item = u8> + '_ {
+    line.chars().map(|c| u8::try_from(c).unwrap_or(b'?'))
+}
+
+pub(crate) fn from_latin1(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| *byte as char).collect()
+}
+
+/// Writes one line with its `\r\n` terminator
+pub(crate) fn write_line<W: Write>(writer: &mut W, line: &str) -> io::Result<()> {
+    let mut bytes: Vec<u8> = to_latin1(line).collect();
+    bytes.extend_from_slice(b"\r\n");
+    writer.write_all(&bytes)
+}
+
+/// Hands every connection to `serve` until `stop` is set, `serve` starts a thread for it.
+///
+/// The listener is polled, so stopping does not depend on a connection waking it up.
+pub(crate) fn accept_connections<F>(
+    listener: &TcpListener,
+    stop: &AtomicBool,
+    mut serve: F,
+) -> io::Result<()>
+where
+    F: FnMut(TcpStream) -> io::Result<()>,
+{
+    listener.set_nonblocking(true)?;
+    while !stop.load(Ordering::SeqCst) {
+        match listener.accept() {
+            // Some platforms hand out accepted sockets non-blocking like their listener
+            Ok((stream, _)) => {
+                if stream.set_nonblocking(false).is_ok() {
+                    serve(stream)?;
+                }
+            }
+            Err(error) if error.kind() == ErrorKind::WouldBlock => thread::sleep(TICK),
+            // Interrupted, or the client gave up before being accepted
+            Err(error)
+                if matches!(
+                    error.kind(),
+                    ErrorKind::Interrupted
+                        | ErrorKind::ConnectionAborted
+                        | ErrorKind::ConnectionReset
+                ) => {}
+            Err(_) => thread::sleep(ACCEPT_RETRY),
+        }
+    }
+    Ok(())
+}
+
+/// An accept loop running on a background thread. Dropping it stops the loop.
+pub(crate) struct AcceptThread {
+    address: SocketAddr,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<io::Result<()>>>,
+}
+
+impl AcceptThread {
+    /// Runs `run`, which accepts on `address` until `stop` is set
+    pub(crate) fn spawn<F>(
+        name: &str,
+        address: SocketAddr,
+        stop: Arc<AtomicBool>,
+        run: F,
+    ) -> io::Result<Self>
+    where
+        F: FnOnce() -> io::Result<()> + Send + 'static,
+    {
+        let thread = thread::Builder::new().name(name.to_string()).spawn(run)?;
+        Ok(AcceptThread {
+            address,
+            stop,
+            thread: Some(thread),
+        })
+    }
+
+    pub(crate) fn local_addr(&self) -> SocketAddr {
+        self.address
+    }
+
+    /// Stops accepting and waits for the loop to end
+    pub(crate) fn stop(&mut self) -> io::Result<()> {
+        let thread = match self.thread.take() {
+            Some(thread) => thread,
+            None => return Ok(()),
+        };
+        self.stop.store(true, Ordering::SeqCst);
+        match thread.join() {
+            Ok(result) => result,
+            Err(panic) => std::panic::resume_unwind(panic),
+        }
+    }
+}
+
+impl Drop for AcceptThread {
+    fn drop(&mut self) {
+        let _ = self.stop();
+    }
+}
+
+pub(crate) enum Incoming {
+    Line(String),
+    /// The read timed out before a whole line arrived
+    Idle,
+    Closed,
+}
+
+// Reads lines off a socket with a read timeout, keeping partial lines across timeouts
+pub(crate) struct LineReader<R: Read> {
+    reader: BufReader<R>,
+    pending: Vec<u8>,
+    // Set while throwing away the rest of an overlong line
+    discarding: bool,
+}
+
+impl<R: Read> LineReader<R> {
+    pub(crate) fn new(reader: R) -> Self {
+        LineReader {
+            reader: BufReader::new(reader),
+            pending: Vec::new(),
+            discarding: false,
+        }
+    }
+
+    pub(crate) fn get_ref(&self) -> &R {
+        self.reader.get_ref()
+    }
+
+    pub(crate) fn next_line(&mut self) -> io::Result<Incoming> {
+        loop {
+            // Never reads past the longest line allowed, however fast the data comes
+            let limit = (MAX_LINE_LENGTH + 1 - self.pending.len()) as u64;
+            match self
+                .reader
+                .by_ref()
+                .take(limit)
+                .read_until(b'\n', &mut self.pending)
+            {
+                Ok(0) => return Ok(Incoming::Closed),
+                Ok(_) if self.pending.ends_with(b"\n") && self.discarding => {
+                    self.pending.clear();
+                    self.discarding = false;
+                }
+                Ok(_) if self.pending.ends_with(b"\n") => {
+                    let line = match self.pending.strip_suffix(b"\r\n") {
+                        Some(line) => from_latin1(line),
+                        None => from_latin1(&self.pending[..self.pending.len() - 1]),
+                    };
+                    self.pending.clear();
+                    if !line.is_empty() {
+                        return Ok(Incoming::Line(line));
+                    }
+                }
+                // Too long for a line, everything up to the next `\n` goes with it
+                Ok(_) if self.pending.len() > MAX_LINE_LENGTH => {
+                    self.pending.clear();
+                    self.discarding = true;
+                }
+                // The other side closed in the middle of a line
+                Ok(_) => return Ok(Incoming::Closed),
+                Err(error)
+                    if matches!(
+                        error.kind(),
+                        ErrorKind::WouldBlock | ErrorKind::TimedOut | ErrorKind::Interrupted
+                    ) =>
+                {
+                    return Ok(Incoming::Idle);
+                }
+                Err(error) => return Err(error),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_overlong_line() {
+        let mut data = b"#TMA:B:one\r\n".to_vec();
+        data.extend(vec![b'x'; 3 * MAX_LINE_LENGTH]);
+        data.extend_from_slice(b"\r\n#TMA:B:two\r\n");
+        let mut reader = LineReader::new(&data[..]);
+
+        let mut lines = Vec::new();
+        while let Incoming::Line(line) = reader.next_line().unwrap() {
+            lines.push(line);
+        }
+        // Nothing of the overlong line comes out, not even its tail
+        assert_eq!(lines, vec!["#TMA:B:one", "#TMA:B:two"]);
+        assert!(reader.pending.capacity() <= 2 * (MAX_LINE_LENGTH + 1));
+    }
+}
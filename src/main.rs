@@ -1,7 +1,60 @@
-use fsdparser::Sniffer;
+#[path = "bin/args/mod.rs"]
+mod args;
+
+use args::take_option;
+use fsdparser::{
+    CaptureSource, ChallengeTracker, DataFeedFile, Direction, HostsFile, LatencyTracker,
+    ServerList, ServerListError, Sniffer, TextFile,
+};
+use std::env;
+use std::io;
+use std::process;
+use std::thread;
+use std::time::{Duration, UNIX_EPOCH};
 use text_io::read;
 
+struct Options {
+    output: Option<String>,
+    servers: ServerList,
+    server_files: Vec<String>,
+    feed_files: Vec<String>,
+    hosts_file: Option<String>,
+    refresh: Option<Duration>,
+    detect: bool,
+}
+
 fn main() {
+    let mut args: Vec<String> = env::args().skip(1).collect();
+
+    // --detect also picks up FSD on unlisted servers by looking at the traffic itself
+    let detect = args.iter().any(|arg| arg == "--detect");
+    args.retain(|arg| arg != "--detect");
+
+    let mut servers = ServerList::from_addresses(take_option(&mut args, "--server"));
+    servers.ports = take_option(&mut args, "--port")
+        .iter()
+        .map(|port| port.parse().expect("Invalid port!"))
+        .collect();
+    let options = Options {
+        // --write <file> saves the server traffic seen as a pcap file
+        output: take_option(&mut args, "--write").pop(),
+        servers,
+        server_files: take_option(&mut args, "--servers"),
+        feed_files: take_option(&mut args, "--feed"),
+        detect,
+        hosts_file: take_option(&mut args, "--hosts").pop(),
+        refresh: take_option(&mut args, "--refresh").pop().map(|seconds| {
+            Duration::from_secs(seconds.parse().expect("Invalid refresh interval!"))
+        }),
+    };
+
+    // A capture file given on the command line is replayed instead of sniffing live
+    if let Some(path) = args.first() {
+        let sniffer = Sniffer::open_file(path).expect("Could not open capture file!");
+        run(sniffer, options);
+        return;
+    }
+
     let mut sniffer = Sniffer::new();
 
     //Prompts user for the interface to use
@@ -23,14 +76,141 @@ fn main() {
     println!("Pick an adapter to use: ");
     let i: usize = read!();
     sniffer.set_user_interface(interfaces.get(i).unwrap());
-    sniffer.start();
-
-    loop {
-        match sniffer.next() {
-            Some(packet) => match packet {
-                _ => println!("{:?}", packet),
-            },
-            None => {}
+    run(sniffer, options);
+}
+
+fn run<S: CaptureSource + Send + 'static>(mut sniffer: Sniffer<S>, options: Options) {
+    let configured = options.servers != ServerList::new()
+        || !options.server_files.is_empty()
+        || !options.feed_files.is_empty();
+    sniffer.add_server_provider(options.servers);
+    for path in options.server_files {
+        sniffer.add_server_provider(TextFile(path.into()));
+    }
+    for path in options.feed_files {
+        sniffer.add_server_provider(DataFeedFile(path.into()));
+    }
+    if !configured {
+        add_default_provider(&mut sniffer);
+    }
+    if let Some(path) = options.hosts_file {
+        sniffer.set_resolver(HostsFile::open(path).expect("Could not read hosts file!"));
+    }
+    sniffer.set_refresh_interval(options.refresh);
+    sniffer.set_content_detection(options.detect);
+    match sniffer.load_servers() {
+        Ok(_) => (),
+        // Servers that are down or renamed should not stop the others from being watched
+        Err(error @ ServerListError::Unresolved(_)) => eprintln!("{}", error),
+        Err(error) => {
+            eprintln!("{}", error);
+            process::exit(1);
         }
     }
+
+    if let Some(path) = options.output {
+        sniffer
+            .record_to_file(path)
+            .expect("Could not create output file!");
+    }
+    let mut capture = sniffer.spawn(1024).expect("Could not start capturing!");
+
+    // Stopping rather than killing the process gets the output file finished properly
+    println!("Enter q to stop.");
+    let stop = capture.stop_handle();
+    thread::spawn(move || {
+        let mut line = String::new();
+        while let Ok(1..) = io::stdin().read_line(&mut line) {
+            if line.trim() == "q" {
+                stop.stop();
+                return;
+            }
+            line.clear();
+        }
+    });
+
+    let mut challenges = ChallengeTracker::new();
+    let mut latency = LatencyTracker::new();
+    for packet in capture.by_ref() {
+        let arrow = match packet.direction {
+            Direction::ServerToClient => "<-",
+            Direction::ClientToServer => "->",
+        };
+        let timestamp = packet
+            .timestamp
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        println!(
+            "{}.{:06} {} {} {} {:?}",
+            timestamp.as_secs(),
+            timestamp.subsec_micros(),
+            packet.connection.source,
+            arrow,
+            packet.connection.destination,
+            packet.packet
+        );
+        if let Some(exchange) = challenges.process(&packet) {
+            let delay = exchange
+                .answered_at
+                .duration_since(exchange.challenged_at)
+                .unwrap_or_default();
+            println!(
+                "  {} answered {}'s challenge {} with {} after {} ms",
+                exchange.responder,
+                exchange.challenger,
+                exchange.challenge,
+                exchange.response,
+                delay.as_millis()
+            );
+        }
+        latency.process(&packet);
+    }
+
+    for (connection, stats) in latency.connections() {
+        println!(
+            "{} <-> {}: {} round trips, {} ms average, {}-{} ms",
+            connection.source,
+            connection.destination,
+            stats.samples,
+            stats.average().as_millis(),
+            stats.min.as_millis(),
+            stats.max.as_millis()
+        );
+    }
+
+    if capture.dropped() > 0 {
+        eprintln!("Dropped {} packets", capture.dropped());
+    }
+    let mut sniffer = capture.join();
+    sniffer
+        .stop_recording()
+        .expect("Could not write output file!");
+    if let Some(error) = sniffer.take_error() {
+        eprintln!("Capture stopped: {}", error);
+    }
+}
+
+// The servers in the VATSIM data feed, or the standard FSD port if the feed cannot be
+// reached. The feed is only fetched once.
+#[cfg(feature = "http")]
+fn add_default_provider<S: CaptureSource>(sniffer: &mut Sniffer<S>) {
+    use fsdparser::ServerProvider;
+
+    match fsdparser::DataFeedUrl::default().load() {
+        Ok(servers) => sniffer.add_server_provider(servers),
+        Err(error) => {
+            eprintln!(
+                "{}, watching port {} instead",
+                error,
+                fsdparser::DEFAULT_FSD_PORT
+            );
+            sniffer.add_server_provider(fsdparser::PortDetection::default());
+        }
+    }
+}
+
+// Without the HTTP feature any connection on the standard FSD port is watched
+#[cfg(not(feature = "http"))]
+fn add_default_provider<S: CaptureSource>(sniffer: &mut Sniffer<S>) {
+    sniffer.add_server_provider(fsdparser::PortDetection::default());
 }
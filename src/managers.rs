@@ -1,24 +1,19 @@
-use crate::{util::AircraftConfiguration, ATCPosition, NetworkClient, PilotPosition};
+use crate::{
+    util::AircraftConfiguration, ATCPosition, ClientCapabilities, FastPilotPosition, NetworkClient,
+    PilotPosition,
+};
 use std::collections::HashMap;
 
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub struct Pilot {
-    client: Option<NetworkClient>,
+    client: Option<NetworkClient<'static>>,
     config: Option<AircraftConfiguration>,
-    position: Option<PilotPosition>,
+    position: Option<PilotPosition<'static>>,
+    fast_position: Option<FastPilotPosition<'static>>,
+    capabilities: Option<ClientCapabilities>,
 }
 
-impl Default for Pilot {
-    fn default() -> Self {
-        Self {
-            client: None,
-            config: None,
-            position: None,
-        }
-    }
-}
-
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub struct PilotManager {
     pilots: HashMap<String, Pilot>,
 }
@@ -30,35 +25,53 @@ impl PilotManager {
         }
     }
 
-    pub fn process_client(&mut self, client: &NetworkClient) {
-        if let Some(data) = self.pilots.get_mut(&client.callsign) {
-            data.client = Some(client.clone());
+    pub fn process_client(&mut self, client: &NetworkClient<'_>) {
+        if let Some(data) = self.pilots.get_mut(client.callsign.as_ref()) {
+            data.client = Some(client.clone().into_owned());
         } else {
             self.pilots.insert(
                 client.callsign.to_string(),
                 Pilot {
-                    client: Some(client.clone()),
+                    client: Some(client.clone().into_owned()),
                     ..Default::default()
                 },
             );
         }
     }
 
-    pub fn process_position(&mut self, position: &PilotPosition) {
-        if let Some(data) = self.pilots.get_mut(&position.callsign) {
-            data.position = Some(position.clone());
+    pub fn process_position(&mut self, position: &PilotPosition<'_>) {
+        if let Some(data) = self.pilots.get_mut(position.callsign.as_ref()) {
+            data.position = Some(position.clone().into_owned());
         } else {
             self.pilots.insert(
                 position.callsign.to_string(),
                 Pilot {
-                    position: Some(position.clone()),
+                    position: Some(position.clone().into_owned()),
                     ..Default::default()
                 },
             );
         }
     }
 
-    pub fn process_config(&mut self, callsign: &String, aircraft_config: &AircraftConfiguration) {
+    /// Moves the pilot's last regular position to where the fast update puts it, keeping the
+    /// rest of it, and stores the update for its velocities
+    pub fn process_fast_position(&mut self, fast_position: &FastPilotPosition<'_>) {
+        let data = self
+            .pilots
+            .entry(fast_position.callsign.to_string())
+            .or_default();
+        if let Some(position) = &mut data.position {
+            let true_alt = fast_position.true_alt.round() as i32;
+            position.pressure_alt += true_alt - position.true_alt;
+            position.true_alt = true_alt;
+            position.lat = fast_position.lat as f32;
+            position.lon = fast_position.lon as f32;
+            position.pbh = fast_position.pbh.clone();
+        }
+        data.fast_position = Some(fast_position.clone().into_owned());
+    }
+
+    pub fn process_config(&mut self, callsign: &str, aircraft_config: &AircraftConfiguration) {
         if let Some(data) = self.pilots.get_mut(callsign) {
             data.config = Some(aircraft_config.clone());
         } else {
@@ -72,43 +85,67 @@ impl PilotManager {
         }
     }
 
-    pub fn get_client(&self, callsign: &String) -> Option<NetworkClient> {
+    /// Stores the flags of the pilot's answer to `CAPS`
+    pub fn process_capabilities(&mut self, callsign: &str, capabilities: &ClientCapabilities) {
+        self.pilots
+            .entry(callsign.to_string())
+            .or_default()
+            .capabilities = Some(capabilities.clone());
+    }
+
+    pub fn get_client(&self, callsign: &str) -> Option<NetworkClient<'static>> {
         if let Some(pilot) = self.pilots.get(callsign) {
             return pilot.client.clone();
         }
         None
     }
 
-    pub fn get_position(&self, callsign: &String) -> Option<PilotPosition> {
+    pub fn get_position(&self, callsign: &str) -> Option<PilotPosition<'static>> {
         if let Some(pilot) = self.pilots.get(callsign) {
             return pilot.position.clone();
         }
         None
     }
 
-    pub fn get_config(&self, callsign: &String) -> Option<AircraftConfiguration> {
+    pub fn get_fast_position(&self, callsign: &str) -> Option<FastPilotPosition<'static>> {
+        if let Some(pilot) = self.pilots.get(callsign) {
+            return pilot.fast_position.clone();
+        }
+        None
+    }
+
+    pub fn get_config(&self, callsign: &str) -> Option<AircraftConfiguration> {
         if let Some(pilot) = self.pilots.get(callsign) {
             return pilot.config.clone();
         }
         None
     }
 
+    /// `None` until the pilot answered a `CAPS` query
+    pub fn get_capabilities(&self, callsign: &str) -> Option<ClientCapabilities> {
+        if let Some(pilot) = self.pilots.get(callsign) {
+            return pilot.capabilities.clone();
+        }
+        None
+    }
+
     pub fn number_tracked(&self) -> usize {
-        return self.pilots.len();
+        self.pilots.len()
     }
 
-    pub fn delete(&mut self, callsign: &String) {
+    pub fn delete(&mut self, callsign: &str) {
         self.pilots.remove(callsign);
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub struct ATC {
-    client: Option<NetworkClient>,
-    position: Option<ATCPosition>,
+    client: Option<NetworkClient<'static>>,
+    position: Option<ATCPosition<'static>>,
+    capabilities: Option<ClientCapabilities>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub struct ATCManager {
     atc: HashMap<String, ATC>,
 }
@@ -120,53 +157,69 @@ impl ATCManager {
         }
     }
 
-    pub fn process_client(&mut self, client: &NetworkClient) {
-        if let Some(data) = self.atc.get_mut(&client.callsign) {
-            data.client = Some(client.clone());
+    pub fn process_client(&mut self, client: &NetworkClient<'_>) {
+        if let Some(data) = self.atc.get_mut(client.callsign.as_ref()) {
+            data.client = Some(client.clone().into_owned());
         } else {
             self.atc.insert(
                 client.callsign.to_string(),
                 ATC {
-                    client: Some(client.clone()),
-                    position: None,
+                    client: Some(client.clone().into_owned()),
+                    ..Default::default()
                 },
             );
         }
     }
 
-    pub fn process_position(&mut self, position: &ATCPosition) {
-        if let Some(data) = self.atc.get_mut(&position.callsign) {
-            data.position = Some(position.clone());
+    pub fn process_position(&mut self, position: &ATCPosition<'_>) {
+        if let Some(data) = self.atc.get_mut(position.callsign.as_ref()) {
+            data.position = Some(position.clone().into_owned());
         } else {
             self.atc.insert(
                 position.callsign.to_string(),
                 ATC {
-                    client: None,
-                    position: Some(position.clone()),
+                    position: Some(position.clone().into_owned()),
+                    ..Default::default()
                 },
             );
         }
     }
 
-    pub fn get_client(&self, callsign: &String) -> Option<NetworkClient> {
+    /// Stores the flags of the controller's answer to `CAPS`
+    pub fn process_capabilities(&mut self, callsign: &str, capabilities: &ClientCapabilities) {
+        self.atc
+            .entry(callsign.to_string())
+            .or_default()
+            .capabilities = Some(capabilities.clone());
+    }
+
+    pub fn get_client(&self, callsign: &str) -> Option<NetworkClient<'static>> {
         if let Some(atc) = self.atc.get(callsign) {
             return atc.client.clone();
         }
         None
     }
 
-    pub fn get_position(&self, callsign: &String) -> Option<ATCPosition> {
+    pub fn get_position(&self, callsign: &str) -> Option<ATCPosition<'static>> {
         if let Some(atc) = self.atc.get(callsign) {
             return atc.position.clone();
         }
         None
     }
 
+    /// `None` until the controller answered a `CAPS` query
+    pub fn get_capabilities(&self, callsign: &str) -> Option<ClientCapabilities> {
+        if let Some(atc) = self.atc.get(callsign) {
+            return atc.capabilities.clone();
+        }
+        None
+    }
+
     pub fn number_tracked(&self) -> usize {
-        return self.atc.len();
+        self.atc.len()
     }
 
-    pub fn delete(&mut self, callsign: &String) {
+    pub fn delete(&mut self, callsign: &str) {
         self.atc.remove(callsign);
     }
 }
@@ -175,16 +228,19 @@ impl ATCManager {
 mod test {
     use super::*;
     use crate::fsdpackets::*;
+    use crate::parser::PacketTypes;
 
     macro_rules! get_test_pilot {
         () => {
             NetworkClient {
                 client_type: NetworkClientType::Pilot,
-                callsign: "DAL512".to_string(),
-                real_name: "Test".to_string(),
-                cid: "3210".to_string(),
-                password: "".to_string(),
+                callsign: "DAL512".into(),
+                to: "SERVER".into(),
+                real_name: "Test".into(),
+                cid: "3210".into(),
+                password: "".into(),
                 rating: NetworkRating::OBS,
+                simulator_type: None,
                 protocol_ver: 100,
             }
         };
@@ -195,11 +251,11 @@ mod test {
         let mut manager = PilotManager::new();
         let pilot = get_test_pilot!();
         manager.process_client(&pilot);
-        assert!(manager.pilots.len() > 0);
+        assert!(!manager.pilots.is_empty());
         let result = manager.get_client(&pilot.callsign).unwrap();
         assert_eq!(result.rating, NetworkRating::OBS);
 
-        assert_eq!(manager.get_client(&"www".to_string()), None);
+        assert_eq!(manager.get_client("www"), None);
     }
 
     #[test]
@@ -210,4 +266,57 @@ mod test {
         manager.delete(&pilot.callsign);
         assert_eq!(manager.get_client(&pilot.callsign), None);
     }
+
+    #[test]
+    fn test_pilot_fast_position() {
+        let mut manager = PilotManager::new();
+        let position = "@N:DAL512:1200:1:42.0:-71.0:3000:120:25170944:100";
+        match position.parse().unwrap() {
+            PacketTypes::PilotPosition(position) => manager.process_position(&position),
+            _ => panic!("Not the right packet type!"),
+        }
+        let fast = "^DAL512:42.5:-71.25:3050.4:3000:25170944:60:1.5:-20:0:0.01:0:0";
+        match fast.parse().unwrap() {
+            PacketTypes::FastPilotPosition(fast) => manager.process_fast_position(&fast),
+            _ => panic!("Not the right packet type!"),
+        }
+
+        let position = manager.get_position("DAL512").unwrap();
+        assert_eq!((position.lat, position.lon), (42.5, -71.25));
+        assert_eq!((position.true_alt, position.pressure_alt), (3050, 3150));
+        assert_eq!(position.squawk_code, 1200);
+        let fast = manager.get_fast_position("DAL512").unwrap();
+        assert_eq!(fast.positional_velocity.lon, 60.0);
+        assert_eq!(fast.rotational_velocity.heading, 0.01);
+    }
+
+    #[test]
+    fn test_capabilities() {
+        let capabilities = match "$CRDAL512:BOS_APP:CAPS:VERSION=1:ACCONFIG=1"
+            .parse()
+            .unwrap()
+        {
+            PacketTypes::ClientQuery(ClientQuery {
+                payload: ClientQueryPayload::CapabilitiesResponse(capabilities),
+                ..
+            }) => capabilities,
+            _ => panic!("Not the right packet type!"),
+        };
+        let mut pilots = PilotManager::new();
+        assert_eq!(pilots.get_capabilities("DAL512"), None);
+        pilots.process_client(&get_test_pilot!());
+        pilots.process_capabilities("DAL512", &capabilities);
+        let stored = pilots.get_capabilities("DAL512").unwrap();
+        assert!(stored.contains(Capability::AircraftConfig));
+        assert!(!stored.contains(Capability::FastPosition));
+        assert!(pilots.get_client("DAL512").is_some());
+
+        let mut atc = ATCManager::new();
+        atc.process_capabilities("BOS_APP", &ClientCapabilities::new());
+        assert_eq!(
+            atc.get_capabilities("BOS_APP"),
+            Some(ClientCapabilities::new())
+        );
+        assert_eq!(atc.get_client("BOS_APP"), None);
+    }
 }
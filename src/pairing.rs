@@ -0,0 +1,103 @@
+use crate::flows::{Direction, PacketSource};
+use crate::reassembly::Connection;
+use std::collections::{HashMap, VecDeque};
+
+// Unanswered requests kept per connection and direction, older ones are given up on
+const MAX_PENDING: usize = 16;
+
+/// The connection a packet was seen on, keyed from the client to the server
+pub(crate) fn client_to_server(packet: &PacketSource) -> Connection {
+    match packet.direction {
+        Direction::ClientToServer => packet.connection,
+        Direction::ServerToClient => packet.connection.reversed(),
+    }
+}
+
+/// Requests waiting for a response, like challenges or pings, per connection and the
+/// direction they went. Shared by the trackers that pair requests with their responses.
+#[derive(Debug)]
+pub(crate) struct PendingRequests<T> {
+    pending: HashMap<(Connection, Direction), VecDeque<T>>,
+}
+
+impl<T> Default for PendingRequests<T> {
+    fn default() -> Self {
+        PendingRequests {
+            pending: HashMap::new(),
+        }
+    }
+}
+
+impl<T> PendingRequests<T> {
+    /// Remembers the request carried by `packet`
+    pub(crate) fn request(&mut self, packet: &PacketSource, request: T) {
+        let pending = self
+            .pending
+            .entry((client_to_server(packet), packet.direction))
+            .or_default();
+        if pending.len() == MAX_PENDING {
+            pending.pop_front();
+        }
+        pending.push_back(request);
+    }
+
+    /// Takes the oldest request sent the other way that `answers` says `packet` responds to,
+    /// with the connection and the direction the request went
+    pub(crate) fn respond<F: FnMut(&T) -> bool>(
+        &mut self,
+        packet: &PacketSource,
+        answers: F,
+    ) -> Option<(Connection, Direction, T)> {
+        let connection = client_to_server(packet);
+        let direction = match packet.direction {
+            Direction::ClientToServer => Direction::ServerToClient,
+            Direction::ServerToClient => Direction::ClientToServer,
+        };
+        let pending = self.pending.get_mut(&(connection, direction))?;
+        let index = pending.iter().position(answers)?;
+        let request = pending.remove(index)?;
+        if pending.is_empty() {
+            self.pending.remove(&(connection, direction));
+        }
+        Some((connection, direction, request))
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.pending.values().map(VecDeque::len).sum()
+    }
+
+    /// Drops the requests of a connection, given either way round
+    pub(crate) fn forget(&mut self, connection: &Connection) {
+        let reversed = connection.reversed();
+        self.pending
+            .retain(|(pending, _), _| pending != connection && *pending != reversed);
+    }
+}
+
+/// Packets on one connection for the tests of the trackers
+#[cfg(test)]
+pub(crate) mod fixtures {
+    use super::*;
+    use std::time::{Duration, SystemTime};
+
+    pub(crate) fn client_to_server() -> Connection {
+        Connection::new(
+            "10.0.0.2:50000".parse().unwrap(),
+            "10.0.0.1:6809".parse().unwrap(),
+        )
+    }
+
+    pub(crate) fn packet(direction: Direction, millis: u64, line: &str) -> PacketSource {
+        let connection = match direction {
+            Direction::ClientToServer => client_to_server(),
+            Direction::ServerToClient => client_to_server().reversed(),
+        };
+        PacketSource {
+            timestamp: SystemTime::UNIX_EPOCH + Duration::from_millis(millis),
+            connection,
+            direction,
+            raw: line.to_string(),
+            packet: line.parse().unwrap(),
+        }
+    }
+}
@@ -1,40 +1,146 @@
+use crate::error::ParseError;
 use crate::fsdpackets::*;
+use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
 
 pub struct Parser {}
 
-#[derive(PartialEq, Debug)]
-pub enum PacketTypes {
-    TextMessage(TextMessage),
-    ATCPosition(ATCPosition),
-    PilotPosition(PilotPosition),
-    NetworkClient(NetworkClient),
-    DeleteClient(DeleteClient),
-    TransferControl(TransferControl),
-    SharedState(SharedState),
-    FlightStrip(FlightStrip),
-    FlightPlan(FlightPlan),
-    ClientQuery(ClientQuery),
-    ClientIdentification(ClientIdentification),
-    Metar(Metar),
-    PlaneInfoRequest(PlaneInfoRequest),
-    PlaneInfo(PlaneInfo),
+#[allow(clippy::large_enum_variant)]
+#[derive(PartialEq, Debug, Clone)]
+pub enum PacketTypes<'a> {
+    TextMessage(TextMessage<'a>),
+    ATCPosition(ATCPosition<'a>),
+    PilotPosition(PilotPosition<'a>),
+    NetworkClient(NetworkClient<'a>),
+    DeleteClient(DeleteClient<'a>),
+    TransferControl(TransferControl<'a>),
+    SharedState(SharedState<'a>),
+    FlightStrip(FlightStrip<'a>),
+    FlightPlan(FlightPlan<'a>),
+    ClientQuery(ClientQuery<'a>),
+    ClientIdentification(ClientIdentification<'a>),
+    Metar(Metar<'a>),
+    PlaneInfoRequest(PlaneInfoRequest<'a>),
+    PlaneInfo(PlaneInfo<'a>),
+    FastPilotPosition(FastPilotPosition<'a>),
+    SendFast(SendFast<'a>),
+    ServerError(ServerError<'a>),
+    KillRequest(KillRequest<'a>),
+    ServerIdentification(ServerIdentification<'a>),
+    AuthChallenge(AuthChallenge<'a>),
+    AuthResponse(AuthResponse<'a>),
+    Ping(Ping<'a>),
+    Heartbeat(Heartbeat<'a>),
+    WeatherRequest(WeatherRequest<'a>),
+    TemperatureData(TemperatureData<'a>),
+    WindData(WindData<'a>),
+    CloudData(CloudData<'a>),
+}
+
+impl<'a> PacketTypes<'a> {
+    pub fn into_owned(self) -> PacketTypes<'static> {
+        match self {
+            PacketTypes::TextMessage(packet) => PacketTypes::TextMessage(packet.into_owned()),
+            PacketTypes::ATCPosition(packet) => PacketTypes::ATCPosition(packet.into_owned()),
+            PacketTypes::PilotPosition(packet) => PacketTypes::PilotPosition(packet.into_owned()),
+            PacketTypes::NetworkClient(packet) => PacketTypes::NetworkClient(packet.into_owned()),
+            PacketTypes::DeleteClient(packet) => PacketTypes::DeleteClient(packet.into_owned()),
+            PacketTypes::TransferControl(packet) => {
+                PacketTypes::TransferControl(packet.into_owned())
+            }
+            PacketTypes::SharedState(packet) => PacketTypes::SharedState(packet.into_owned()),
+            PacketTypes::FlightStrip(packet) => PacketTypes::FlightStrip(packet.into_owned()),
+            PacketTypes::FlightPlan(packet) => PacketTypes::FlightPlan(packet.into_owned()),
+            PacketTypes::ClientQuery(packet) => PacketTypes::ClientQuery(packet.into_owned()),
+            PacketTypes::ClientIdentification(packet) => {
+                PacketTypes::ClientIdentification(packet.into_owned())
+            }
+            PacketTypes::Metar(packet) => PacketTypes::Metar(packet.into_owned()),
+            PacketTypes::PlaneInfoRequest(packet) => {
+                PacketTypes::PlaneInfoRequest(packet.into_owned())
+            }
+            PacketTypes::PlaneInfo(packet) => PacketTypes::PlaneInfo(packet.into_owned()),
+            PacketTypes::FastPilotPosition(packet) => {
+                PacketTypes::FastPilotPosition(packet.into_owned())
+            }
+            PacketTypes::SendFast(packet) => PacketTypes::SendFast(packet.into_owned()),
+            PacketTypes::ServerError(packet) => PacketTypes::ServerError(packet.into_owned()),
+            PacketTypes::KillRequest(packet) => PacketTypes::KillRequest(packet.into_owned()),
+            PacketTypes::ServerIdentification(packet) => {
+                PacketTypes::ServerIdentification(packet.into_owned())
+            }
+            PacketTypes::AuthChallenge(packet) => PacketTypes::AuthChallenge(packet.into_owned()),
+            PacketTypes::AuthResponse(packet) => PacketTypes::AuthResponse(packet.into_owned()),
+            PacketTypes::Ping(packet) => PacketTypes::Ping(packet.into_owned()),
+            PacketTypes::Heartbeat(packet) => PacketTypes::Heartbeat(packet.into_owned()),
+            PacketTypes::WeatherRequest(packet) => PacketTypes::WeatherRequest(packet.into_owned()),
+            PacketTypes::TemperatureData(packet) => {
+                PacketTypes::TemperatureData(packet.into_owned())
+            }
+            PacketTypes::WindData(packet) => PacketTypes::WindData(packet.into_owned()),
+            PacketTypes::CloudData(packet) => PacketTypes::CloudData(packet.into_owned()),
+        }
+    }
+
+    pub fn to_fsd_string(&self) -> String {
+        match self {
+            PacketTypes::TextMessage(packet) => packet.to_fsd_string(),
+            PacketTypes::ATCPosition(packet) => packet.to_fsd_string(),
+            PacketTypes::PilotPosition(packet) => packet.to_fsd_string(),
+            PacketTypes::NetworkClient(packet) => packet.to_fsd_string(),
+            PacketTypes::DeleteClient(packet) => packet.to_fsd_string(),
+            PacketTypes::TransferControl(packet) => packet.to_fsd_string(),
+            PacketTypes::SharedState(packet) => packet.to_fsd_string(),
+            PacketTypes::FlightStrip(packet) => packet.to_fsd_string(),
+            PacketTypes::FlightPlan(packet) => packet.to_fsd_string(),
+            PacketTypes::ClientQuery(packet) => packet.to_fsd_string(),
+            PacketTypes::ClientIdentification(packet) => packet.to_fsd_string(),
+            PacketTypes::Metar(packet) => packet.to_fsd_string(),
+            PacketTypes::PlaneInfoRequest(packet) => packet.to_fsd_string(),
+            PacketTypes::PlaneInfo(packet) => packet.to_fsd_string(),
+            PacketTypes::FastPilotPosition(packet) => packet.to_fsd_string(),
+            PacketTypes::SendFast(packet) => packet.to_fsd_string(),
+            PacketTypes::ServerError(packet) => packet.to_fsd_string(),
+            PacketTypes::KillRequest(packet) => packet.to_fsd_string(),
+            PacketTypes::ServerIdentification(packet) => packet.to_fsd_string(),
+            PacketTypes::AuthChallenge(packet) => packet.to_fsd_string(),
+            PacketTypes::AuthResponse(packet) => packet.to_fsd_string(),
+            PacketTypes::Ping(packet) => packet.to_fsd_string(),
+            PacketTypes::Heartbeat(packet) => packet.to_fsd_string(),
+            PacketTypes::WeatherRequest(packet) => packet.to_fsd_string(),
+            PacketTypes::TemperatureData(packet) => packet.to_fsd_string(),
+            PacketTypes::WindData(packet) => packet.to_fsd_string(),
+            PacketTypes::CloudData(packet) => packet.to_fsd_string(),
+        }
+    }
+}
+
+impl<'a> Display for PacketTypes<'a> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.to_fsd_string())
+    }
+}
+
+impl FromStr for PacketTypes<'static> {
+    type Err = ParseError;
+
+    fn from_str(data: &str) -> Result<Self, Self::Err> {
+        Parser::parse(data).map(PacketTypes::into_owned)
+    }
 }
 
 impl Parser {
     const DELIMETER: &'static str = ":";
 
-    pub fn parse(data: &str) -> Option<PacketTypes> {
-        let data = data.trim().to_string();
+    pub fn parse(data: &str) -> Result<PacketTypes<'_>, ParseError> {
+        let data = data.trim();
 
-        if data.len() == 0 || data.find(":") == None {
-            return None;
+        if data.len() < 3 || !data.contains(':') {
+            return Err(ParseError::UnknownCommand(data.to_string()));
         }
         // Make sure first few characters are alphanumeric
-        let mut chars = data.chars();
-        for _ in 0..3 {
-            if !chars.next().unwrap().is_ascii() {
-                return None;
-            }
+        if !data.bytes().take(3).all(|x| x.is_ascii()) {
+            return Err(ParseError::UnknownCommand(data.to_string()));
         }
 
         let command_prefix = &data[0..1];
@@ -42,241 +148,139 @@ impl Parser {
             "#" | "$" => {
                 let command = &data[1..3];
                 let fields: &Vec<&str> = &data[3..].split(Parser::DELIMETER).collect();
+                let unknown = || ParseError::UnknownCommand(data[0..3].to_string());
+                let sub_command = |index: usize| {
+                    fields
+                        .get(index)
+                        .copied()
+                        .ok_or(ParseError::MissingField(index))
+                };
                 match command {
-                    "AA" => Some(PacketTypes::NetworkClient(NetworkClient::new(
+                    "AA" => Ok(PacketTypes::NetworkClient(NetworkClient::new(
                         fields,
                         NetworkClientType::ATC,
-                    ))),
-                    "DA" => Some(PacketTypes::DeleteClient(DeleteClient::new(
+                    )?)),
+                    "DA" => Ok(PacketTypes::DeleteClient(DeleteClient::new(
                         fields,
                         NetworkClientType::ATC,
-                    ))),
-                    "AP" => Some(PacketTypes::NetworkClient(NetworkClient::new(
+                    )?)),
+                    "AP" => Ok(PacketTypes::NetworkClient(NetworkClient::new(
                         fields,
                         NetworkClientType::Pilot,
-                    ))),
-                    "DP" => Some(PacketTypes::DeleteClient(DeleteClient::new(
+                    )?)),
+                    "DP" => Ok(PacketTypes::DeleteClient(DeleteClient::new(
                         fields,
                         NetworkClientType::Pilot,
-                    ))),
+                    )?)),
 
-                    "TM" => Some(PacketTypes::TextMessage(TextMessage::from_string(fields))),
-                    "SB" => match fields[2] {
-                        "PIR" => Some(PacketTypes::PlaneInfoRequest(
-                            PlaneInfoRequest::from_string(fields),
+                    "TM" => Ok(PacketTypes::TextMessage(TextMessage::from_string(fields)?)),
+                    "SL" => Ok(PacketTypes::FastPilotPosition(FastPilotPosition::new(
+                        fields,
+                        FastPositionType::Slow,
+                    )?)),
+                    "ST" => Ok(PacketTypes::FastPilotPosition(FastPilotPosition::new(
+                        fields,
+                        FastPositionType::Stopped,
+                    )?)),
+                    "SF" => Ok(PacketTypes::SendFast(SendFast::from_string(fields)?)),
+                    "ER" => Ok(PacketTypes::ServerError(ServerError::from_string(fields)?)),
+                    "!!" => Ok(PacketTypes::KillRequest(KillRequest::from_string(fields)?)),
+                    "SB" => match sub_command(2)? {
+                        "PIR" => Ok(PacketTypes::PlaneInfoRequest(
+                            PlaneInfoRequest::from_string(fields)?,
                         )),
-                        "PI" => Some(PacketTypes::PlaneInfo(PlaneInfo::from_string(fields))),
-                        _ => None,
+                        "PI" => Ok(PacketTypes::PlaneInfo(PlaneInfo::from_string(fields)?)),
+                        _ => Err(unknown()),
                     },
-                    "PC" => match fields[3] {
-                        "HC" => Some(PacketTypes::TransferControl(TransferControl::new(
+                    "PC" => match sub_command(3)? {
+                        "HC" => Ok(PacketTypes::TransferControl(TransferControl::new(
                             fields,
                             TransferControlType::Cancelled,
-                        ))),
-                        "ST" => Some(PacketTypes::FlightStrip(FlightStrip::from_string(fields))),
-                        "DP" => Some(PacketTypes::TransferControl(TransferControl::new(
+                        )?)),
+                        "ST" => Ok(PacketTypes::FlightStrip(FlightStrip::from_string(fields)?)),
+                        "DP" => Ok(PacketTypes::TransferControl(TransferControl::new(
                             fields,
                             TransferControlType::PushToDepartures,
-                        ))),
-                        "PT" => Some(PacketTypes::TransferControl(TransferControl::new(
+                        )?)),
+                        "PT" => Ok(PacketTypes::TransferControl(TransferControl::new(
                             fields,
                             TransferControlType::Pointout,
-                        ))),
-                        "IH" => Some(PacketTypes::TransferControl(TransferControl::new(
+                        )?)),
+                        "IH" => Ok(PacketTypes::TransferControl(TransferControl::new(
                             fields,
                             TransferControlType::IHaveControl,
-                        ))),
-                        "SC" => Some(PacketTypes::SharedState(SharedState::new(
-                            fields,
-                            SharedStateType::Scratchpad,
-                        ))),
-                        "BC" => Some(PacketTypes::SharedState(SharedState::new(
-                            fields,
-                            SharedStateType::BeaconCode,
-                        ))),
-                        "VT" => Some(PacketTypes::SharedState(SharedState::new(
-                            fields,
-                            SharedStateType::VoiceType,
-                        ))),
-                        "TA" => Some(PacketTypes::SharedState(SharedState::new(
-                            fields,
-                            SharedStateType::TempAlt,
-                        ))),
-                        _ => None,
+                        )?)),
+                        // Shared states this crate does not know keep their command
+                        _ => Ok(PacketTypes::SharedState(SharedState::from_string(fields)?)),
                     },
-                    "HO" => Some(PacketTypes::TransferControl(TransferControl::new(
+                    "HO" => Ok(PacketTypes::TransferControl(TransferControl::new(
                         fields,
                         TransferControlType::Received,
-                    ))),
-                    "HA" => Some(PacketTypes::TransferControl(TransferControl::new(
+                    )?)),
+                    "HA" => Ok(PacketTypes::TransferControl(TransferControl::new(
                         fields,
                         TransferControlType::Accepted,
-                    ))),
-                    "FP" => Some(PacketTypes::FlightPlan(FlightPlan::from_string(fields))),
-                    "AM" => Some(PacketTypes::FlightPlan(FlightPlan::new(
+                    )?)),
+                    "FP" => Ok(PacketTypes::FlightPlan(FlightPlan::from_string(fields)?)),
+                    "AM" => Ok(PacketTypes::FlightPlan(FlightPlan::new(
                         fields,
-                        Some(fields[17]),
-                    ))),
-                    "AR" => Some(PacketTypes::Metar(Metar::new(fields, true))),
-                    "AX" => Some(PacketTypes::Metar(Metar::new(fields, false))),
-                    "ID" => Some(PacketTypes::ClientIdentification(
-                        ClientIdentification::from_string(fields),
+                        Some(sub_command(17)?),
+                    )?)),
+                    "AR" => Ok(PacketTypes::Metar(Metar::new(fields, true)?)),
+                    "AX" => Ok(PacketTypes::Metar(Metar::new(fields, false)?)),
+                    "ID" => Ok(PacketTypes::ClientIdentification(
+                        ClientIdentification::from_string(fields)?,
+                    )),
+                    "DI" => Ok(PacketTypes::ServerIdentification(
+                        ServerIdentification::from_string(fields)?,
                     )),
+                    "ZC" => Ok(PacketTypes::AuthChallenge(AuthChallenge::from_string(
+                        fields,
+                    )?)),
+                    "ZR" => Ok(PacketTypes::AuthResponse(AuthResponse::from_string(
+                        fields,
+                    )?)),
+                    "PI" => Ok(PacketTypes::Ping(Ping::new(fields, false)?)),
+                    "PO" => Ok(PacketTypes::Ping(Ping::new(fields, true)?)),
+                    "DL" => Ok(PacketTypes::Heartbeat(Heartbeat::from_string(fields)?)),
+                    "WX" => Ok(PacketTypes::WeatherRequest(WeatherRequest::from_string(
+                        fields,
+                    )?)),
+                    "TD" => Ok(PacketTypes::TemperatureData(TemperatureData::from_string(
+                        fields,
+                    )?)),
+                    "WD" => Ok(PacketTypes::WindData(WindData::from_string(fields)?)),
+                    "CD" => Ok(PacketTypes::CloudData(CloudData::from_string(fields)?)),
                     "CQ" | "CR" => {
                         let is_response = command == "CR";
-                        match fields[2] {
-                            "ATC" => Some(PacketTypes::ClientQuery(ClientQuery::new(
-                                fields,
-                                ClientQueryType::IsValidATC,
-                                is_response,
-                            ))),
-                            "CAPS" => Some(PacketTypes::ClientQuery(ClientQuery::new(
-                                fields,
-                                ClientQueryType::Capabilities,
-                                is_response,
-                            ))),
-                            "C?" => Some(PacketTypes::ClientQuery(ClientQuery::new(
-                                fields,
-                                ClientQueryType::COM1Freq,
-                                is_response,
-                            ))),
-                            "RN" => Some(PacketTypes::ClientQuery(ClientQuery::new(
-                                fields,
-                                ClientQueryType::RealName,
-                                is_response,
-                            ))),
-                            "SV" => Some(PacketTypes::ClientQuery(ClientQuery::new(
-                                fields,
-                                ClientQueryType::Server,
-                                is_response,
-                            ))),
-                            "ATIS" => Some(PacketTypes::ClientQuery(ClientQuery::new(
-                                fields,
-                                ClientQueryType::ATIS,
-                                is_response,
-                            ))),
-                            "IP" => Some(PacketTypes::ClientQuery(ClientQuery::new(
-                                fields,
-                                ClientQueryType::PublicIP,
-                                is_response,
-                            ))),
-                            "INF" => Some(PacketTypes::ClientQuery(ClientQuery::new(
-                                fields,
-                                ClientQueryType::INF,
-                                is_response,
-                            ))),
-                            "FP" => Some(PacketTypes::ClientQuery(ClientQuery::new(
-                                fields,
-                                ClientQueryType::FlightPlan,
-                                is_response,
-                            ))),
-                            "IPC" => Some(PacketTypes::ClientQuery(ClientQuery::new(
-                                fields,
-                                ClientQueryType::IPC,
-                                is_response,
-                            ))),
-                            "BY" => Some(PacketTypes::ClientQuery(ClientQuery::new(
-                                fields,
-                                ClientQueryType::RequestRelief,
-                                is_response,
-                            ))),
-                            "HI" => Some(PacketTypes::ClientQuery(ClientQuery::new(
-                                fields,
-                                ClientQueryType::CancelRequestRelief,
-                                is_response,
-                            ))),
-                            "HLP" => Some(PacketTypes::ClientQuery(ClientQuery::new(
-                                fields,
-                                ClientQueryType::RequestHelp,
-                                is_response,
-                            ))),
-                            "NOHLP" => Some(PacketTypes::ClientQuery(ClientQuery::new(
-                                fields,
-                                ClientQueryType::CancelRequestHelp,
-                                is_response,
-                            ))),
-                            "WH" => Some(PacketTypes::ClientQuery(ClientQuery::new(
-                                fields,
-                                ClientQueryType::WhoHas,
-                                is_response,
-                            ))),
-                            "IT" => Some(PacketTypes::ClientQuery(ClientQuery::new(
-                                fields,
-                                ClientQueryType::InitiateTrack,
-                                is_response,
-                            ))),
-                            "HT" => Some(PacketTypes::ClientQuery(ClientQuery::new(
-                                fields,
-                                ClientQueryType::AcceptHandoff,
-                                is_response,
-                            ))),
-                            "DR" => Some(PacketTypes::ClientQuery(ClientQuery::new(
-                                fields,
-                                ClientQueryType::DropTrack,
-                                is_response,
-                            ))),
-                            "FA" => Some(PacketTypes::ClientQuery(ClientQuery::new(
-                                fields,
-                                ClientQueryType::SetFinalAltitude,
-                                is_response,
-                            ))),
-                            "TA" => Some(PacketTypes::ClientQuery(ClientQuery::new(
-                                fields,
-                                ClientQueryType::SetTempAltitude,
-                                is_response,
-                            ))),
-                            "BC" => Some(PacketTypes::ClientQuery(ClientQuery::new(
-                                fields,
-                                ClientQueryType::SetBeaconCode,
-                                is_response,
-                            ))),
-                            "SC" => Some(PacketTypes::ClientQuery(ClientQuery::new(
-                                fields,
-                                ClientQueryType::SetScratchpad,
-                                is_response,
-                            ))),
-                            "VT" => Some(PacketTypes::ClientQuery(ClientQuery::new(
-                                fields,
-                                ClientQueryType::SetVoiceType,
-                                is_response,
-                            ))),
-                            "ACC" => Some(PacketTypes::ClientQuery(ClientQuery::new(
-                                fields,
-                                ClientQueryType::AircraftConfiguration,
-                                is_response,
-                            ))),
-                            "NEWINFO" => Some(PacketTypes::ClientQuery(ClientQuery::new(
-                                fields,
-                                ClientQueryType::NewInfo,
-                                is_response,
-                            ))),
-                            "NEWATIS" => Some(PacketTypes::ClientQuery(ClientQuery::new(
-                                fields,
-                                ClientQueryType::NewATIS,
-                                is_response,
-                            ))),
-                            _ => Some(PacketTypes::ClientQuery(ClientQuery::new(
-                                fields,
-                                ClientQueryType::Unknown,
-                                is_response,
-                            ))),
-                        }
+                        let query_type = ClientQueryType::from_command(sub_command(2)?);
+                        Ok(PacketTypes::ClientQuery(ClientQuery::new(
+                            fields,
+                            query_type,
+                            is_response,
+                        )?))
                     }
 
-                    _ => None,
+                    _ => Err(unknown()),
                 }
             }
             "%" => {
                 let fields: &Vec<&str> = &data[1..].split(Parser::DELIMETER).collect();
-                Some(PacketTypes::ATCPosition(ATCPosition::from_string(fields)))
+                Ok(PacketTypes::ATCPosition(ATCPosition::from_string(fields)?))
             }
             "@" => {
                 let fields: &Vec<&str> = &data[1..].split(Parser::DELIMETER).collect();
-                Some(PacketTypes::PilotPosition(PilotPosition::from_string(
+                Ok(PacketTypes::PilotPosition(PilotPosition::from_string(
                     fields,
-                )))
+                )?))
+            }
+            "^" => {
+                let fields: &Vec<&str> = &data[1..].split(Parser::DELIMETER).collect();
+                Ok(PacketTypes::FastPilotPosition(
+                    FastPilotPosition::from_string(fields)?,
+                ))
             }
-            _ => None,
+            _ => Err(ParseError::UnknownCommand(command_prefix.to_string())),
         }
     }
 }
@@ -284,10 +288,9 @@ impl Parser {
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::fsdpackets::*;
 
     macro_rules! test_message {
-        ($string: expr, $to_match:path) => {
+        ($string: expr, $to_match:pat) => {
             let tm = Parser::parse($string);
             match tm.unwrap() {
                 PacketTypes::TextMessage(message) => match message.receiver {
@@ -338,7 +341,7 @@ mod test {
 
     #[test]
     fn test_private_text_message() {
-        test_message!("#TMA:SWA283:", TextMessageReceiver::PrivateMessage);
+        test_message!("#TMA:SWA283:", TextMessageReceiver::PrivateMessage(_));
     }
 
     #[test]
@@ -378,6 +381,157 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_fast_pilot_position() {
+        match Parser::parse("^DAL1:42.3656120:-71.0096210:1200.50:1180.25:25170944:61.2500:-2.5000:-40.1250:0.0100:-0.0020:0.0000:12.50").unwrap() {
+            PacketTypes::FastPilotPosition(pos) => {
+                assert_eq!(pos.position_type, FastPositionType::Fast);
+                assert_eq!(pos.callsign, "DAL1");
+                assert_eq!(pos.lat, 42.365612);
+                assert_eq!(pos.lon, -71.009621);
+                assert_eq!(pos.true_alt, 1200.5);
+                assert_eq!(pos.agl_alt, 1180.25);
+                assert_eq!(pos.pbh.hdg.round(), 90.0);
+                assert_eq!(pos.positional_velocity.lon, 61.25);
+                assert_eq!(pos.positional_velocity.alt, -2.5);
+                assert_eq!(pos.positional_velocity.lat, -40.125);
+                assert_eq!(pos.rotational_velocity.pitch, 0.01);
+                assert_eq!(pos.rotational_velocity.heading, -0.002);
+                assert_eq!(pos.nose_gear_angle, Some(12.5));
+            }
+            _ => panic!("Not the right packet type!"),
+        }
+
+        match Parser::parse("#STDAL1:42.3656120:-71.0096210:20.00:0.00:25170944").unwrap() {
+            PacketTypes::FastPilotPosition(pos) => {
+                assert_eq!(pos.position_type, FastPositionType::Stopped);
+                assert_eq!(pos.positional_velocity, PositionalVelocity::default());
+                assert_eq!(pos.nose_gear_angle, None);
+            }
+            _ => panic!("Not the right packet type!"),
+        }
+
+        match Parser::parse("$SFSERVER:DAL1:1").unwrap() {
+            PacketTypes::SendFast(send) => assert!(send.enabled),
+            _ => panic!("Not the right packet type!"),
+        }
+    }
+
+    #[test]
+    fn test_server_error() {
+        match Parser::parse("$ERserver:N123:001:N123:Callsign in use").unwrap() {
+            PacketTypes::ServerError(error) => {
+                assert_eq!(error.from, "server");
+                assert_eq!(error.to, "N123");
+                assert_eq!(error.code, ServerErrorCode::CallsignInUse);
+                assert!(error.code.is_fatal());
+                assert_eq!(error.parameter, "N123");
+                assert_eq!(error.message, "Callsign in use");
+            }
+            _ => panic!("Not the right packet type!"),
+        }
+        match Parser::parse("$ERserver:N123:042::Something new: try later").unwrap() {
+            PacketTypes::ServerError(error) => {
+                assert_eq!(error.code, ServerErrorCode::Unknown(42));
+                assert_eq!(error.message, "Something new: try later");
+            }
+            _ => panic!("Not the right packet type!"),
+        }
+        match Parser::parse("$!!SERVER:N123:Disrupting the network").unwrap() {
+            PacketTypes::KillRequest(kill) => {
+                assert_eq!(kill.to, "N123");
+                assert_eq!(kill.reason, "Disrupting the network");
+            }
+            _ => panic!("Not the right packet type!"),
+        }
+    }
+
+    #[test]
+    fn test_ping() {
+        match Parser::parse("$POSERVER:N123:1700000000").unwrap() {
+            PacketTypes::Ping(pong) => {
+                assert!(pong.is_response);
+                assert_eq!(pong.from, "SERVER");
+                assert_eq!(pong.to, "N123");
+                assert_eq!(pong.token, "1700000000");
+            }
+            _ => panic!("Not the right packet type!"),
+        }
+        assert!(matches!(
+            Parser::parse("#DLSERVER:*:0:0"),
+            Ok(PacketTypes::Heartbeat(_))
+        ));
+    }
+
+    #[test]
+    fn test_weather() {
+        match Parser::parse("#TDserver:N123:100:15:10000:-5:18000:-21:35000:-54:2992").unwrap() {
+            PacketTypes::TemperatureData(data) => {
+                assert_eq!(data.to, "N123");
+                assert_eq!(data.layers.len(), 4);
+                assert_eq!(
+                    data.layers[1],
+                    TemperatureLayer {
+                        ceiling: 10000,
+                        temperature: -5
+                    }
+                );
+                assert_eq!(data.pressure, 2992);
+            }
+            _ => panic!("Not the right packet type!"),
+        }
+        match Parser::parse(
+            "#WDserver:N123:2500:0:270:12:1:3:10000:2500:280:25:0:0:18000:10000:290:40:0:0:35000:18000:300:85:0:10",
+        )
+        .unwrap()
+        {
+            PacketTypes::WindData(data) => {
+                assert_eq!(
+                    data.layers[0],
+                    WindLayer {
+                        ceiling: 2500,
+                        floor: 0,
+                        direction: 270,
+                        speed: 12,
+                        gusting: true,
+                        turbulence: 3
+                    }
+                );
+                assert_eq!(data.layers[1].direction, 280);
+            }
+            _ => panic!("Not the right packet type!"),
+        }
+        match Parser::parse("#CDserver:N123:3500:2500:4:0:2:12000:9000:6:1:10:0:0:0:0:0:10.5")
+            .unwrap()
+        {
+            PacketTypes::CloudData(data) => {
+                assert_eq!(data.layers.len(), 2);
+                assert_eq!(data.layers[1].coverage, 6);
+                assert!(data.layers[1].icing);
+                assert_eq!(data.thunderstorm.coverage, 0);
+                assert_eq!(data.visibility, 10.5);
+            }
+            _ => panic!("Not the right packet type!"),
+        }
+        assert_eq!(
+            Parser::parse("#WDserver:N123:2500:0:27O:12:1:3:10000:2500:280:25:0:0:18000:10000:290:40:0:0:35000:18000:300:85:0:10"),
+            Err(ParseError::InvalidNumber(4))
+        );
+        assert_eq!(
+            Parser::parse("#WDserver:N123:2500:0:270:12:1:3:10000:2500:280:25:0:0"),
+            Err(ParseError::MissingField(14))
+        );
+        // Truncated profiles are missing their last layers rather than having fewer
+        assert_eq!(
+            Parser::parse("#TDserver:N123:100:15:10000:-5:18000:-21:35000"),
+            Err(ParseError::MissingField(9))
+        );
+        assert_eq!(
+            Parser::parse("#CDserver:N123:3500:2500:4:0:2:12000:9000:6:1:10:10.5"),
+            Err(ParseError::MissingField(13))
+        );
+    }
+
     #[test]
     fn test_flight_plan() {
         match Parser::parse("$FPSWA1895:*A:I:B738/L:461:KBNA:1835:1835:35000:KRDU:1:14:3:4:KIAD:GFOSTER85PBN/A1B1C1D1S1S2NAV/RNVD1E2A1REG/N8310CEET/KZTL0012KZDC0044SEL/GPCSRMK/SIMBRIEFAIRAC/2009CHARTSONBOARD:TAZMO3BURMEVXVKPASSALDAN2").unwrap() {
@@ -409,7 +563,7 @@ mod test {
             PacketTypes::Metar(metar) => {
                 assert_eq!(metar.from, "BOS_GND");
                 assert_eq!(metar.to, "SERVER");
-                assert_eq!(metar.is_response, false);
+                assert!(!metar.is_response);
                 assert_eq!(metar.payload, "KBOS");
             }
             _ => panic!("Not the right packet type!"),
@@ -419,7 +573,7 @@ mod test {
             PacketTypes::Metar(metar) => {
                 assert_eq!(metar.to, "BOS_GND");
                 assert_eq!(metar.from, "SERVER");
-                assert_eq!(metar.is_response, true);
+                assert!(metar.is_response);
                 assert_eq!(metar.payload, "KBOS 180154Z 02011KT 10SM SCT060 OVC250 18/13 A3000 RMK AO2 SLP159 T01780128");
             },
             _ => panic!("Not the right packet type!")
@@ -446,19 +600,126 @@ mod test {
             PacketTypes::ClientQuery(cq) => {
                 assert_eq!(cq.from, "BOS_GND");
                 assert_eq!(cq.to, "@94835");
-                assert_eq!(cq.is_response, false);
-                match cq.payload {
-                    ClientQueryPayload::SetBeaconCode(callsign, code) => {
-                        assert_eq!(callsign, "DAL1");
-                        assert_eq!(code, "1001");
-                    }
-                    _ => (),
+                assert!(!cq.is_response);
+                if let ClientQueryPayload::SetBeaconCode(callsign, code) = cq.payload {
+                    assert_eq!(callsign, "DAL1");
+                    assert_eq!(code, "1001");
+                } else {
+                    panic!("Not the right payload type!");
                 }
             }
             _ => panic!("Not the right packet type!"),
         }
     }
 
+    #[test]
+    fn test_query_responses() {
+        let payload = |line| match Parser::parse(line).unwrap() {
+            PacketTypes::ClientQuery(cq) => cq.payload,
+            _ => panic!("Not the right packet type!"),
+        };
+        assert_eq!(
+            payload("$CQBOS_GND:DAL1:RN"),
+            ClientQueryPayload::RealNameQuery
+        );
+        assert_eq!(
+            payload("$CRDAL1:BOS_GND:RN:John Doe::1"),
+            ClientQueryPayload::RealName(RealNamePayload {
+                real_name: "John Doe".into(),
+                facility_name: "".into(),
+                rating: NetworkRating::OBS,
+            })
+        );
+        // A rating this crate does not know does not lose the rest of the answer
+        assert_eq!(
+            payload("$CRDAL1:BOS_GND:RN:John Doe::42"),
+            ClientQueryPayload::RealName(RealNamePayload {
+                real_name: "John Doe".into(),
+                facility_name: "".into(),
+                rating: NetworkRating::Undefined,
+            })
+        );
+        assert_eq!(
+            payload("$CRSERVER:BOS_GND:ATC:Y:BOS_TWR"),
+            ClientQueryPayload::IsValidATCResponse(true, Some("BOS_TWR".into()))
+        );
+        assert_eq!(
+            payload("$CRDAL1:BOS_GND:CAPS:VERSION=1:ATCINFO=1"),
+            ClientQueryPayload::CapabilitiesResponse(
+                [Capability::Version, Capability::ATCInfo]
+                    .iter()
+                    .copied()
+                    .collect()
+            )
+        );
+        assert_eq!(
+            payload("$CRDAL1:BOS_GND:INF:vPilot 2.4 CID=123:extra"),
+            ClientQueryPayload::INFResponse("vPilot 2.4 CID=123:extra".into())
+        );
+        assert_eq!(
+            payload("$CRBOS_TWR:DAL1:ATIS:T:Boston tower, runway 4R"),
+            ClientQueryPayload::ATISResponse(ATISLine::Text("Boston tower, runway 4R".into()))
+        );
+        assert_eq!(
+            payload("$CRBOS_TWR:DAL1:ATIS:E:4"),
+            ClientQueryPayload::ATISResponse(ATISLine::End(4))
+        );
+        assert_eq!(
+            payload("$CQBOS_GND:@94835:HT:DAL1:BOS_TWR"),
+            ClientQueryPayload::AcceptHandoff("DAL1".into(), "BOS_TWR".into())
+        );
+        assert_eq!(
+            payload("$CQBOS_GND:DAL1:C?"),
+            ClientQueryPayload::COM1FreqQuery
+        );
+        assert_eq!(
+            payload("$CQBOS_GND:SERVER:HLP"),
+            ClientQueryPayload::RequestHelp(None)
+        );
+        assert_eq!(
+            Parser::parse("$CRBOS_TWR:DAL1:ATIS:X:text"),
+            Err(ParseError::InvalidEnumValue(3))
+        );
+        assert_eq!(
+            Parser::parse("$CQBOS_GND:@94835:HT:DAL1"),
+            Err(ParseError::MissingField(4))
+        );
+    }
+
+    #[test]
+    fn test_capabilities() {
+        let capabilities =
+            match Parser::parse("$CRDAL1:BOS_GND:CAPS:VERSION=1:ACCONFIG=1:FASTPOS=0:NEWFLAG=1")
+                .unwrap()
+            {
+                PacketTypes::ClientQuery(ClientQuery {
+                    payload: ClientQueryPayload::CapabilitiesResponse(capabilities),
+                    ..
+                }) => capabilities,
+                _ => panic!("Not the right packet type!"),
+            };
+        assert!(capabilities.contains(Capability::AircraftConfig));
+        assert!(!capabilities.contains(Capability::FastPosition));
+        assert_eq!(
+            capabilities.iter().collect::<Vec<_>>(),
+            vec![Capability::Version, Capability::AircraftConfig]
+        );
+        assert_eq!(capabilities.unknown(), ["NEWFLAG=1".to_string()]);
+
+        let mut capabilities = ClientCapabilities::new();
+        capabilities.insert(Capability::FastPosition);
+        capabilities.insert(Capability::Version);
+        capabilities.remove(Capability::FastPosition);
+        let query = ClientQuery {
+            is_response: true,
+            from: "DAL1".into(),
+            to: "BOS_GND".into(),
+            query_type: ClientQueryType::Capabilities,
+            payload: ClientQueryPayload::CapabilitiesResponse(capabilities),
+        };
+        assert_eq!(query.to_fsd_string(), "$CRDAL1:BOS_GND:CAPS:VERSION=1");
+    }
+
     #[test]
     fn test_plane_info() {
         match Parser::parse("#SBDW033:TOWER:PI:GEN:EQUIPMENT=CONC:AIRLINE=BA:LIVERY=swift_a10743")
@@ -486,4 +747,214 @@ mod test {
             _ => panic!("Not the right packet type!"),
         }
     }
+
+    #[test]
+    fn test_malformed_lines() {
+        assert_eq!(
+            Parser::parse("@S:N513PW:4717:1:41.93848"),
+            Err(ParseError::MissingField(6))
+        );
+        assert_eq!(
+            Parser::parse("$FPSWA1895:*A:I:B738/L"),
+            Err(ParseError::MissingField(4))
+        );
+        assert_eq!(
+            Parser::parse("#XXA:B"),
+            Err(ParseError::UnknownCommand("#XX".to_string()))
+        );
+        assert_eq!(
+            Parser::parse("$CQA:B:ACC:{\"config\":"),
+            Err(ParseError::InvalidJson(
+                "EOF while parsing a value at line 1 column 10".to_string()
+            ))
+        );
+        assert!(Parser::parse("").is_err());
+        assert!(Parser::parse("a:").is_err());
+    }
+
+    macro_rules! round_trip {
+        ($string: expr) => {
+            let packet = Parser::parse($string).unwrap();
+            let encoded = packet.to_string();
+            assert_eq!(Parser::parse(&encoded), Ok(packet), "Encoded: {}", encoded);
+        };
+    }
+
+    #[test]
+    fn test_round_trip_text_message() {
+        round_trip!("#TMNY_CAM_APP:@28120:EK188,turnrightheading310");
+        round_trip!("#TMA:@49999:hello");
+        round_trip!("#TMA:*:hello");
+        round_trip!("#TMA:*S:hello");
+        round_trip!("#TMA:SWA283:see you at 12:30");
+    }
+
+    #[test]
+    fn test_round_trip_positions() {
+        round_trip!("%BOS_APP:33000:5:150:5:42.35745:-70.98955:0");
+        round_trip!("@S:N513PW:4717:1:41.93848:-72.69294:174:0:4282386784:61");
+        round_trip!("@N:DAL1:0200:1:-33.5:151.25:35000:450:25170944:-120");
+        round_trip!("^DAL1:42.365612:-71.009621:1200.5:1180.25:25170944:61.25:-2.5:-40.125:0.01:-0.002:0:12.5");
+        round_trip!(
+            "#SLDAL1:42.365612:-71.009621:1200.5:1180.25:25170944:61.25:-2.5:-40.125:0:0:0"
+        );
+        round_trip!("#STDAL1:42.365612:-71.009621:20:0:25170944:0");
+        round_trip!("$SFSERVER:DAL1:0");
+    }
+
+    #[test]
+    fn test_round_trip_clients() {
+        round_trip!("#AABOS_APP:SERVER:John Doe:1234567:pass:5:100");
+        round_trip!("#APDAL1:SERVER:1234567:pass:1:100:6:John Doe");
+        round_trip!("#DABOS_APP:1234567");
+        round_trip!("#DPDAL1:1234567");
+        round_trip!("$IDDAL1:SERVER:de1e:vPilot:3:8:1234567:123456789:a1b2c3");
+        round_trip!("$DISERVER:CLIENT:VATSIM FSD V3.13:0123456789abcdef");
+        round_trip!("$ZCSERVER:DAL1:5c3e2f10a9b8");
+        round_trip!("$ZRDAL1:SERVER:3a4e5d6c7b8a");
+    }
+
+    #[test]
+    fn test_round_trip_flight_plan() {
+        round_trip!("$FPSWA1895:*A:I:B738/L:461:KBNA:1835:1835:35000:KRDU:1:14:3:4:KIAD:GFOSTER85PBN/A1B1C1D1S1S2NAV/RNVD1E2A1REG/N8310CEET/KZTL0012KZDC0044SEL/GPCSRMK/SIMBRIEFAIRAC/2009CHARTSONBOARD:TAZMO3BURMEVXVKPASSALDAN2");
+        round_trip!(
+            "$AMSWA1895:*A:V:C172:100:KBOS:1835:1835:3500:KBED:0:30:2:0::/V/:DIRECT:BOS_DEL"
+        );
+    }
+
+    #[test]
+    fn test_round_trip_controller_coordination() {
+        round_trip!("#PCBOS_GND:BOS_TWR:CCP:ST:DAL2463:1:A::B::G:C:::");
+        round_trip!("#PCBOS_GND:BOS_TWR:CCP:HC:DAL2463");
+        round_trip!("#PCBOS_GND:BOS_TWR:CCP:IH:DAL2463");
+        round_trip!("#PCBOS_GND:BOS_TWR:CCP:SC:DAL2463:RWY4R");
+        round_trip!("#PCBOS_GND:BOS_TWR:CCP:TA:DAL2463:3000");
+
+        // Shared states this crate does not know keep their command
+        round_trip!("#PCBOS_GND:BOS_TWR:CCP:XX:DAL2463:1");
+        match Parser::parse("#PCBOS_GND:BOS_TWR:CCP:XX:DAL2463:1").unwrap() {
+            PacketTypes::SharedState(state) => {
+                assert_eq!(state.shared_type, SharedStateType::Unknown("XX".into()));
+            }
+            _ => panic!("Not the right packet type!"),
+        }
+        round_trip!("$HOBOS_GND:BOS_TWR:DAL2463");
+        round_trip!("$HABOS_TWR:BOS_GND:DAL2463");
+    }
+
+    #[test]
+    fn test_encode_keeps_fields() {
+        // Encoded exactly as received, receivers and the low bits of the pbh included
+        for line in [
+            "#AABOS_APP:ZNY:John Doe:1234567:pass:5:100",
+            "#APDAL1:ZNY:1234567:pass:1:100:6:John Doe",
+            "$IDDAL1:ZNY:de1e:vPilot:3:8:1234567:123456789:a1b2c3",
+            "$FPSWA1895:BOS_GND:I:B738/L:461:KBNA:1835:1835:35000:KRDU:1:14:3:4:KIAD:RMK:TAZMO3",
+            "$AMBOS_GND:*A:I:B738/L:461:KBNA:1835:1835:35000:KRDU:1:14:3:4:KIAD:RMK:TAZMO3:SWA1",
+            "@N:DAL1:0200:1:-33.5:151.25:35000:450:25170946:-120",
+        ]
+        .iter()
+        {
+            assert_eq!(Parser::parse(line).unwrap().to_string(), *line);
+        }
+        assert_eq!(
+            Parser::parse("#AABOS_APP:SERVER:John Doe:1234567:pass:5:x"),
+            Err(ParseError::InvalidNumber(6))
+        );
+    }
+
+    #[test]
+    fn test_round_trip_queries() {
+        // Queries this crate does not know are passed on as they came
+        let unknown = "$CQA:B:FOO:x:y";
+        match Parser::parse(unknown).unwrap() {
+            PacketTypes::ClientQuery(query) => {
+                assert_eq!(query.query_type, ClientQueryType::Unknown("FOO".into()));
+                assert_eq!(query.to_fsd_string(), unknown);
+            }
+            _ => panic!("Not the right packet type!"),
+        }
+        round_trip!("$CQBOS_GND:@94835:BC:DAL1:1001");
+        round_trip!("$CQBOS_GND:DAL1:FP:DAL1");
+        round_trip!("$CQBOS_GND:SERVER:ATC:BOS_TWR");
+        round_trip!("$CQBOS_GND:@94835:WH:DAL1");
+        round_trip!("$CQDAL1:@94835:ACC:{\"config\":{\"gear_down\":true}}");
+        round_trip!("$CQBOS_GND:DAL1:RN");
+        round_trip!("$CRDAL1:BOS_GND:RN:John Doe:BOS:3");
+        round_trip!("$CRSERVER:BOS_GND:ATC:N:BOS_TWR");
+        round_trip!("$CRDAL1:BOS_GND:CAPS:VERSION=1:ATCINFO=1");
+        round_trip!("$CRDAL1:BOS_GND:CAPS:VERSION=1:ATCINFO=1:MODELDESC=1:ACCONFIG=1:VISUPDATE=1");
+        round_trip!("$CRDAL1:BOS_GND:INF:vPilot 2.4 CID=123");
+        round_trip!("$CRBOS_TWR:DAL1:ATIS:V:voice.example.com/bos_twr");
+        round_trip!("$CRBOS_TWR:DAL1:ATIS:E:4");
+        round_trip!("$CRDAL1:BOS_GND:C?:122.800");
+        round_trip!("$CQBOS_GND:@94835:HT:DAL1:BOS_TWR");
+        round_trip!("$CQBOS_GND:SERVER:HLP:Lost comms");
+        round_trip!("$CQBOS_GND:DAL1:IPC:W:852:20");
+        round_trip!("$CQBOS_GND:@94835:NEWATIS:ATIS B: 4R 4L");
+        round_trip!("$AXBOS_GND:SERVER:METAR:KBOS");
+        round_trip!("$ARSERVER:BOS_GND:METAR:KBOS 180154Z 02011KT 10SM");
+        round_trip!("#SBBOS_GND:DAL1:PIR");
+        round_trip!("#SBDW033:TOWER:PI:GEN:EQUIPMENT=CONC:AIRLINE=BA:LIVERY=swift_a10743");
+        round_trip!("#SBDW033:TOWER:PI:X:0:1:B738");
+    }
+
+    #[test]
+    fn test_round_trip_errors() {
+        round_trip!("$ERserver:N123:015::Invalid position for rating");
+        round_trip!("$ERserver:N123:007:DAL1:No such callsign");
+        round_trip!("$!!SUP:N123:Kicked");
+        round_trip!("$!!SERVER:N123");
+    }
+
+    #[test]
+    fn test_round_trip_keepalive() {
+        round_trip!("$PIN123:SERVER:1700000000");
+        round_trip!("$PON123:SERVER:1700000000");
+        round_trip!("#DLSERVER:*:0:0");
+        round_trip!("#DLSERVER:*");
+    }
+
+    #[test]
+    fn test_round_trip_weather() {
+        round_trip!("#WXN123:SERVER:KBOS");
+        round_trip!("#TDserver:N123:100:15:10000:-5:18000:-21:35000:-54:2992");
+        round_trip!("#WDserver:N123:2500:0:270:12:1:3:10000:2500:280:25:0:0:18000:10000:290:40:0:0:35000:18000:300:85:0:10");
+        round_trip!("#CDserver:N123:3500:2500:4:0:2:12000:9000:6:1:10:0:0:0:0:0:10.5");
+    }
+
+    #[test]
+    fn test_encode() {
+        let line = "@S:N513PW:4717:1:41.93848:-72.69294:174:0:4282386784:61";
+        let packet: PacketTypes = line.parse().unwrap();
+        assert_eq!(
+            packet.to_string(),
+            "@S:N513PW:4717:1:41.93848:-72.69294:174:0:4282386784:61"
+        );
+        assert_eq!(
+            Parser::parse("%BOS_APP:33000:5:150:5:42.35745:-70.98955:0")
+                .unwrap()
+                .to_string(),
+            "%BOS_APP:33000:5:150:5:42.35745:-70.98955:0"
+        );
+    }
+
+    #[test]
+    fn test_borrowed_fields() {
+        let line = "@S:N513PW:4717:1:41.93848:-72.69294:174:0:4282386784:61".to_string();
+        let packet = Parser::parse(&line).unwrap();
+        match &packet {
+            PacketTypes::PilotPosition(pos) => {
+                assert!(matches!(pos.callsign, std::borrow::Cow::Borrowed(_)))
+            }
+            _ => panic!("Not the right packet type!"),
+        }
+
+        let owned = packet.clone().into_owned();
+        drop(line);
+        assert_eq!(
+            owned.to_string(),
+            "@S:N513PW:4717:1:41.93848:-72.69294:174:0:4282386784:61"
+        );
+    }
 }
@@ -0,0 +1,567 @@
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Error, ErrorKind, Read, Result, Write};
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+pub const LINKTYPE_ETHERNET: u32 = 1;
+pub const LINKTYPE_LINUX_SLL: u32 = 113;
+
+const PCAP_MAGIC_MICROS: u32 = 0xa1b2_c3d4;
+const PCAP_MAGIC_NANOS: u32 = 0xa1b2_3c4d;
+const PCAPNG_SECTION_HEADER: u32 = 0x0a0d_0d0a;
+const PCAPNG_BYTE_ORDER_MAGIC: u32 = 0x1a2b_3c4d;
+const PCAPNG_INTERFACE_DESCRIPTION: u32 = 1;
+const PCAPNG_OBSOLETE_PACKET: u32 = 2;
+const PCAPNG_SIMPLE_PACKET: u32 = 3;
+const PCAPNG_ENHANCED_PACKET: u32 = 6;
+const PCAPNG_OPTION_END: u16 = 0;
+const PCAPNG_OPTION_TIMESTAMP_RESOLUTION: u16 = 9;
+
+// Anything larger is a corrupt length field rather than a real frame
+const MAX_RECORD_LENGTH: usize = 16 * 1024 * 1024;
+
+/// One captured frame as stored in a capture file
+#[derive(Debug, Clone, PartialEq)]
+pub struct PcapFrame<'a> {
+    pub timestamp: SystemTime,
+    pub link_type: u32,
+    pub data: &'a [u8],
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Resolution {
+    Decimal(u8),
+    Binary(u8),
+}
+
+impl Resolution {
+    fn to_time(self, ticks: u64) -> SystemTime {
+        let per_second: u128 = match self {
+            Resolution::Decimal(exponent) => 10u128.pow(exponent.min(19) as u32),
+            Resolution::Binary(exponent) => 1u128 << exponent.min(63),
+        };
+        let ticks = ticks as u128;
+        let nanos = (ticks % per_second) * 1_000_000_000 / per_second;
+        UNIX_EPOCH + Duration::new((ticks / per_second) as u64, nanos as u32)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Interface {
+    link_type: u32,
+    snap_length: u32,
+    resolution: Resolution,
+}
+
+enum Format {
+    Pcap {
+        link_type: u32,
+        resolution: Resolution,
+    },
+    PcapNg {
+        interfaces: Vec<Interface>,
+    },
+}
+
+/// Reads frames from classic pcap (microsecond and nanosecond) and pcapng files
+pub struct PcapReader<R: Read> {
+    reader: R,
+    format: Format,
+    big_endian: bool,
+    buffer: Vec<u8>,
+}
+
+impl PcapReader<BufReader<File>> {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::new(BufReader::new(File::open(path)?))
+    }
+}
+
+impl<R: Read> PcapReader<R> {
+    pub fn new(mut reader: R) -> Result<Self> {
+        let mut magic = [0; 4];
+        reader.read_exact(&mut magic)?;
+
+        let mut pcap = PcapReader {
+            reader,
+            format: Format::PcapNg {
+                interfaces: Vec::new(),
+            },
+            big_endian: false,
+            buffer: Vec::new(),
+        };
+
+        if u32::from_le_bytes(magic) == PCAPNG_SECTION_HEADER {
+            pcap.read_section_header()?;
+            return Ok(pcap);
+        }
+
+        let resolution = match (u32::from_le_bytes(magic), u32::from_be_bytes(magic)) {
+            (PCAP_MAGIC_MICROS, _) => Resolution::Decimal(6),
+            (PCAP_MAGIC_NANOS, _) => Resolution::Decimal(9),
+            (_, PCAP_MAGIC_MICROS) => {
+                pcap.big_endian = true;
+                Resolution::Decimal(6)
+            }
+            (_, PCAP_MAGIC_NANOS) => {
+                pcap.big_endian = true;
+                Resolution::Decimal(9)
+            }
+            _ => return Err(invalid("Not a pcap or pcapng file")),
+        };
+
+        // Version, timezone, sigfigs and snaplen are not needed to read the frames
+        let mut header = [0; 20];
+        pcap.reader.read_exact(&mut header)?;
+        pcap.format = Format::Pcap {
+            link_type: pcap.u32_at(&header, 16),
+            resolution,
+        };
+
+        Ok(pcap)
+    }
+
+    /// Returns the next frame, or `None` once the end of the file is reached
+    pub fn next_frame(&mut self) -> Result<Option<PcapFrame<'_>>> {
+        match self.format {
+            Format::Pcap {
+                link_type,
+                resolution,
+            } => self.next_pcap_frame(link_type, resolution),
+            Format::PcapNg { .. } => self.next_pcapng_frame(),
+        }
+    }
+
+    fn next_pcap_frame(
+        &mut self,
+        link_type: u32,
+        resolution: Resolution,
+    ) -> Result<Option<PcapFrame<'_>>> {
+        let mut header = [0; 16];
+        if !self.read_or_eof(&mut header)? {
+            return Ok(None);
+        }
+
+        let seconds = self.u32_at(&header, 0) as u64;
+        let fraction = self.u32_at(&header, 4) as u64;
+        let captured_length = self.u32_at(&header, 8) as usize;
+        let per_second = match resolution {
+            Resolution::Decimal(9) => 1_000_000_000,
+            _ => 1_000_000,
+        };
+
+        self.fill_buffer(captured_length)?;
+        Ok(Some(PcapFrame {
+            timestamp: resolution.to_time(seconds * per_second + fraction),
+            link_type,
+            data: &self.buffer,
+        }))
+    }
+
+    fn next_pcapng_frame(&mut self) -> Result<Option<PcapFrame<'_>>> {
+        loop {
+            let mut header = [0; 8];
+            if !self.read_or_eof(&mut header)? {
+                return Ok(None);
+            }
+
+            let block_type = self.u32_at(&header, 0);
+            if block_type == PCAPNG_SECTION_HEADER {
+                self.read_section_header_body(&header)?;
+                continue;
+            }
+
+            let total_length = self.u32_at(&header, 4) as usize;
+            if total_length < 12 || total_length % 4 != 0 {
+                return Err(invalid("Invalid pcapng block length"));
+            }
+            // The body followed by the repeated total length
+            self.fill_buffer(total_length - 8)?;
+            let body_length = total_length - 12;
+
+            match block_type {
+                PCAPNG_INTERFACE_DESCRIPTION => self.read_interface_description(body_length)?,
+                PCAPNG_ENHANCED_PACKET | PCAPNG_OBSOLETE_PACKET => {
+                    if body_length < 20 {
+                        return Err(invalid("Truncated pcapng packet block"));
+                    }
+                    let interface_id = match block_type {
+                        PCAPNG_ENHANCED_PACKET => self.u32_at(&self.buffer, 0) as usize,
+                        _ => self.u16_at(&self.buffer, 0) as usize,
+                    };
+                    let ticks = (self.u32_at(&self.buffer, 4) as u64) << 32
+                        | self.u32_at(&self.buffer, 8) as u64;
+                    let captured_length = self.u32_at(&self.buffer, 12) as usize;
+                    if 20 + captured_length > body_length {
+                        return Err(invalid("Truncated pcapng packet block"));
+                    }
+
+                    let interface = self.interface(interface_id)?;
+                    return Ok(Some(PcapFrame {
+                        timestamp: interface.resolution.to_time(ticks),
+                        link_type: interface.link_type,
+                        data: &self.buffer[20..20 + captured_length],
+                    }));
+                }
+                PCAPNG_SIMPLE_PACKET => {
+                    if body_length < 4 {
+                        return Err(invalid("Truncated pcapng packet block"));
+                    }
+                    let interface = self.interface(0)?;
+                    let original_length = self.u32_at(&self.buffer, 0) as usize;
+                    let mut captured_length = original_length.min(body_length - 4);
+                    if interface.snap_length != 0 {
+                        captured_length = captured_length.min(interface.snap_length as usize);
+                    }
+
+                    // Simple packet blocks carry no timestamp
+                    return Ok(Some(PcapFrame {
+                        timestamp: UNIX_EPOCH,
+                        link_type: interface.link_type,
+                        data: &self.buffer[4..4 + captured_length],
+                    }));
+                }
+                // Statistics, name resolution and custom blocks
+                _ => (),
+            }
+        }
+    }
+
+    fn read_section_header(&mut self) -> Result<()> {
+        let mut header = [0; 8];
+        header[..4].copy_from_slice(&PCAPNG_SECTION_HEADER.to_le_bytes());
+        self.reader.read_exact(&mut header[4..])?;
+        self.read_section_header_body(&header)
+    }
+
+    fn read_section_header_body(&mut self, header: &[u8; 8]) -> Result<()> {
+        let mut byte_order = [0; 4];
+        self.reader.read_exact(&mut byte_order)?;
+        self.big_endian = match u32::from_le_bytes(byte_order) {
+            PCAPNG_BYTE_ORDER_MAGIC => false,
+            _ if u32::from_be_bytes(byte_order) == PCAPNG_BYTE_ORDER_MAGIC => true,
+            _ => return Err(invalid("Invalid pcapng byte order magic")),
+        };
+
+        let total_length = self.u32_at(header, 4) as usize;
+        if total_length < 28 || total_length % 4 != 0 {
+            return Err(invalid("Invalid pcapng block length"));
+        }
+        // Interface ids restart with every section
+        self.format = Format::PcapNg {
+            interfaces: Vec::new(),
+        };
+        self.fill_buffer(total_length - 12)
+    }
+
+    fn read_interface_description(&mut self, body_length: usize) -> Result<()> {
+        if body_length < 8 {
+            return Err(invalid("Truncated pcapng interface block"));
+        }
+
+        let mut interface = Interface {
+            link_type: self.u16_at(&self.buffer, 0) as u32,
+            snap_length: self.u32_at(&self.buffer, 4),
+            resolution: Resolution::Decimal(6),
+        };
+
+        let mut offset = 8;
+        while offset + 4 <= body_length {
+            let code = self.u16_at(&self.buffer, offset);
+            let length = self.u16_at(&self.buffer, offset + 2) as usize;
+            if code == PCAPNG_OPTION_END || offset + 4 + length > body_length {
+                break;
+            }
+            if code == PCAPNG_OPTION_TIMESTAMP_RESOLUTION && length >= 1 {
+                let value = self.buffer[offset + 4];
+                interface.resolution = match value & 0x80 {
+                    0 => Resolution::Decimal(value),
+                    _ => Resolution::Binary(value & 0x7f),
+                };
+            }
+            // Option values are padded to 32 bits
+            offset += 4 + length.div_ceil(4) * 4;
+        }
+
+        if let Format::PcapNg { interfaces } = &mut self.format {
+            interfaces.push(interface);
+        }
+        Ok(())
+    }
+
+    fn interface(&self, id: usize) -> Result<Interface> {
+        match &self.format {
+            Format::PcapNg { interfaces } => interfaces.get(id).copied(),
+            Format::Pcap { .. } => None,
+        }
+        .ok_or_else(|| invalid("Packet block references an unknown interface"))
+    }
+
+    fn fill_buffer(&mut self, length: usize) -> Result<()> {
+        if length > MAX_RECORD_LENGTH {
+            return Err(invalid("Record is too large"));
+        }
+        self.buffer.resize(length, 0);
+        self.reader.read_exact(&mut self.buffer)
+    }
+
+    // Reads a whole record header, a clean end of file before it returns false
+    fn read_or_eof(&mut self, buffer: &mut [u8]) -> Result<bool> {
+        let mut read = 0;
+        while read < buffer.len() {
+            match self.reader.read(&mut buffer[read..]) {
+                Ok(0) if read == 0 => return Ok(false),
+                Ok(0) => return Err(ErrorKind::UnexpectedEof.into()),
+                Ok(n) => read += n,
+                Err(error) if error.kind() == ErrorKind::Interrupted => (),
+                Err(error) => return Err(error),
+            }
+        }
+        Ok(true)
+    }
+
+    fn u16_at(&self, bytes: &[u8], offset: usize) -> u16 {
+        let bytes = [bytes[offset], bytes[offset + 1]];
+        match self.big_endian {
+            true => u16::from_be_bytes(bytes),
+            false => u16::from_le_bytes(bytes),
+        }
+    }
+
+    fn u32_at(&self, bytes: &[u8], offset: usize) -> u32 {
+        let bytes = [
+            bytes[offset],
+            bytes[offset + 1],
+            bytes[offset + 2],
+            bytes[offset + 3],
+        ];
+        match self.big_endian {
+            true => u32::from_be_bytes(bytes),
+            false => u32::from_le_bytes(bytes),
+        }
+    }
+}
+
+/// Writes frames as a classic pcap file with nanosecond timestamps.
+///
+/// A pcap file has a single link type, it is taken from the first frame written and the
+/// file header is only written at that point. `finish` writes an Ethernet header for a file
+/// that got no frames, so it can still be opened.
+pub struct PcapWriter<W: Write> {
+    writer: W,
+    link_type: Option<u32>,
+}
+
+impl PcapWriter<BufWriter<File>> {
+    pub fn create<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Ok(Self::new(BufWriter::new(File::create(path)?)))
+    }
+}
+
+impl<W: Write> PcapWriter<W> {
+    pub fn new(writer: W) -> Self {
+        PcapWriter {
+            writer,
+            link_type: None,
+        }
+    }
+
+    pub fn write_frame(&mut self, frame: &PcapFrame) -> Result<()> {
+        match self.link_type {
+            Some(link_type) if link_type != frame.link_type => {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    "Frame link type differs from the rest of the file",
+                ));
+            }
+            Some(_) => (),
+            None => self.write_header(frame.link_type)?,
+        }
+
+        let since_epoch = frame
+            .timestamp
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let length = frame.data.len() as u32;
+        let mut header = Vec::with_capacity(16);
+        header.extend_from_slice(&(since_epoch.as_secs() as u32).to_le_bytes());
+        header.extend_from_slice(&since_epoch.subsec_nanos().to_le_bytes());
+        header.extend_from_slice(&length.to_le_bytes());
+        header.extend_from_slice(&length.to_le_bytes());
+        self.writer.write_all(&header)?;
+        self.writer.write_all(frame.data)
+    }
+
+    fn write_header(&mut self, link_type: u32) -> Result<()> {
+        let mut header = Vec::with_capacity(24);
+        header.extend_from_slice(&PCAP_MAGIC_NANOS.to_le_bytes());
+        header.extend_from_slice(&2u16.to_le_bytes());
+        header.extend_from_slice(&4u16.to_le_bytes());
+        header.extend_from_slice(&[0; 8]);
+        header.extend_from_slice(&(MAX_RECORD_LENGTH as u32).to_le_bytes());
+        header.extend_from_slice(&link_type.to_le_bytes());
+        self.writer.write_all(&header)?;
+        self.link_type = Some(link_type);
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<()> {
+        self.writer.flush()
+    }
+
+    /// Writes the file header if no frame did and flushes
+    pub fn finish(&mut self) -> Result<()> {
+        if self.link_type.is_none() {
+            self.write_header(LINKTYPE_ETHERNET)?;
+        }
+        self.flush()
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+fn invalid(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Cursor;
+
+    fn pcap_file(big_endian: bool, magic: u32) -> Vec<u8> {
+        let u32_bytes = |value: u32| match big_endian {
+            true => value.to_be_bytes(),
+            false => value.to_le_bytes(),
+        };
+        let mut file = Vec::new();
+        file.extend_from_slice(&u32_bytes(magic));
+        file.extend_from_slice(&[0; 12]);
+        file.extend_from_slice(&u32_bytes(65535));
+        file.extend_from_slice(&u32_bytes(LINKTYPE_ETHERNET));
+        for (seconds, fraction, data) in &[(10, 500, &b"abc"[..]), (11, 0, &b"de"[..])] {
+            file.extend_from_slice(&u32_bytes(*seconds));
+            file.extend_from_slice(&u32_bytes(*fraction));
+            file.extend_from_slice(&u32_bytes(data.len() as u32));
+            file.extend_from_slice(&u32_bytes(data.len() as u32));
+            file.extend_from_slice(data);
+        }
+        file
+    }
+
+    fn block(block_type: u32, body: &[u8]) -> Vec<u8> {
+        let total_length = (12 + body.len()) as u32;
+        let mut block = block_type.to_le_bytes().to_vec();
+        block.extend_from_slice(&total_length.to_le_bytes());
+        block.extend_from_slice(body);
+        block.extend_from_slice(&total_length.to_le_bytes());
+        block
+    }
+
+    fn pcapng_file() -> Vec<u8> {
+        let mut section = PCAPNG_BYTE_ORDER_MAGIC.to_le_bytes().to_vec();
+        section.extend_from_slice(&[1, 0, 0, 0]);
+        section.extend_from_slice(&u64::MAX.to_le_bytes());
+        let mut file = block(PCAPNG_SECTION_HEADER, &section);
+
+        // Nanosecond timestamps through if_tsresol
+        let mut interface = vec![113, 0, 0, 0, 0, 0, 0, 0];
+        interface.extend_from_slice(&[9, 0, 1, 0, 9, 0, 0, 0, 0, 0, 0, 0]);
+        file.extend(block(PCAPNG_INTERFACE_DESCRIPTION, &interface));
+
+        file.extend(block(0xbad, &[0; 8]));
+
+        let ticks: u64 = 5_000_000_123;
+        let mut packet = 0u32.to_le_bytes().to_vec();
+        packet.extend_from_slice(&((ticks >> 32) as u32).to_le_bytes());
+        packet.extend_from_slice(&(ticks as u32).to_le_bytes());
+        packet.extend_from_slice(&3u32.to_le_bytes());
+        packet.extend_from_slice(&3u32.to_le_bytes());
+        packet.extend_from_slice(b"xyz\0");
+        file.extend(block(PCAPNG_ENHANCED_PACKET, &packet));
+
+        let mut simple = 2u32.to_le_bytes().to_vec();
+        simple.extend_from_slice(b"hi\0\0");
+        file.extend(block(PCAPNG_SIMPLE_PACKET, &simple));
+        file
+    }
+
+    #[test]
+    fn test_pcap_byte_orders() {
+        for big_endian in &[false, true] {
+            let mut reader =
+                PcapReader::new(Cursor::new(pcap_file(*big_endian, PCAP_MAGIC_MICROS))).unwrap();
+            let frame = reader.next_frame().unwrap().unwrap();
+            assert_eq!(frame.data, b"abc");
+            assert_eq!(frame.link_type, LINKTYPE_ETHERNET);
+            assert_eq!(frame.timestamp, UNIX_EPOCH + Duration::new(10, 500_000));
+            assert_eq!(reader.next_frame().unwrap().unwrap().data, b"de");
+            assert_eq!(reader.next_frame().unwrap(), None);
+        }
+    }
+
+    #[test]
+    fn test_pcap_nanoseconds() {
+        let mut reader = PcapReader::new(Cursor::new(pcap_file(false, PCAP_MAGIC_NANOS))).unwrap();
+        let frame = reader.next_frame().unwrap().unwrap();
+        assert_eq!(frame.timestamp, UNIX_EPOCH + Duration::new(10, 500));
+    }
+
+    #[test]
+    fn test_pcapng() {
+        let mut reader = PcapReader::new(Cursor::new(pcapng_file())).unwrap();
+        let frame = reader.next_frame().unwrap().unwrap();
+        assert_eq!(frame.data, b"xyz");
+        assert_eq!(frame.link_type, LINKTYPE_LINUX_SLL);
+        assert_eq!(frame.timestamp, UNIX_EPOCH + Duration::new(5, 123));
+        assert_eq!(reader.next_frame().unwrap().unwrap().data, b"hi");
+        assert_eq!(reader.next_frame().unwrap(), None);
+    }
+
+    #[test]
+    fn test_write_round_trip() {
+        let mut writer = PcapWriter::new(Vec::new());
+        let timestamp = UNIX_EPOCH + Duration::new(1_600_000_000, 123_456_789);
+        for data in &[&b"abc"[..], &b""[..]] {
+            let frame = PcapFrame {
+                timestamp,
+                link_type: LINKTYPE_ETHERNET,
+                data,
+            };
+            writer.write_frame(&frame).unwrap();
+        }
+        let other = PcapFrame {
+            timestamp,
+            link_type: LINKTYPE_LINUX_SLL,
+            data: b"x",
+        };
+        assert!(writer.write_frame(&other).is_err());
+
+        let mut reader = PcapReader::new(Cursor::new(writer.into_inner())).unwrap();
+        let frame = reader.next_frame().unwrap().unwrap();
+        assert_eq!(frame.data, b"abc");
+        assert_eq!(frame.timestamp, timestamp);
+        assert_eq!(frame.link_type, LINKTYPE_ETHERNET);
+        assert_eq!(reader.next_frame().unwrap().unwrap().data, b"");
+        assert_eq!(reader.next_frame().unwrap(), None);
+
+        // Nothing recorded is still a valid file
+        let mut writer = PcapWriter::new(Vec::new());
+        writer.finish().unwrap();
+        let mut reader = PcapReader::new(Cursor::new(writer.into_inner())).unwrap();
+        assert_eq!(reader.next_frame().unwrap(), None);
+    }
+
+    #[test]
+    fn test_truncated() {
+        let mut file = pcap_file(false, PCAP_MAGIC_MICROS);
+        file.truncate(file.len() - 1);
+        let mut reader = PcapReader::new(Cursor::new(file)).unwrap();
+        assert!(reader.next_frame().unwrap().is_some());
+        assert!(reader.next_frame().is_err());
+
+        assert!(PcapReader::new(Cursor::new(b"nope".to_vec())).is_err());
+    }
+}
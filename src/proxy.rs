@@ -0,0 +1,309 @@
+use crate::flows::Direction;
+use crate::lines::{accept_connections, write_line, AcceptThread, Incoming, LineReader, TICK};
+use crate::parser::{PacketTypes, Parser};
+use crate::reassembly::Connection;
+use std::io;
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::SystemTime;
+
+/// A line passing through an `FsdProxy`
+#[derive(Debug, Clone)]
+pub struct ProxiedLine {
+    /// When the line was read
+    pub timestamp: SystemTime,
+    /// Sender and receiver of the line
+    pub connection: Connection,
+    pub direction: Direction,
+    /// The line as it was sent, without the line ending
+    pub raw: String,
+    /// `None` for lines that do not parse, they are passed on all the same
+    pub packet: Option<PacketTypes<'static>>,
+}
+
+/// What happens to a line after the hook has seen it
+#[derive(Debug, Clone, PartialEq)]
+pub enum ProxyAction {
+    Forward,
+    Drop,
+    /// Sends this line instead, without the line ending
+    Replace(String),
+}
+
+/// Looks at every line going through an `FsdProxy` and decides what to do with it. Hooks are
+/// shared by all connections and called for one line at a time.
+pub trait ProxyHook {
+    fn process(&mut self, line: &ProxiedLine) -> ProxyAction;
+}
+
+impl<F: FnMut(&ProxiedLine) -> ProxyAction> ProxyHook for F {
+    fn process(&mut self, line: &ProxiedLine) -> ProxyAction {
+        self(line)
+    }
+}
+
+type SharedHook = Arc<Mutex<Box<dyn ProxyHook + Send>>>;
+
+/// A transparent proxy between FSD clients and an upstream server.
+///
+/// Every client connecting to the proxy gets its own connection to the upstream server. Lines
+/// in both directions are parsed and handed to the hook, which can log, drop or rewrite them.
+/// Without a hook everything is forwarded as is.
+pub struct FsdProxy {
+    listener: TcpListener,
+    upstream: Vec<SocketAddr>,
+    hook: SharedHook,
+    stop: Arc<AtomicBool>,
+}
+
+impl FsdProxy {
+    /// Listens on `address` for clients of the server at `upstream`
+    pub fn bind<A: ToSocketAddrs, U: ToSocketAddrs>(address: A, upstream: U) -> io::Result<Self> {
+        let upstream: Vec<SocketAddr> = upstream.to_socket_addrs()?.collect();
+        if upstream.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Upstream server has no address",
+            ));
+        }
+        Ok(FsdProxy {
+            listener: TcpListener::bind(address)?,
+            upstream,
+            hook: Arc::new(Mutex::new(Box::new(|_: &ProxiedLine| ProxyAction::Forward))),
+            stop: Arc::new(AtomicBool::new(false)),
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    pub fn set_hook<H: ProxyHook + Send + 'static>(&mut self, hook: H) {
+        self.hook = Arc::new(Mutex::new(Box::new(hook)));
+    }
+
+    /// Accepts clients until the proxy is stopped, each on its own thread
+    pub fn run(self) -> io::Result<()> {
+        accept_connections(&self.listener, &self.stop, |client| {
+            let upstream = self.upstream.clone();
+            let hook = self.hook.clone();
+            let stop = self.stop.clone();
+            thread::Builder::new()
+                .name("fsd-proxy-client".to_string())
+                .spawn(move || relay(client, &upstream, hook, stop))?;
+            Ok(())
+        })
+    }
+
+    /// Runs the proxy on a background thread
+    pub fn spawn(self) -> io::Result<ProxyHandle> {
+        let address = self.local_addr()?;
+        let stop = self.stop.clone();
+        let thread = AcceptThread::spawn("fsd-proxy", address, stop, move || self.run())?;
+        Ok(ProxyHandle { thread })
+    }
+}
+
+/// A proxy running on a background thread, created with `FsdProxy::spawn`. Dropping it stops
+/// the proxy.
+pub struct ProxyHandle {
+    thread: AcceptThread,
+}
+
+impl ProxyHandle {
+    pub fn local_addr(&self) -> SocketAddr {
+        self.thread.local_addr()
+    }
+
+    /// Stops accepting and closes every connection
+    pub fn shutdown(mut self) -> io::Result<()> {
+        self.thread.stop()
+    }
+}
+
+// Passes lines both ways until either side closes
+fn relay(
+    client: TcpStream,
+    upstream: &[SocketAddr],
+    hook: SharedHook,
+    stop: Arc<AtomicBool>,
+) -> io::Result<()> {
+    let server = match TcpStream::connect(upstream) {
+        Ok(server) => server,
+        Err(error) => {
+            let _ = client.shutdown(Shutdown::Both);
+            return Err(error);
+        }
+    };
+    for stream in [&client, &server].iter() {
+        stream.set_nodelay(true)?;
+        stream.set_read_timeout(Some(TICK))?;
+    }
+    let connection = Connection::new(client.peer_addr()?, server.peer_addr()?);
+
+    let downstream = {
+        let (from, to) = (server.try_clone()?, client.try_clone()?);
+        let (hook, stop) = (hook.clone(), stop.clone());
+        thread::Builder::new()
+            .name("fsd-proxy-server".to_string())
+            .spawn(move || {
+                let direction = (connection.reversed(), Direction::ServerToClient);
+                pass_lines(from, to, direction, &hook, &stop)
+            })?
+    };
+    let result = pass_lines(
+        client,
+        server,
+        (connection, Direction::ClientToServer),
+        &hook,
+        &stop,
+    );
+    let downstream = match downstream.join() {
+        Ok(result) => result,
+        Err(panic) => std::panic::resume_unwind(panic),
+    };
+    result.and(downstream)
+}
+
+// One direction of a relayed connection. Closes both sockets when done, so the other
+// direction ends too.
+fn pass_lines(
+    from: TcpStream,
+    mut to: TcpStream,
+    route: (Connection, Direction),
+    hook: &SharedHook,
+    stop: &AtomicBool,
+) -> io::Result<()> {
+    let mut reader = LineReader::new(from);
+    let result = forward_lines(&mut reader, &mut to, route, hook, stop);
+    let _ = reader.get_ref().shutdown(Shutdown::Both);
+    let _ = to.shutdown(Shutdown::Both);
+    result
+}
+
+fn forward_lines(
+    reader: &mut LineReader<TcpStream>,
+    to: &mut TcpStream,
+    (connection, direction): (Connection, Direction),
+    hook: &SharedHook,
+    stop: &AtomicBool,
+) -> io::Result<()> {
+    while !stop.load(Ordering::SeqCst) {
+        let raw = match reader.next_line()? {
+            Incoming::Line(line) => line,
+            Incoming::Idle => continue,
+            Incoming::Closed => break,
+        };
+        let line = ProxiedLine {
+            timestamp: SystemTime::now(),
+            connection,
+            direction,
+            packet: Parser::parse(&raw).ok().map(PacketTypes::into_owned),
+            raw,
+        };
+        let action = hook
+            .lock()
+            .unwrap_or_else(|error| error.into_inner())
+            .process(&line);
+        match action {
+            ProxyAction::Forward => write_line(to, &line.raw)?,
+            ProxyAction::Drop => (),
+            ProxyAction::Replace(replacement) => write_line(to, &replacement)?,
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+
+    fn read_line(reader: &mut BufReader<TcpStream>) -> String {
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        line
+    }
+
+    #[test]
+    fn test_proxy() {
+        let upstream = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut proxy = FsdProxy::bind("127.0.0.1:0", upstream.local_addr().unwrap()).unwrap();
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let log = seen.clone();
+        proxy.set_hook(move |line: &ProxiedLine| {
+            log.lock()
+                .unwrap()
+                .push((line.direction, line.raw.clone(), line.packet.is_some()));
+            match line.packet {
+                Some(PacketTypes::TextMessage(ref message)) if message.text == "secret" => {
+                    ProxyAction::Drop
+                }
+                Some(PacketTypes::TextMessage(ref message)) if message.text == "hello" => {
+                    ProxyAction::Replace(line.raw.replace("hello", "howdy"))
+                }
+                _ => ProxyAction::Forward,
+            }
+        });
+        let proxy = proxy.spawn().unwrap();
+
+        let mut client = TcpStream::connect(proxy.local_addr()).unwrap();
+        let (mut server, _) = upstream.accept().unwrap();
+        server.write_all(b"#TMSERVER:N123:welcome\r\n").unwrap();
+        let mut client_reader = BufReader::new(client.try_clone().unwrap());
+        assert_eq!(read_line(&mut client_reader), "#TMSERVER:N123:welcome\r\n");
+
+        client
+            .write_all(b"#TMN123:BOS_APP:secret\r\nnot fsd\r\n#TMN123:BOS_APP:hello\r\n")
+            .unwrap();
+        let mut server_reader = BufReader::new(server.try_clone().unwrap());
+        assert_eq!(read_line(&mut server_reader), "not fsd\r\n");
+        assert_eq!(read_line(&mut server_reader), "#TMN123:BOS_APP:howdy\r\n");
+
+        // Either side closing ends the other one too
+        drop(server);
+        drop(server_reader);
+        assert_eq!(read_line(&mut client_reader), "");
+        proxy.shutdown().unwrap();
+
+        let seen = seen.lock().unwrap();
+        assert_eq!(
+            *seen,
+            vec![
+                (
+                    Direction::ServerToClient,
+                    "#TMSERVER:N123:welcome".to_string(),
+                    true
+                ),
+                (
+                    Direction::ClientToServer,
+                    "#TMN123:BOS_APP:secret".to_string(),
+                    true
+                ),
+                (Direction::ClientToServer, "not fsd".to_string(), false),
+                (
+                    Direction::ClientToServer,
+                    "#TMN123:BOS_APP:hello".to_string(),
+                    true
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_upstream_down() {
+        // Nothing listens on the upstream port once the listener is gone
+        let upstream = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = upstream.local_addr().unwrap();
+        drop(upstream);
+        let proxy = FsdProxy::bind("127.0.0.1:0", address)
+            .unwrap()
+            .spawn()
+            .unwrap();
+        let client = TcpStream::connect(proxy.local_addr()).unwrap();
+        assert_eq!(read_line(&mut BufReader::new(client)), "");
+    }
+}
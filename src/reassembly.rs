@@ -0,0 +1,410 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+
+// Guards against streams that never send a line terminator
+const MAX_BUFFERED_BYTES: usize = 64 * 1024;
+// Segments held behind a gap before the gap is given up on, e.g. after a capture drop
+const MAX_PENDING_SEGMENTS: usize = 64;
+// Streams tracked at once, the least recently active one makes way for a new one. Connections
+// whose FIN or RST was never captured would pile up otherwise.
+const MAX_STREAMS: usize = 4096;
+
+/// One direction of a TCP connection
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub struct Connection {
+    pub source: SocketAddr,
+    pub destination: SocketAddr,
+}
+
+impl Connection {
+    pub fn new(source: SocketAddr, destination: SocketAddr) -> Self {
+        Self {
+            source,
+            destination,
+        }
+    }
+
+    /// The other direction of the same connection
+    pub fn reversed(&self) -> Self {
+        Self::new(self.destination, self.source)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TcpSegment<'a> {
+    pub sequence: u32,
+    pub syn: bool,
+    pub fin: bool,
+    pub rst: bool,
+    pub payload: &'a [u8],
+}
+
+#[derive(Debug, Default)]
+struct StreamState {
+    next_sequence: Option<u32>,
+    pending: HashMap<u32, Vec<u8>>,
+    buffer: Vec<u8>,
+    // Set after skipping a gap, the data after it starts somewhere in a line
+    partial_line: bool,
+    // Sequence number right after the last byte, once the FIN was seen
+    fin: Option<u32>,
+    last_active: u64,
+}
+
+impl StreamState {
+    fn accept(&mut self, sequence: u32, payload: &[u8]) {
+        let next = *self.next_sequence.get_or_insert(sequence);
+        let offset = sequence.wrapping_sub(next) as i32;
+
+        if offset > 0 {
+            // Arrived ahead of a gap, hold on to it until the gap is filled
+            self.pending.insert(sequence, payload.to_vec());
+            if self.pending.len() >= MAX_PENDING_SEGMENTS {
+                self.skip_gap(next);
+            }
+            return;
+        }
+
+        // Retransmitted or overlapping data, only keep what has not been seen yet
+        let skip = offset.unsigned_abs() as usize;
+        if skip >= payload.len() {
+            return;
+        }
+
+        self.buffer.extend_from_slice(&payload[skip..]);
+        self.next_sequence = Some(next.wrapping_add((payload.len() - skip) as u32));
+
+        if self.buffer.len() > MAX_BUFFERED_BYTES {
+            self.buffer.clear();
+            self.partial_line = true;
+        }
+    }
+
+    // The missing data is not coming, carries on from the oldest segment held
+    fn skip_gap(&mut self, next: u32) {
+        let resume = self
+            .pending
+            .keys()
+            .copied()
+            .min_by_key(|sequence| sequence.wrapping_sub(next));
+        if let Some(resume) = resume {
+            self.buffer.clear();
+            self.partial_line = true;
+            self.next_sequence = Some(resume);
+        }
+    }
+
+    fn drain_pending(&mut self) {
+        while let Some(next) = self.next_sequence {
+            let ready = self.pending.keys().copied().find(|sequence| {
+                let offset = sequence.wrapping_sub(next) as i32;
+                offset <= 0
+            });
+
+            match ready {
+                Some(sequence) => {
+                    let payload = self.pending.remove(&sequence).unwrap();
+                    self.accept(sequence, &payload);
+                }
+                None => break,
+            }
+        }
+    }
+
+    // Everything up to the FIN arrived, segments it overtook included
+    fn is_finished(&self) -> bool {
+        match (self.fin, self.next_sequence) {
+            (Some(fin), Some(next)) => fin.wrapping_sub(next) as i32 <= 0,
+            (Some(_), None) => true,
+            (None, _) => false,
+        }
+    }
+
+    fn take_lines(&mut self) -> Vec<String> {
+        let mut lines = Vec::new();
+
+        while let Some(end) = self.buffer.iter().position(|byte| *byte == b'\n') {
+            let line: Vec<u8> = self.buffer.drain(..=end).collect();
+            if self.partial_line {
+                self.partial_line = false;
+                continue;
+            }
+            let line = match line.strip_suffix(b"\r\n") {
+                Some(line) => line,
+                None => &line[..line.len() - 1],
+            };
+            lines.push(line.iter().map(|byte| *byte as char).collect());
+        }
+
+        lines
+    }
+}
+
+/// Rebuilds the byte stream of each TCP connection and splits it into complete lines
+#[derive(Debug, Default)]
+pub struct StreamReassembler {
+    streams: HashMap<Connection, StreamState>,
+    // Counts segments, for telling which stream was active last
+    clock: u64,
+}
+
+impl StreamReassembler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Feeds one captured segment and returns the lines it completed, without their terminators
+    pub fn process(&mut self, connection: Connection, segment: &TcpSegment) -> Vec<String> {
+        if segment.rst {
+            self.streams.remove(&connection);
+            return Vec::new();
+        }
+
+        if self.streams.len() >= MAX_STREAMS && !self.streams.contains_key(&connection) {
+            self.evict_idlest();
+        }
+        self.clock += 1;
+        let stream = self.streams.entry(connection).or_default();
+        stream.last_active = self.clock;
+
+        if segment.syn {
+            // The SYN occupies one sequence number
+            *stream = StreamState::default();
+            stream.next_sequence = Some(segment.sequence.wrapping_add(1));
+        }
+
+        if !segment.payload.is_empty() {
+            let sequence = match segment.syn {
+                true => segment.sequence.wrapping_add(1),
+                false => segment.sequence,
+            };
+            stream.accept(sequence, segment.payload);
+            stream.drain_pending();
+        }
+
+        if segment.fin {
+            let length = segment.payload.len() as u32 + segment.syn as u32;
+            stream.fin = Some(segment.sequence.wrapping_add(length));
+        }
+
+        let lines = stream.take_lines();
+        if stream.is_finished() {
+            self.streams.remove(&connection);
+        }
+
+        lines
+    }
+
+    fn evict_idlest(&mut self) {
+        let idlest = self
+            .streams
+            .iter()
+            .min_by_key(|(_, stream)| stream.last_active)
+            .map(|(connection, _)| *connection);
+        if let Some(connection) = idlest {
+            self.streams.remove(&connection);
+        }
+    }
+
+    pub fn remove(&mut self, connection: &Connection) {
+        self.streams.remove(connection);
+    }
+
+    pub fn number_tracked(&self) -> usize {
+        self.streams.len()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn connection() -> Connection {
+        Connection::new(
+            "10.0.0.2:50000".parse().unwrap(),
+            "10.0.0.1:6809".parse().unwrap(),
+        )
+    }
+
+    fn segment(sequence: u32, payload: &[u8]) -> TcpSegment<'_> {
+        TcpSegment {
+            sequence,
+            syn: false,
+            fin: false,
+            rst: false,
+            payload,
+        }
+    }
+
+    #[test]
+    fn test_split_line() {
+        let mut reassembler = StreamReassembler::new();
+        assert!(reassembler
+            .process(connection(), &segment(100, b"#TMA:B:hel"))
+            .is_empty());
+        assert_eq!(
+            reassembler.process(connection(), &segment(110, b"lo\r\n#TMA:C:x\r\n")),
+            vec!["#TMA:B:hello", "#TMA:C:x"]
+        );
+    }
+
+    #[test]
+    fn test_out_of_order() {
+        let mut reassembler = StreamReassembler::new();
+        reassembler.process(connection(), &segment(0, b"AB"));
+        assert!(reassembler
+            .process(connection(), &segment(4, b"EF\r\n"))
+            .is_empty());
+        assert_eq!(
+            reassembler.process(connection(), &segment(2, b"CD")),
+            vec!["ABCDEF"]
+        );
+    }
+
+    #[test]
+    fn test_retransmission() {
+        let mut reassembler = StreamReassembler::new();
+        reassembler.process(connection(), &segment(0, b"ABC"));
+        assert!(reassembler
+            .process(connection(), &segment(0, b"ABC"))
+            .is_empty());
+        // Partially overlaps what was already received
+        assert_eq!(
+            reassembler.process(connection(), &segment(1, b"BCD\r\n")),
+            vec!["ABCD"]
+        );
+    }
+
+    #[test]
+    fn test_lost_segment() {
+        let mut reassembler = StreamReassembler::new();
+        assert_eq!(
+            reassembler.process(connection(), &segment(0, b"#TMA:B:first\r\n#TMA:B:par")),
+            vec!["#TMA:B:first"]
+        );
+        // The segment at 24 with the rest of the line never shows up
+        let mut sequence = 30;
+        let mut lines = Vec::new();
+        for i in 0..MAX_PENDING_SEGMENTS {
+            let line = format!("#TMA:B:{:03}\r\n", i);
+            lines.extend(reassembler.process(connection(), &segment(sequence, line.as_bytes())));
+            sequence += line.len() as u32;
+        }
+        // Where the gap ended is not known to be the start of a line, so that line is dropped
+        assert_eq!(lines.len(), MAX_PENDING_SEGMENTS - 1);
+        assert_eq!(lines.first().unwrap(), "#TMA:B:001");
+        assert_eq!(
+            reassembler.process(connection(), &segment(sequence, b"#TMA:B:last\r\n")),
+            vec!["#TMA:B:last"]
+        );
+        // The lost segment turning up late changes nothing
+        assert!(reassembler
+            .process(connection(), &segment(24, b"tial\r\n"))
+            .is_empty());
+    }
+
+    #[test]
+    fn test_sequence_wraparound() {
+        let mut reassembler = StreamReassembler::new();
+        reassembler.process(connection(), &segment(u32::MAX - 1, b"AB"));
+        assert_eq!(
+            reassembler.process(connection(), &segment(0, b"C\r\n")),
+            vec!["ABC"]
+        );
+    }
+
+    #[test]
+    fn test_syn_and_fin() {
+        let mut reassembler = StreamReassembler::new();
+        let syn = TcpSegment {
+            syn: true,
+            ..segment(999, b"")
+        };
+        reassembler.process(connection(), &syn);
+        let fin = TcpSegment {
+            fin: true,
+            ..segment(1000, b"$DISERVER:CLIENT:x\r\n")
+        };
+        assert_eq!(
+            reassembler.process(connection(), &fin),
+            vec!["$DISERVER:CLIENT:x"]
+        );
+        assert_eq!(reassembler.number_tracked(), 0);
+
+        // The FIN overtook data that is still on its way
+        reassembler.process(connection(), &segment(0, b"AB"));
+        let fin = TcpSegment {
+            fin: true,
+            ..segment(4, b"EF\r\n")
+        };
+        assert!(reassembler.process(connection(), &fin).is_empty());
+        assert_eq!(reassembler.number_tracked(), 1);
+        assert_eq!(
+            reassembler.process(connection(), &segment(2, b"CD")),
+            vec!["ABCDEF"]
+        );
+        assert_eq!(reassembler.number_tracked(), 0);
+    }
+
+    #[test]
+    fn test_overlong_line() {
+        let mut reassembler = StreamReassembler::new();
+        let junk = vec![b'x'; MAX_BUFFERED_BYTES + 1];
+        assert!(reassembler
+            .process(connection(), &segment(0, &junk))
+            .is_empty());
+        // The rest of the cut off line is not a line of its own
+        assert_eq!(
+            reassembler.process(
+                connection(),
+                &segment(junk.len() as u32, b"xx\r\n#TMA:B:x\r\n")
+            ),
+            vec!["#TMA:B:x"]
+        );
+    }
+
+    #[test]
+    fn test_stream_limit() {
+        let mut reassembler = StreamReassembler::new();
+        let client = |port: u16| {
+            Connection::new(
+                SocketAddr::from(([10, 0, 0, 2], port)),
+                "10.0.0.1:6809".parse().unwrap(),
+            )
+        };
+        for port in 0..MAX_STREAMS as u16 {
+            reassembler.process(client(port), &segment(0, b"AB"));
+        }
+        reassembler.process(client(0), &segment(2, b"C"));
+        reassembler.process(client(u16::MAX), &segment(0, b"AB"));
+        assert_eq!(reassembler.number_tracked(), MAX_STREAMS);
+
+        // The stream that was quiet the longest made way
+        assert_eq!(
+            reassembler.process(client(0), &segment(3, b"\r\n")),
+            vec!["ABC"]
+        );
+        assert_eq!(
+            reassembler.process(client(1), &segment(2, b"\r\n")),
+            vec![""]
+        );
+    }
+
+    #[test]
+    fn test_connections_are_separate() {
+        let mut reassembler = StreamReassembler::new();
+        let other = Connection::new(
+            "10.0.0.3:50000".parse().unwrap(),
+            "10.0.0.1:6809".parse().unwrap(),
+        );
+        reassembler.process(connection(), &segment(0, b"AB"));
+        assert_eq!(
+            reassembler.process(other, &segment(0, b"XY\r\n")),
+            vec!["XY"]
+        );
+        assert_eq!(
+            reassembler.process(connection(), &segment(2, b"\r\n")),
+            vec!["AB"]
+        );
+    }
+}
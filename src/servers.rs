@@ -0,0 +1,363 @@
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::io;
+use std::io::{Error, ErrorKind};
+use std::net::{IpAddr, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+
+pub const DEFAULT_FSD_PORT: u16 = 6809;
+
+#[cfg(feature = "http")]
+pub const VATSIM_SERVER_FEED: &str = "https://data.vatsim.net/v3/vatsim-data.json";
+
+/// Reasons a server list could not be loaded
+#[derive(Debug)]
+pub enum ServerListError {
+    Io(io::Error),
+    InvalidJson(String),
+    Http(String),
+    /// Hostnames that did not resolve, the rest of the list was still loaded
+    Unresolved(Vec<String>),
+}
+
+impl Display for ServerListError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ServerListError::Io(error) => write!(f, "Could not read server list: {}", error),
+            ServerListError::InvalidJson(error) => {
+                write!(f, "Could not deserialize server list: {}", error)
+            }
+            ServerListError::Http(error) => write!(f, "Could not retrieve server list: {}", error),
+            ServerListError::Unresolved(hosts) => {
+                write!(f, "Could not resolve {}", hosts.join(", "))
+            }
+        }
+    }
+}
+
+impl std::error::Error for ServerListError {}
+
+impl From<io::Error> for ServerListError {
+    fn from(error: io::Error) -> Self {
+        ServerListError::Io(error)
+    }
+}
+
+/// What identifies traffic as going to or coming from an FSD server
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ServerList {
+    /// Addresses or hostnames of servers
+    pub addresses: Vec<String>,
+    /// Ports servers listen on, any host using them counts as a server
+    pub ports: Vec<u16>,
+}
+
+impl ServerList {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn from_addresses<I: IntoIterator<Item = S>, S: Into<String>>(addresses: I) -> Self {
+        ServerList {
+            addresses: addresses.into_iter().map(Into::into).collect(),
+            ports: Vec::new(),
+        }
+    }
+
+    pub fn from_port(port: u16) -> Self {
+        ServerList {
+            addresses: Vec::new(),
+            ports: vec![port],
+        }
+    }
+
+    pub fn extend(&mut self, other: ServerList) {
+        self.addresses.extend(other.addresses);
+        self.ports.extend(other.ports);
+    }
+
+    /// Turns the addresses into IPs, literal addresses are used as they are.
+    ///
+    /// Returns the IPs of everything that resolved and the hostnames that did not.
+    pub fn resolve(&self, resolver: &dyn Resolver) -> (HashSet<IpAddr>, Vec<String>) {
+        let mut ips = HashSet::new();
+        let mut unresolved = Vec::new();
+
+        for address in &self.addresses {
+            match resolve_address(address, resolver) {
+                Some(resolved) => ips.extend(resolved),
+                None => unresolved.push(address.clone()),
+            }
+        }
+
+        (ips, unresolved)
+    }
+}
+
+// The IPs of one address or hostname, `None` if it did not resolve to any
+pub(crate) fn resolve_address(address: &str, resolver: &dyn Resolver) -> Option<Vec<IpAddr>> {
+    if let Ok(ip) = address.parse::<IpAddr>() {
+        return Some(vec![ip]);
+    }
+    match resolver.resolve(address) {
+        Ok(resolved) if !resolved.is_empty() => Some(resolved),
+        _ => None,
+    }
+}
+
+/// Looks up the addresses of a hostname
+pub trait Resolver {
+    fn resolve(&self, host: &str) -> io::Result<Vec<IpAddr>>;
+}
+
+impl<F: Fn(&str) -> io::Result<Vec<IpAddr>>> Resolver for F {
+    fn resolve(&self, host: &str) -> io::Result<Vec<IpAddr>> {
+        self(host)
+    }
+}
+
+/// Resolves through the operating system, the same way connecting to the host would
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemResolver;
+
+impl Resolver for SystemResolver {
+    fn resolve(&self, host: &str) -> io::Result<Vec<IpAddr>> {
+        Ok((host, 0)
+            .to_socket_addrs()?
+            .map(|address| address.ip())
+            .collect())
+    }
+}
+
+/// Resolves from a file in the `/etc/hosts` format, without asking DNS
+#[derive(Debug, Default, Clone)]
+pub struct HostsFile {
+    hosts: HashMap<String, Vec<IpAddr>>,
+}
+
+impl HostsFile {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Ok(Self::parse(&fs::read_to_string(path)?))
+    }
+
+    /// Lines that do not start with a valid address are skipped
+    pub fn parse(text: &str) -> Self {
+        let mut hosts: HashMap<String, Vec<IpAddr>> = HashMap::new();
+
+        for line in text.lines() {
+            let mut fields = line.split('#').next().unwrap_or("").split_whitespace();
+            let ip = match fields.next().map(str::parse::<IpAddr>) {
+                Some(Ok(ip)) => ip,
+                _ => continue,
+            };
+            for name in fields {
+                hosts.entry(name.to_lowercase()).or_default().push(ip);
+            }
+        }
+
+        HostsFile { hosts }
+    }
+}
+
+impl Resolver for HostsFile {
+    fn resolve(&self, host: &str) -> io::Result<Vec<IpAddr>> {
+        match self.hosts.get(&host.to_lowercase()) {
+            Some(ips) => Ok(ips.clone()),
+            None => Err(Error::new(
+                ErrorKind::NotFound,
+                "Host is not in the hosts file",
+            )),
+        }
+    }
+}
+
+/// A source for the server list
+pub trait ServerProvider {
+    fn load(&self) -> Result<ServerList, ServerListError>;
+}
+
+/// A fixed list, e.g. a private server or the ones given on the command line
+impl ServerProvider for ServerList {
+    fn load(&self) -> Result<ServerList, ServerListError> {
+        Ok(self.clone())
+    }
+}
+
+#[derive(Deserialize)]
+struct DataFeed {
+    servers: Vec<Server>,
+}
+
+#[derive(Deserialize)]
+struct Server {
+    hostname_or_ip: String,
+}
+
+/// Reads the server addresses out of a VATSIM data feed document
+pub fn parse_data_feed(data: &str) -> Result<ServerList, ServerListError> {
+    let feed = serde_json::from_str::<DataFeed>(data)
+        .map_err(|error| ServerListError::InvalidJson(error.to_string()))?;
+    Ok(ServerList::from_addresses(
+        feed.servers.into_iter().map(|s| s.hostname_or_ip),
+    ))
+}
+
+/// A saved copy of the VATSIM data feed
+#[derive(Debug, Clone)]
+pub struct DataFeedFile(pub PathBuf);
+
+impl ServerProvider for DataFeedFile {
+    fn load(&self) -> Result<ServerList, ServerListError> {
+        parse_data_feed(&fs::read_to_string(&self.0)?)
+    }
+}
+
+/// A text file with one address or hostname per line, `#` starts a comment
+#[derive(Debug, Clone)]
+pub struct TextFile(pub PathBuf);
+
+impl ServerProvider for TextFile {
+    fn load(&self) -> Result<ServerList, ServerListError> {
+        let text = fs::read_to_string(&self.0)?;
+        Ok(ServerList::from_addresses(
+            text.lines()
+                .map(|line| line.split('#').next().unwrap_or("").trim())
+                .filter(|line| !line.is_empty()),
+        ))
+    }
+}
+
+/// Treats any host talking on the port as a server, for servers that are not listed anywhere
+#[derive(Debug, Clone, Copy)]
+pub struct PortDetection(pub u16);
+
+impl Default for PortDetection {
+    fn default() -> Self {
+        PortDetection(DEFAULT_FSD_PORT)
+    }
+}
+
+impl ServerProvider for PortDetection {
+    fn load(&self) -> Result<ServerList, ServerListError> {
+        Ok(ServerList::from_port(self.0))
+    }
+}
+
+/// Fetches the data feed over HTTP
+#[cfg(feature = "http")]
+#[derive(Debug, Clone)]
+pub struct DataFeedUrl(pub String);
+
+#[cfg(feature = "http")]
+impl Default for DataFeedUrl {
+    fn default() -> Self {
+        DataFeedUrl(VATSIM_SERVER_FEED.to_string())
+    }
+}
+
+#[cfg(feature = "http")]
+impl ServerProvider for DataFeedUrl {
+    fn load(&self) -> Result<ServerList, ServerListError> {
+        let response =
+            requests::get(&self.0).map_err(|error| ServerListError::Http(error.to_string()))?;
+
+        if !response.status_code().is_success() {
+            return Err(ServerListError::Http(format!(
+                "status {}",
+                response.status_code()
+            )));
+        }
+
+        match response.text() {
+            Some(text) => parse_data_feed(text),
+            None => Err(ServerListError::Http("Response is not text".to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn temp_file(name: &str, contents: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("fsdparser-{}-{}", std::process::id(), name));
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_data_feed_file() {
+        let path = temp_file(
+            "feed.json",
+            r#"{"general": {}, "servers": [
+                {"ident": "USA-EAST", "hostname_or_ip": "97.107.135.245", "location": "New York"},
+                {"ident": "GERMANY", "hostname_or_ip": "fsd.example.net", "location": "Frankfurt"}
+            ]}"#,
+        );
+        let list = DataFeedFile(path.clone()).load().unwrap();
+        fs::remove_file(path).unwrap();
+        assert_eq!(list.addresses, vec!["97.107.135.245", "fsd.example.net"]);
+        assert!(list.ports.is_empty());
+    }
+
+    #[test]
+    fn test_text_file() {
+        let path = temp_file(
+            "servers.txt",
+            "# lab servers\n10.0.0.1\n\n  2001:db8::1  # v6\nfsd.local\n",
+        );
+        let list = TextFile(path.clone()).load().unwrap();
+        fs::remove_file(path).unwrap();
+        assert_eq!(list.addresses, vec!["10.0.0.1", "2001:db8::1", "fsd.local"]);
+    }
+
+    #[test]
+    fn test_errors() {
+        assert!(matches!(
+            parse_data_feed("{\"servers\": 1}"),
+            Err(ServerListError::InvalidJson(_))
+        ));
+        let missing = std::env::temp_dir().join("fsdparser-does-not-exist.txt");
+        assert!(matches!(
+            TextFile(missing).load(),
+            Err(ServerListError::Io(_))
+        ));
+    }
+
+    #[test]
+    fn test_resolve() {
+        let hosts = HostsFile::parse(
+            "127.0.0.1 localhost\n# comment\n10.0.0.1  FSD.example.net fsd # lab\nnot-an-ip x\n10.0.0.2 fsd\n",
+        );
+        let list = ServerList::from_addresses(vec!["fsd", "fsd.example.net", "192.0.2.1", "x"]);
+        let (ips, unresolved) = list.resolve(&hosts);
+
+        let expected: HashSet<IpAddr> = ["10.0.0.1", "10.0.0.2", "192.0.2.1"]
+            .iter()
+            .map(|ip| ip.parse().unwrap())
+            .collect();
+        assert_eq!(ips, expected);
+        assert_eq!(unresolved, vec!["x"]);
+    }
+
+    #[test]
+    fn test_resolver_closure() {
+        let resolver = |host: &str| match host {
+            "fsd.local" => Ok(vec!["::1".parse().unwrap()]),
+            _ => Err(Error::new(ErrorKind::NotFound, "unknown")),
+        };
+        let (ips, unresolved) = ServerList::from_addresses(vec!["fsd.local"]).resolve(&resolver);
+        assert!(ips.contains(&"::1".parse::<IpAddr>().unwrap()));
+        assert!(unresolved.is_empty());
+    }
+
+    #[test]
+    fn test_port_detection() {
+        assert_eq!(
+            PortDetection::default().load().unwrap(),
+            ServerList::from_port(DEFAULT_FSD_PORT)
+        );
+    }
+}
@@ -1,242 +1,1363 @@
 #![cfg(feature = "sniffer")]
-use crate::parser::{PacketTypes, Parser};
-use pnet::datalink;
-use pnet::datalink::{Channel, DataLinkReceiver, MacAddr, NetworkInterface};
-use pnet::packet::ethernet::{EtherTypes, EthernetPacket};
-use pnet::packet::ip::IpNextHeaderProtocols;
+use crate::capture::{CaptureSource, PacketSniffer};
+use crate::flows::{FlowClassifier, PacketSource};
+use crate::pcap::{PcapFrame, PcapReader, PcapWriter, LINKTYPE_ETHERNET, LINKTYPE_LINUX_SLL};
+use crate::reassembly::{Connection, StreamReassembler, TcpSegment};
+use crate::servers::{
+    resolve_address, Resolver, ServerList, ServerListError, ServerProvider, SystemResolver,
+};
+use pnet::datalink::{MacAddr, NetworkInterface};
+use pnet::packet::ethernet::{EtherType, EtherTypes, EthernetPacket};
+use pnet::packet::ip::{IpNextHeaderProtocol, IpNextHeaderProtocols};
 use pnet::packet::ipv4::Ipv4Packet;
-use pnet::packet::tcp::TcpPacket;
-use serde::Deserialize;
-use std::collections::{HashSet, VecDeque};
-use std::net::Ipv4Addr;
+use pnet::packet::ipv6::Ipv6Packet;
+use pnet::packet::tcp::{TcpFlags, TcpPacket};
+use pnet::packet::vlan::VlanPacket;
+use pnet::packet::Packet;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fs::File;
+use std::io;
+use std::io::{BufReader, BufWriter};
+use std::net::{IpAddr, SocketAddr};
+use std::panic;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc;
+use std::sync::mpsc::{Receiver, TrySendError};
+use std::sync::Arc;
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant, SystemTime};
 
-pub struct EthernetIpv4TCPPacket<'a> {
-    payload: &'a [u8],
-    ether_packet: EthernetPacket<'a>,
-    ipv4_packet: Ipv4Packet<'a>,
-    tcp_packet: TcpPacket<'a>,
+const ETHERNET_HEADER_LENGTH: usize = 14;
+const VLAN_TAG_LENGTH: usize = 4;
+const SLL_HEADER_LENGTH: usize = 16;
+const IPV6_HEADER_LENGTH: usize = 40;
+const IPV6_FRAGMENT_HEADER_LENGTH: usize = 8;
+// 802.1ad service tag, pnet only knows the older 0x9100 value
+const ETHERTYPE_SERVICE_VLAN: EtherType = EtherType(0x88a8);
+// Frames of a flow content detection has not decided on yet, held back from the recording
+const MAX_HELD_FRAMES: usize = 64;
+
+/// The link layer header captured frames start with
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LinkType {
+    Ethernet,
+    // Linux cooked capture, produced when capturing on the "any" pseudo-interface
+    LinuxSll,
 }
 
-impl<'a> EthernetIpv4TCPPacket<'a> {
-    pub fn new(packet: &'a [u8]) -> Result<EthernetIpv4TCPPacket<'a>, &'static str> {
-        let mut offset = 0;
+impl LinkType {
+    /// Maps a pcap `LINKTYPE_` value, `None` for link types that cannot be decoded
+    pub fn from_pcap(link_type: u32) -> Option<LinkType> {
+        match link_type {
+            LINKTYPE_ETHERNET => Some(LinkType::Ethernet),
+            LINKTYPE_LINUX_SLL => Some(LinkType::LinuxSll),
+            _ => None,
+        }
+    }
 
-        let ether_packet = match EthernetPacket::new(packet) {
-            Some(packet) => packet,
-            None => return Err("Invalid ethernet frame provided!"),
-        };
+    pub fn to_pcap(self) -> u32 {
+        match self {
+            LinkType::Ethernet => LINKTYPE_ETHERNET,
+            LinkType::LinuxSll => LINKTYPE_LINUX_SLL,
+        }
+    }
+}
 
-        match ether_packet.get_ethertype() {
-            EtherTypes::Ipv4 => offset += 14,
-            _ => return Err("Ethertype not supported."),
+// Returns the addresses and where the TCP segment starts and ends within the frame
+fn decode_ipv4(
+    packet: &[u8],
+    offset: usize,
+) -> Result<(IpAddr, IpAddr, usize, usize), &'static str> {
+    let ipv4_packet = match Ipv4Packet::new(&packet[offset..]) {
+        Some(packet) => packet,
+        None => return Err("Invalid Ipv4 packet!"),
+    };
+
+    if ipv4_packet.get_next_level_protocol() != IpNextHeaderProtocols::Tcp {
+        return Err("Not TCP packet!");
+    }
+
+    // Short frames are padded past the end of the IP packet. Captures taken with
+    // segmentation offload can report a total length of zero, use the whole frame then.
+    let header_length = ipv4_packet.get_header_length() as usize * 4;
+    let end = match ipv4_packet.get_total_length() as usize {
+        0 => packet.len(),
+        total_length => (offset + total_length).min(packet.len()),
+    };
+    if header_length < Ipv4Packet::minimum_packet_size() || offset + header_length > end {
+        return Err("Invalid Ipv4 packet!");
+    }
+
+    Ok((
+        ipv4_packet.get_source().into(),
+        ipv4_packet.get_destination().into(),
+        offset + header_length,
+        end,
+    ))
+}
+
+fn decode_ipv6(
+    packet: &[u8],
+    offset: usize,
+) -> Result<(IpAddr, IpAddr, usize, usize), &'static str> {
+    let ipv6_packet = match Ipv6Packet::new(&packet[offset..]) {
+        Some(packet) => packet,
+        None => return Err("Invalid Ipv6 packet!"),
+    };
+
+    // A payload length of zero is either a jumbogram or segmentation offload
+    let end = match ipv6_packet.get_payload_length() as usize {
+        0 => packet.len(),
+        payload_length => (offset + IPV6_HEADER_LENGTH + payload_length).min(packet.len()),
+    };
+
+    let mut next_header = ipv6_packet.get_next_header();
+    let mut offset = offset + IPV6_HEADER_LENGTH;
+
+    // Walk the extension header chain until the TCP header is reached
+    while next_header != IpNextHeaderProtocols::Tcp {
+        if offset + 2 > end {
+            return Err("Invalid Ipv6 packet!");
+        }
+        let header_length = match next_header {
+            IpNextHeaderProtocols::Hopopt
+            | IpNextHeaderProtocols::Ipv6Route
+            | IpNextHeaderProtocols::Ipv6Opts => (packet[offset + 1] as usize + 1) * 8,
+            IpNextHeaderProtocols::Ipv6Frag => {
+                if offset + IPV6_FRAGMENT_HEADER_LENGTH > end {
+                    return Err("Invalid Ipv6 packet!");
+                }
+                // Only the first fragment carries the TCP header
+                if u16::from_be_bytes([packet[offset + 2], packet[offset + 3]]) >> 3 != 0 {
+                    return Err("Not first Ipv6 fragment!");
+                }
+                IPV6_FRAGMENT_HEADER_LENGTH
+            }
+            IpNextHeaderProtocols::Ah => (packet[offset + 1] as usize + 2) * 4,
+            _ => return Err("Not TCP packet!"),
         };
+        next_header = IpNextHeaderProtocol(packet[offset]);
+        offset += header_length;
+    }
 
-        let ipv4_packet = match Ipv4Packet::new(&packet[offset..]) {
-            Some(packet) => packet,
-            None => return Err("Invalid Ipv4 packet!"),
+    if offset > end {
+        return Err("Invalid Ipv6 packet!");
+    }
+
+    Ok((
+        ipv6_packet.get_source().into(),
+        ipv6_packet.get_destination().into(),
+        offset,
+        end,
+    ))
+}
+
+pub struct EthernetIpTcpPacket<'a> {
+    payload: &'a [u8],
+    source_mac: Option<MacAddr>,
+    source_ip: IpAddr,
+    destination_ip: IpAddr,
+    tcp_packet: TcpPacket<'a>,
+}
+
+impl<'a> EthernetIpTcpPacket<'a> {
+    pub fn new(packet: &'a [u8]) -> Result<EthernetIpTcpPacket<'a>, &'static str> {
+        Self::with_link_type(packet, LinkType::Ethernet)
+    }
+
+    pub fn with_link_type(
+        packet: &'a [u8],
+        link_type: LinkType,
+    ) -> Result<EthernetIpTcpPacket<'a>, &'static str> {
+        let (mut ethertype, mut offset, source_mac) = match link_type {
+            LinkType::Ethernet => {
+                let ether_packet = match EthernetPacket::new(packet) {
+                    Some(packet) => packet,
+                    None => return Err("Invalid ethernet frame provided!"),
+                };
+                (
+                    ether_packet.get_ethertype(),
+                    ETHERNET_HEADER_LENGTH,
+                    Some(ether_packet.get_source()),
+                )
+            }
+            LinkType::LinuxSll => {
+                if packet.len() < SLL_HEADER_LENGTH {
+                    return Err("Invalid Linux cooked capture frame provided!");
+                }
+                let source_mac = match u16::from_be_bytes([packet[4], packet[5]]) {
+                    6 => Some(MacAddr::new(
+                        packet[6], packet[7], packet[8], packet[9], packet[10], packet[11],
+                    )),
+                    _ => None,
+                };
+                (
+                    EtherType(u16::from_be_bytes([packet[14], packet[15]])),
+                    SLL_HEADER_LENGTH,
+                    source_mac,
+                )
+            }
         };
 
-        match ipv4_packet.get_next_level_protocol() {
-            IpNextHeaderProtocols::Tcp => (offset += 20),
-            _ => return Err("Not TCP packet!"),
+        // Skip over any 802.1Q or 802.1ad tags
+        while ethertype == EtherTypes::Vlan
+            || ethertype == EtherTypes::QinQ
+            || ethertype == ETHERTYPE_SERVICE_VLAN
+        {
+            let vlan_packet = match VlanPacket::new(&packet[offset..]) {
+                Some(packet) => packet,
+                None => return Err("Invalid VLAN tag!"),
+            };
+            ethertype = vlan_packet.get_ethertype();
+            offset += VLAN_TAG_LENGTH;
         }
 
-        let tcp_packet = match TcpPacket::new(&packet[offset..]) {
+        let (source_ip, destination_ip, offset, end) = match ethertype {
+            EtherTypes::Ipv4 => decode_ipv4(packet, offset)?,
+            EtherTypes::Ipv6 => decode_ipv6(packet, offset)?,
+            _ => return Err("Ethertype not supported."),
+        };
+
+        let tcp_packet = match TcpPacket::new(&packet[offset..end]) {
             Some(packet) => packet,
             None => return Err("Invalid TCP packet!"),
         };
 
-        offset += 20;
+        let data_offset = tcp_packet.get_data_offset() as usize * 4;
+        if data_offset < TcpPacket::minimum_packet_size() || data_offset > tcp_packet.packet().len()
+        {
+            return Err("Invalid TCP packet!");
+        }
 
-        Ok(EthernetIpv4TCPPacket {
-            payload: &packet[offset..],
-            ether_packet: ether_packet,
-            ipv4_packet: ipv4_packet,
-            tcp_packet: tcp_packet,
+        Ok(EthernetIpTcpPacket {
+            payload: &packet[offset + data_offset..end],
+            source_mac,
+            source_ip,
+            destination_ip,
+            tcp_packet,
         })
     }
 
-    pub fn get_source_ip(&self) -> Ipv4Addr {
-        return self.ipv4_packet.get_source();
+    pub fn get_source_ip(&self) -> IpAddr {
+        self.source_ip
     }
 
-    pub fn get_destination_ip(&self) -> Ipv4Addr {
-        return self.ipv4_packet.get_destination();
+    pub fn get_destination_ip(&self) -> IpAddr {
+        self.destination_ip
     }
 
     pub fn get_source_port(&self) -> u16 {
-        return self.tcp_packet.get_source();
+        self.tcp_packet.get_source()
     }
 
     pub fn get_destination_port(&self) -> u16 {
-        return self.tcp_packet.get_destination();
+        self.tcp_packet.get_destination()
     }
 
-    pub fn get_source_mac(&self) -> MacAddr {
-        return self.ether_packet.get_source();
+    pub fn get_sequence(&self) -> u32 {
+        self.tcp_packet.get_sequence()
+    }
+
+    pub fn get_segment(&self) -> TcpSegment<'_> {
+        let flags = self.tcp_packet.get_flags();
+        TcpSegment {
+            sequence: self.get_sequence(),
+            syn: flags & TcpFlags::SYN != 0,
+            fin: flags & TcpFlags::FIN != 0,
+            rst: flags & TcpFlags::RST != 0,
+            payload: self.payload,
+        }
+    }
+
+    pub fn get_connection(&self) -> Connection {
+        Connection::new(
+            SocketAddr::new(self.get_source_ip(), self.get_source_port()),
+            SocketAddr::new(self.get_destination_ip(), self.get_destination_port()),
+        )
+    }
+
+    pub fn get_source_mac(&self) -> Option<MacAddr> {
+        self.source_mac
     }
 
     pub fn get_payload(&self) -> &[u8] {
-        return self.payload;
+        self.payload
     }
 
     pub fn get_payload_as_ascii(&self) -> String {
-        return self
-            .payload
-            .iter()
-            .map(|byte| std::char::from_u32(*byte as u32).unwrap().to_string())
-            .collect::<String>();
+        self.payload.iter().map(|byte| *byte as char).collect()
     }
 }
 
-pub struct PacketSniffer {
-    rx: Option<Box<dyn DataLinkReceiver>>,
-    using_interface: Option<NetworkInterface>,
+// What the server list is loaded with, moved onto a thread of its own while refreshing
+struct ServerLoader {
+    providers: Vec<Box<dyn ServerProvider + Send>>,
+    resolver: Box<dyn Resolver + Send>,
+    // The IPs of every hostname the last time it resolved
+    resolved: HashMap<String, Vec<IpAddr>>,
+}
+
+struct LoadedServers {
+    ips: HashSet<IpAddr>,
+    ports: HashSet<u16>,
+    unresolved: Vec<String>,
 }
 
-impl PacketSniffer {
-    pub fn new() -> PacketSniffer {
-        PacketSniffer {
-            rx: None,
-            using_interface: None,
+type Refresh = JoinHandle<(ServerLoader, Result<LoadedServers, ServerListError>)>;
+
+impl Default for ServerLoader {
+    fn default() -> Self {
+        ServerLoader {
+            providers: Vec::new(),
+            resolver: Box::new(SystemResolver),
+            resolved: HashMap::new(),
+        }
+    }
+}
+
+impl ServerLoader {
+    // Hostnames that do not resolve keep the IPs they had before
+    fn load(&mut self) -> Result<LoadedServers, ServerListError> {
+        let mut list = ServerList::new();
+        for provider in &self.providers {
+            list.extend(provider.load()?);
+        }
+
+        let mut ips = HashSet::new();
+        let mut resolved = HashMap::new();
+        let mut unresolved = Vec::new();
+        for address in list.addresses {
+            match resolve_address(&address, self.resolver.as_ref()) {
+                Some(addresses) => {
+                    ips.extend(addresses.iter().copied());
+                    resolved.insert(address, addresses);
+                }
+                None => {
+                    if let Some(addresses) = self.resolved.remove(&address) {
+                        ips.extend(addresses.iter().copied());
+                        resolved.insert(address.clone(), addresses);
+                    }
+                    unresolved.push(address);
+                }
+            }
         }
+        self.resolved = resolved;
+
+        Ok(LoadedServers {
+            ips,
+            ports: list.ports.into_iter().collect(),
+            unresolved,
+        })
+    }
+}
+
+// The pcap file being written, with the frames of flows that may still turn out to be FSD
+struct Recording {
+    writer: PcapWriter<BufWriter<File>>,
+    // By the connection from the lower address to the higher one
+    held: HashMap<Connection, Vec<(SystemTime, u32, Vec<u8>)>>,
+}
+
+impl Recording {
+    fn write(&mut self, frame: &PcapFrame) -> io::Result<()> {
+        // Live captures usually end with Ctrl-C, so do not keep frames buffered
+        match self
+            .writer
+            .write_frame(frame)
+            .and_then(|_| self.writer.flush())
+        {
+            // Frames of another link type than the first cannot go in the same file
+            Err(error) if error.kind() == io::ErrorKind::InvalidInput => Ok(()),
+            result => result,
+        }
+    }
+
+    fn hold(&mut self, flow: Connection, frame: &PcapFrame) {
+        let held = self.held.entry(flow).or_default();
+        if held.len() < MAX_HELD_FRAMES {
+            held.push((frame.timestamp, frame.link_type, frame.data.to_vec()));
+        }
+    }
+
+    // Writes the frames held for a flow that turned out to be FSD
+    fn release(&mut self, flow: &Connection) -> io::Result<()> {
+        for (timestamp, link_type, data) in self.held.remove(flow).unwrap_or_default() {
+            self.write(&PcapFrame {
+                timestamp,
+                link_type,
+                data: &data,
+            })?;
+        }
+        Ok(())
+    }
+}
+
+pub struct Sniffer<S: CaptureSource = PacketSniffer> {
+    source: S,
+    reassembler: StreamReassembler,
+    classifier: FlowClassifier,
+    packet_queue: VecDeque<PacketSource>,
+    recording: Option<Recording>,
+    error: Option<io::Error>,
+    // Away on the refresh thread while `refresh` runs
+    loader: Option<ServerLoader>,
+    refresh: Option<Refresh>,
+    refresh_interval: Option<Duration>,
+    last_refresh: Option<Instant>,
+    pub search_ips: HashSet<IpAddr>,
+    pub search_ports: HashSet<u16>,
+}
+
+impl Sniffer {
+    pub fn new() -> Self {
+        Self::with_source(PacketSniffer::new())
     }
 
     pub fn get_available_interfaces(&self) -> Vec<NetworkInterface> {
-        return datalink::interfaces();
+        self.source.get_available_interfaces()
     }
 
     pub fn set_user_interface(&mut self, interface: &NetworkInterface) {
-        self.using_interface = Some(interface.clone());
+        self.source.set_user_interface(interface);
+    }
+
+    pub fn set_link_type(&mut self, link_type: LinkType) {
+        self.source.set_link_type(link_type);
+    }
+}
+
+impl Sniffer<PcapReader<BufReader<File>>> {
+    /// Replays a pcap or pcapng file
+    pub fn open_file<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Ok(Self::with_source(PcapReader::open(path)?))
+    }
+}
+
+impl<S: CaptureSource> Sniffer<S> {
+    pub fn with_source(source: S) -> Self {
+        Self {
+            source,
+            reassembler: StreamReassembler::new(),
+            classifier: FlowClassifier::new(),
+            loader: Some(ServerLoader::default()),
+            refresh: None,
+            refresh_interval: None,
+            last_refresh: None,
+            search_ips: HashSet::new(),
+            search_ports: HashSet::new(),
+            packet_queue: VecDeque::new(),
+            recording: None,
+            error: None,
+        }
+    }
+
+    pub fn source(&self) -> &S {
+        &self.source
+    }
+
+    pub fn source_mut(&mut self) -> &mut S {
+        &mut self.source
+    }
+
+    /// Starts capturing, `load_servers` should have been called before
+    pub fn start(&mut self) -> io::Result<()> {
+        self.source.start()
+    }
+
+    pub fn add_server_provider<P: ServerProvider + Send + 'static>(&mut self, provider: P) {
+        self.loader().providers.push(Box::new(provider));
+    }
+
+    /// Changes how hostnames in the server list are resolved, the system resolver by default
+    pub fn set_resolver<R: Resolver + Send + 'static>(&mut self, resolver: R) {
+        self.loader().resolver = Box::new(resolver);
+    }
+
+    /// Also watch flows that match no server address or port, and treat them as FSD if their
+    /// first lines parse
+    pub fn set_content_detection(&mut self, enabled: bool) {
+        self.classifier.set_content_detection(enabled);
+    }
+
+    /// Reloads the server list this often while capturing, servers move and DNS changes.
+    ///
+    /// The list is loaded and resolved on a thread of its own and swapped in once done, so
+    /// capturing never waits for a slow provider or resolver.
+    pub fn set_refresh_interval(&mut self, interval: Option<Duration>) {
+        self.refresh_interval = interval;
+    }
+
+    /// Saves every frame to or from a server as a pcap file, keeping the capture timestamps
+    pub fn record_to_file<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+        self.stop_recording()?;
+        self.recording = Some(Recording {
+            writer: PcapWriter::create(path)?,
+            held: HashMap::new(),
+        });
+        Ok(())
+    }
+
+    pub fn stop_recording(&mut self) -> io::Result<()> {
+        match self.recording.take() {
+            Some(mut recording) => recording.writer.finish(),
+            None => Ok(()),
+        }
+    }
+
+    /// The error that ended the capture, if it did not simply run out of frames
+    pub fn take_error(&mut self) -> Option<io::Error> {
+        self.error.take()
+    }
+
+    /// Reads frames until one completes a packet, `None` once the source is exhausted or fails
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Option<PacketSource> {
+        while self.packet_queue.is_empty() {
+            match self.read_frame() {
+                Ok(true) => (),
+                Ok(false) => break,
+                Err(error) => {
+                    self.error = Some(error);
+                    break;
+                }
+            }
+        }
+
+        self.packet_queue.pop_front()
     }
 
-    pub fn start(&mut self) {
-        // Establish link
-        if let None = self.using_interface {
-            panic!("No interface.");
+    // Reads and processes one frame, false once the source is exhausted. A source timing out
+    // is not an error, it only gives the caller a chance to look around.
+    fn read_frame(&mut self) -> io::Result<bool> {
+        self.refresh_servers();
+        match self.source.next_frame() {
+            Ok(Some(frame)) => {
+                Self::process_frame(
+                    &frame,
+                    &self.search_ips,
+                    &self.search_ports,
+                    &mut self.reassembler,
+                    &mut self.classifier,
+                    &mut self.recording,
+                    &mut self.packet_queue,
+                );
+                Ok(true)
+            }
+            Ok(None) => Ok(false),
+            Err(error)
+                if matches!(
+                    error.kind(),
+                    io::ErrorKind::TimedOut
+                        | io::ErrorKind::WouldBlock
+                        | io::ErrorKind::Interrupted
+                ) =>
+            {
+                Ok(true)
+            }
+            Err(error) => Err(error),
         }
+    }
 
-        self.rx =
-            match datalink::channel(self.using_interface.as_ref().unwrap(), Default::default()) {
-                Ok(channel) => match channel {
-                    Channel::Ethernet(_, rx) => Some(rx),
-                    _ => panic!("Unhandled channel type."),
-                },
-                Err(_) => panic!("Error opening interface."),
+    // Captures until stopped or the source runs out or fails. `deliver` returns false once
+    // nobody is listening anymore.
+    fn run<F: FnMut(PacketSource) -> bool>(&mut self, stop: &AtomicBool, mut deliver: F) {
+        loop {
+            while let Some(packet) = self.packet_queue.pop_front() {
+                if !deliver(packet) {
+                    return;
+                }
             }
+            if stop.load(Ordering::SeqCst) {
+                return;
+            }
+            match self.read_frame() {
+                Ok(true) => (),
+                Ok(false) => return,
+                Err(error) => {
+                    self.error = Some(error);
+                    return;
+                }
+            }
+        }
     }
 
-    pub fn next(&mut self) -> Option<EthernetIpv4TCPPacket> {
-        if let Ok(packet) = self.rx.as_mut().unwrap().next() {
-            return match EthernetIpv4TCPPacket::new(packet) {
-                Ok(full_packet) => Some(full_packet),
-                Err(_) => None,
+    // Takes the fields separately as the frame still borrows the source
+    fn process_frame(
+        frame: &PcapFrame,
+        search_ips: &HashSet<IpAddr>,
+        search_ports: &HashSet<u16>,
+        reassembler: &mut StreamReassembler,
+        classifier: &mut FlowClassifier,
+        recording: &mut Option<Recording>,
+        packet_queue: &mut VecDeque<PacketSource>,
+    ) {
+        let packet = match LinkType::from_pcap(frame.link_type)
+            .and_then(|link_type| EthernetIpTcpPacket::with_link_type(frame.data, link_type).ok())
+        {
+            Some(packet) => packet,
+            None => return,
+        };
+
+        let from_server = search_ips.contains(&packet.get_source_ip())
+            || search_ports.contains(&packet.get_source_port());
+        let to_server = search_ips.contains(&packet.get_destination_ip())
+            || search_ports.contains(&packet.get_destination_port());
+        let connection = packet.get_connection();
+        let matched = match from_server || to_server {
+            true => Some(from_server),
+            false => None,
+        };
+        if !classifier.wants(&connection, matched.is_some()) {
+            return;
+        }
+
+        let segment = packet.get_segment();
+        let lines = reassembler.process(connection, &segment);
+        packet_queue.extend(classifier.process(&connection, frame.timestamp, &lines, matched));
+
+        // The frames that got content detection to decide on a flow are recorded as well
+        let flow = match connection.source < connection.destination {
+            true => connection,
+            false => connection.reversed(),
+        };
+        let decided = matched.is_some() || classifier.is_fsd(&connection);
+        let finished = classifier.is_rejected(&connection) || segment.fin || segment.rst;
+        if let Some(file) = recording.as_mut() {
+            let written = match decided {
+                true => file.release(&flow).and_then(|_| file.write(frame)),
+                false => {
+                    file.hold(flow, frame);
+                    Ok(())
+                }
             };
-        } else {
-            panic!("Error reading interface!")
+            if finished {
+                file.held.remove(&flow);
+            }
+            // A failing disk is not worth losing the capture over
+            if written.is_err() {
+                *recording = None;
+            }
+        }
+
+        if classifier.is_rejected(&connection) {
+            reassembler.remove(&connection);
+            reassembler.remove(&connection.reversed());
+        }
+        if segment.fin || segment.rst {
+            classifier.remove(&connection);
+        }
+    }
+
+    /// Loads the server list from every provider added with `add_server_provider` and
+    /// replaces `search_ips` and `search_ports` with it.
+    ///
+    /// Hostnames that do not resolve are reported as `Unresolved` after the rest is applied,
+    /// keeping the IPs they resolved to last time. A provider failing leaves the previous list
+    /// in place.
+    pub fn load_servers(&mut self) -> Result<(), ServerListError> {
+        self.last_refresh = Some(Instant::now());
+        let loaded = self.loader().load();
+        self.apply_servers(loaded)
+    }
+
+    fn apply_servers(
+        &mut self,
+        loaded: Result<LoadedServers, ServerListError>,
+    ) -> Result<(), ServerListError> {
+        let servers = loaded?;
+        self.search_ips = servers.ips;
+        self.search_ports = servers.ports;
+
+        match servers.unresolved.is_empty() {
+            true => Ok(()),
+            false => Err(ServerListError::Unresolved(servers.unresolved)),
+        }
+    }
+
+    // The loader, once a refresh running in the background has handed it back
+    fn loader(&mut self) -> &mut ServerLoader {
+        let _ = self.finish_refresh(true);
+        self.loader.get_or_insert_with(ServerLoader::default)
+    }
+
+    // Applies the result of a background refresh once it is done, or waits for it
+    fn finish_refresh(&mut self, wait: bool) -> Result<(), ServerListError> {
+        let refresh = match self.refresh.take() {
+            Some(refresh) if wait || refresh.is_finished() => refresh,
+            refresh => {
+                self.refresh = refresh;
+                return Ok(());
+            }
+        };
+        let (loader, loaded) = match refresh.join() {
+            Ok(done) => done,
+            Err(payload) => panic::resume_unwind(payload),
+        };
+        self.loader = Some(loader);
+        self.apply_servers(loaded)
+    }
+
+    fn refresh_servers(&mut self) {
+        // Keep capturing with what resolved, the next refresh may do better
+        let _ = self.finish_refresh(false);
+
+        if let (Some(interval), Some(last_refresh)) = (self.refresh_interval, self.last_refresh) {
+            if last_refresh.elapsed() >= interval {
+                if let Some(mut loader) = self.loader.take() {
+                    self.last_refresh = Some(Instant::now());
+                    self.refresh = Some(thread::spawn(move || {
+                        let loaded = loader.load();
+                        (loader, loaded)
+                    }));
+                }
+            }
         }
     }
 }
 
-const VATSIM_SERVER_FEED: &str = "https://data.vatsim.net/v3/vatsim-data.json";
+impl Default for Sniffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<S: CaptureSource + Send + 'static> Sniffer<S> {
+    /// Starts the source and moves the sniffer onto its own capture thread. Up to `capacity`
+    /// packets are held for the consumer, later ones are dropped until it catches up.
+    pub fn spawn(self, capacity: usize) -> io::Result<SnifferThread<S>> {
+        let (sender, receiver) = mpsc::sync_channel(capacity);
+        Ok(SnifferThread {
+            receiver,
+            capture: self.spawn_with(move |packet| match sender.try_send(packet) {
+                Ok(_) => Delivery::Sent,
+                Err(TrySendError::Full(_)) => Delivery::Full,
+                Err(TrySendError::Disconnected(_)) => Delivery::Closed,
+            })?,
+        })
+    }
+
+    // Runs the capture on a new thread, `send` hands packets over without blocking
+    pub(crate) fn spawn_with<F>(mut self, mut send: F) -> io::Result<CaptureThread<S>>
+    where
+        F: FnMut(PacketSource) -> Delivery + Send + 'static,
+    {
+        self.start()?;
+
+        let stop = StopHandle::default();
+        let dropped = Arc::new(AtomicU64::new(0));
+        let thread = {
+            let stop = stop.clone();
+            let dropped = dropped.clone();
+            thread::Builder::new()
+                .name("fsd-sniffer".to_string())
+                .spawn(move || {
+                    // A slow consumer should not hold up reading the interface
+                    self.run(&stop.0, |packet| match send(packet) {
+                        Delivery::Sent => true,
+                        Delivery::Full => {
+                            dropped.fetch_add(1, Ordering::Relaxed);
+                            true
+                        }
+                        Delivery::Closed => false,
+                    });
+                    self
+                })?
+        };
+
+        Ok(CaptureThread {
+            stop,
+            dropped,
+            thread: Some(thread),
+        })
+    }
+}
+
+// What became of a packet handed to the consumer's channel
+pub(crate) enum Delivery {
+    Sent,
+    Full,
+    Closed,
+}
 
-#[derive(Deserialize)]
-struct DataFeed {
-    servers: Vec<Server>,
+/// Stops a `SnifferThread`, can be cloned and handed to other threads
+#[derive(Debug, Clone, Default)]
+pub struct StopHandle(Arc<AtomicBool>);
+
+impl StopHandle {
+    pub fn stop(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
 }
 
-#[derive(Deserialize)]
-struct Server {
-    hostname_or_ip: String,
+// The thread behind `SnifferThread` and friends, whatever channel they use
+pub(crate) struct CaptureThread<S: CaptureSource> {
+    stop: StopHandle,
+    dropped: Arc<AtomicU64>,
+    thread: Option<JoinHandle<Sniffer<S>>>,
 }
 
-#[derive(Debug)]
-pub enum PacketSource {
-    Server(PacketTypes),
-    Client(PacketTypes),
+impl<S: CaptureSource> CaptureThread<S> {
+    pub(crate) fn stop_handle(&self) -> StopHandle {
+        self.stop.clone()
+    }
+
+    pub(crate) fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn is_finished(&self) -> bool {
+        self.thread.as_ref().is_none_or(JoinHandle::is_finished)
+    }
+
+    pub(crate) fn join(mut self) -> Sniffer<S> {
+        self.stop.stop();
+        let thread = self
+            .thread
+            .take()
+            .expect("Capture thread was already joined");
+        match thread.join() {
+            Ok(sniffer) => sniffer,
+            Err(panic) => std::panic::resume_unwind(panic),
+        }
+    }
 }
 
-pub struct Sniffer {
-    sniffer: PacketSniffer,
-    packet_queue: VecDeque<PacketSource>,
-    pub search_ips: HashSet<String>,
+impl<S: CaptureSource> Drop for CaptureThread<S> {
+    fn drop(&mut self) {
+        self.stop.stop();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
 }
 
-impl Sniffer {
-    pub fn new() -> Self {
-        return Self {
-            sniffer: PacketSniffer::new(),
-            search_ips: HashSet::new(),
-            packet_queue: VecDeque::new(),
-        };
+/// A `Sniffer` capturing on a background thread, created with `Sniffer::spawn`.
+///
+/// Iterating blocks until the next packet and ends once the capture has stopped, the source
+/// ran out or failed. Dropping it stops the capture.
+pub struct SnifferThread<S: CaptureSource> {
+    receiver: Receiver<PacketSource>,
+    capture: CaptureThread<S>,
+}
+
+impl<S: CaptureSource> SnifferThread<S> {
+    pub fn receiver(&self) -> &Receiver<PacketSource> {
+        &self.receiver
     }
 
-    pub fn start(&mut self) {
-        self.load_server_ips();
-        self.sniffer.start();
+    pub fn stop_handle(&self) -> StopHandle {
+        self.capture.stop_handle()
     }
 
-    pub fn get_available_interfaces(&self) -> Vec<NetworkInterface> {
-        return self.sniffer.get_available_interfaces();
+    /// Asks the capture thread to stop, packets already captured can still be received
+    pub fn stop(&self) {
+        self.capture.stop.stop();
     }
 
-    pub fn set_user_interface(&mut self, interface: &NetworkInterface) {
-        self.sniffer.set_user_interface(interface);
+    /// Packets thrown away because the consumer fell behind
+    pub fn dropped(&self) -> u64 {
+        self.capture.dropped()
     }
 
-    pub fn next(&mut self) -> Option<PacketSource> {
-        if self.packet_queue.len() > 0 {
-            return self.packet_queue.pop_front();
-        }
-
-        let packet = self.sniffer.next();
-        match packet {
-            Some(packet) => {
-                let from_server = self
-                    .search_ips
-                    .contains(&packet.get_source_ip().to_string());
-                if from_server
-                    || self
-                        .search_ips
-                        .contains(&packet.get_destination_ip().to_string())
-                {
-                    let text = &packet.get_payload_as_ascii();
-                    for payload in text.split("\n") {
-                        if let Some(packet) = Parser::parse(payload) {
-                            if from_server {
-                                self.packet_queue.push_back(PacketSource::Server(packet));
-                            } else {
-                                self.packet_queue.push_back(PacketSource::Client(packet));
-                            }
-                        }
-                    }
-                }
+    pub fn is_finished(&self) -> bool {
+        self.capture.is_finished()
+    }
+
+    /// Stops capturing and hands back the sniffer, e.g. to stop recording or take its error
+    pub fn join(self) -> Sniffer<S> {
+        self.capture.join()
+    }
+}
+
+impl<S: CaptureSource> Iterator for SnifferThread<S> {
+    type Item = PacketSource;
+
+    fn next(&mut self) -> Option<PacketSource> {
+        self.receiver.recv().ok()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::capture::MemoryCapture;
+    use crate::flows::Direction;
+    use crate::parser::PacketTypes;
+    use crate::servers::ServerList;
+
+    const LINE: &[u8] = b"#TMA:B:hi\r\n";
+
+    // IPv4 + TCP with the given amount of TCP option bytes, followed by LINE
+    fn ip_tcp(tcp_options: usize) -> Vec<u8> {
+        let tcp_length = 20 + tcp_options;
+        let total_length = 20 + tcp_length + LINE.len();
+        let mut packet = vec![
+            0x45,
+            0,
+            (total_length >> 8) as u8,
+            total_length as u8,
+            0,
+            0,
+            0x40,
+            0,
+            64,
+            6,
+            0,
+            0,
+            10,
+            0,
+            0,
+            2,
+            10,
+            0,
+            0,
+            1,
+        ];
+        packet.extend_from_slice(&[0xc3, 0x50, 0x1a, 0x99, 0, 0, 0, 1, 0, 0, 0, 0]);
+        packet.push(((tcp_length / 4) as u8) << 4);
+        packet.extend_from_slice(&[0x18, 0xff, 0xff, 0, 0, 0, 0]);
+        packet.resize(packet.len() + tcp_options, 1);
+        packet.extend_from_slice(LINE);
+        packet
+    }
+
+    fn ethernet(ethertype: &[u8], rest: &[u8]) -> Vec<u8> {
+        let mut frame = vec![0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 2];
+        frame.extend_from_slice(ethertype);
+        frame.extend_from_slice(rest);
+        frame
+    }
+
+    #[test]
+    fn test_tcp_options() {
+        let frame = ethernet(&[0x08, 0x00], &ip_tcp(12));
+        let packet = EthernetIpTcpPacket::new(&frame).unwrap();
+        assert_eq!(packet.get_payload(), LINE);
+        assert_eq!(packet.get_source_ip(), IpAddr::from([10, 0, 0, 2]));
+        assert_eq!(packet.get_destination_port(), 6809);
+        assert_eq!(
+            packet.get_source_mac(),
+            Some(MacAddr::new(0, 0, 0, 0, 0, 2))
+        );
+    }
+
+    #[test]
+    fn test_ethernet_padding() {
+        let mut frame = ethernet(&[0x08, 0x00], &ip_tcp(0));
+        frame.extend_from_slice(&[0; 6]);
+        let packet = EthernetIpTcpPacket::new(&frame).unwrap();
+        assert_eq!(packet.get_payload(), LINE);
+    }
+
+    #[test]
+    fn test_vlan_tags() {
+        let mut tagged = vec![0x00, 0x64, 0x81, 0x00, 0x00, 0x0a, 0x08, 0x00];
+        tagged.extend_from_slice(&ip_tcp(12));
+        let frame = ethernet(&[0x88, 0xa8], &tagged);
+        let packet = EthernetIpTcpPacket::new(&frame).unwrap();
+        assert_eq!(packet.get_payload(), LINE);
+    }
+
+    #[test]
+    fn test_linux_cooked() {
+        let mut frame = vec![0, 0, 0, 1, 0, 6, 0, 0, 0, 0, 0, 2, 0, 0, 0x08, 0x00];
+        frame.extend_from_slice(&ip_tcp(12));
+        let packet = EthernetIpTcpPacket::with_link_type(&frame, LinkType::LinuxSll).unwrap();
+        assert_eq!(packet.get_payload(), LINE);
+        assert_eq!(
+            packet.get_source_mac(),
+            Some(MacAddr::new(0, 0, 0, 0, 0, 2))
+        );
+    }
+
+    // IPv6 with a hop-by-hop and a destination options header in front of TCP
+    fn ipv6_tcp() -> Vec<u8> {
+        let tcp = &ip_tcp(12)[20..];
+        let payload_length = 8 + 16 + tcp.len();
+        let mut packet = vec![0x60, 0, 0, 0, 0, payload_length as u8, 0, 64];
+        packet.extend_from_slice(&[0x20, 0x01, 0x0d, 0xb8]);
+        packet.extend_from_slice(&[0; 11]);
+        packet.push(2);
+        packet.extend_from_slice(&[0x20, 0x01, 0x0d, 0xb8]);
+        packet.extend_from_slice(&[0; 11]);
+        packet.push(1);
+        packet.extend_from_slice(&[60, 0, 1, 4, 0, 0, 0, 0]);
+        packet.extend_from_slice(&[6, 1, 1, 12]);
+        packet.extend_from_slice(&[0; 12]);
+        packet.extend_from_slice(tcp);
+        packet
+    }
+
+    #[test]
+    fn test_ipv6_extension_headers() {
+        let frame = ethernet(&[0x86, 0xdd], &ipv6_tcp());
+        let packet = EthernetIpTcpPacket::new(&frame).unwrap();
+        assert_eq!(packet.get_payload(), LINE);
+        assert_eq!(
+            packet.get_source_ip(),
+            "2001:db8::2".parse::<IpAddr>().unwrap()
+        );
+        assert_eq!(
+            packet.get_connection().destination,
+            "[2001:db8::1]:6809".parse().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_ipv6_not_tcp() {
+        let mut packet = ipv6_tcp();
+        // Destination options header now says UDP follows
+        packet[48] = 17;
+        assert!(EthernetIpTcpPacket::new(&ethernet(&[0x86, 0xdd], &packet)).is_err());
+    }
+
+    #[test]
+    fn test_capture_file() {
+        let mut file = Vec::new();
+        for value in &[0xa1b2_c3d4u32, 0x0004_0002, 0, 0, 65535, 1] {
+            file.extend_from_slice(&value.to_le_bytes());
+        }
+        let frames = [
+            ethernet(&[0x08, 0x00], &ip_tcp(12)),
+            ethernet(&[0x08, 0x06], &[0; 28]),
+            ethernet(&[0x08, 0x00], &ip_tcp(0)),
+        ];
+        for frame in &frames {
+            for value in &[1, 0, frame.len() as u32, frame.len() as u32] {
+                file.extend_from_slice(&value.to_le_bytes());
             }
-            None => (),
+            file.extend_from_slice(frame);
+        }
+        let path = std::env::temp_dir().join(format!("fsdparser-{}.pcap", std::process::id()));
+        std::fs::write(&path, file).unwrap();
+
+        let recorded = path.with_extension("recorded.pcap");
+
+        let mut sniffer = Sniffer::open_file(&path).unwrap();
+        sniffer.search_ips.insert([10, 0, 0, 1].into());
+        sniffer.record_to_file(&recorded).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        // Both frames carry the same sequence number, so the second is a retransmission
+        let packet = sniffer.next().unwrap();
+        assert!(matches!(packet.packet, PacketTypes::TextMessage(_)));
+        assert_eq!(
+            packet.timestamp,
+            std::time::UNIX_EPOCH + std::time::Duration::new(1, 0)
+        );
+        assert!(sniffer.next().is_none());
+        sniffer.stop_recording().unwrap();
+
+        // The ARP frame is not to or from a server
+        let mut reader = PcapReader::open(&recorded).unwrap();
+        for expected in &[&frames[0], &frames[2]] {
+            let frame = reader.next_frame().unwrap().unwrap();
+            assert_eq!(frame.data, &expected[..]);
+            assert_eq!(
+                frame.timestamp,
+                std::time::UNIX_EPOCH + std::time::Duration::new(1, 0)
+            );
+        }
+        assert!(reader.next_frame().unwrap().is_none());
+        std::fs::remove_file(&recorded).unwrap();
+    }
+
+    // Ethernet + IPv4 + TCP frame carrying payload
+    fn frame(source: [u8; 4], destination: [u8; 4], sequence: u32, payload: &[u8]) -> Vec<u8> {
+        let mut packet = ip_tcp(0);
+        packet.truncate(packet.len() - LINE.len());
+        let total_length = (packet.len() + payload.len()) as u16;
+        packet[2..4].copy_from_slice(&total_length.to_be_bytes());
+        packet[12..16].copy_from_slice(&source);
+        packet[16..20].copy_from_slice(&destination);
+        if source == [10, 0, 0, 1] {
+            packet[20..24].copy_from_slice(&[0x1a, 0x99, 0xc3, 0x50]);
+        }
+        packet[24..28].copy_from_slice(&sequence.to_be_bytes());
+        packet.extend_from_slice(payload);
+        ethernet(&[0x08, 0x00], &packet)
+    }
+
+    fn memory_sniffer(frames: Vec<Vec<u8>>) -> Sniffer<MemoryCapture> {
+        let mut capture = MemoryCapture::new();
+        for (index, frame) in frames.into_iter().enumerate() {
+            let timestamp = std::time::UNIX_EPOCH + Duration::from_secs(index as u64);
+            capture.push(timestamp, LinkType::Ethernet, frame);
         }
+        let mut sniffer = Sniffer::with_source(capture);
+        sniffer.search_ips.insert([10, 0, 0, 1].into());
+        sniffer
+    }
+
+    #[test]
+    fn test_server_and_client() {
+        let server = [10, 0, 0, 1];
+        let client = [10, 0, 0, 2];
+        let mut sniffer = memory_sniffer(vec![
+            frame(client, server, 0, b"#TMA:B:from client\r\n"),
+            frame([10, 0, 0, 3], [10, 0, 0, 4], 0, b"#TMC:D:unrelated\r\n"),
+            frame(server, client, 0, b"#TMB:A:from server\r\n"),
+        ]);
+
+        let packet = sniffer.next().unwrap();
+        assert_eq!(packet.direction, Direction::ClientToServer);
+        assert!(matches!(packet.packet, PacketTypes::TextMessage(_)));
+        assert_eq!(packet.raw, "#TMA:B:from client");
+        assert_eq!(packet.connection.source, "10.0.0.2:50000".parse().unwrap());
+        assert_eq!(packet.timestamp, std::time::UNIX_EPOCH);
+
+        let packet = sniffer.next().unwrap();
+        assert_eq!(packet.direction, Direction::ServerToClient);
+        assert_eq!(packet.raw, "#TMB:A:from server");
+        assert_eq!(packet.connection.source, "10.0.0.1:6809".parse().unwrap());
+        assert_eq!(
+            packet.timestamp,
+            std::time::UNIX_EPOCH + Duration::from_secs(2)
+        );
+        assert!(sniffer.next().is_none());
+        assert!(sniffer.take_error().is_none());
+    }
+
+    #[test]
+    fn test_server_providers() {
+        let mut sniffer = memory_sniffer(vec![frame(
+            [10, 0, 0, 5],
+            [10, 0, 0, 6],
+            0,
+            b"#TMA:B:private server\r\n",
+        )]);
+        sniffer.search_ips.clear();
+        sniffer.add_server_provider(ServerList::from_addresses(vec!["2001:DB8:0::1"]));
+        sniffer.add_server_provider(crate::servers::PortDetection::default());
+        sniffer.load_servers().unwrap();
+
+        assert!(sniffer
+            .search_ips
+            .contains(&"2001:db8::1".parse::<IpAddr>().unwrap()));
+        // Neither address is listed, the destination port gives the server away
+        assert!(matches!(
+            sniffer.next(),
+            Some(PacketSource {
+                direction: Direction::ClientToServer,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_content_detection() {
+        let client = [192, 168, 1, 2];
+        let server = [192, 168, 1, 3];
+        let mut sniffer = memory_sniffer(vec![
+            frame(client, server, 0, b"#TMA:B:one\r\n"),
+            frame(client, server, 12, b"#TMA:B:two\r\n"),
+            frame(client, [192, 168, 1, 4], 0, b"GET / HTTP/1.1\r\n"),
+        ]);
+        sniffer.set_content_detection(true);
+
+        assert!(matches!(
+            sniffer.next(),
+            Some(PacketSource {
+                direction: Direction::ClientToServer,
+                ..
+            })
+        ));
+        assert!(matches!(
+            sniffer.next(),
+            Some(PacketSource {
+                direction: Direction::ClientToServer,
+                ..
+            })
+        ));
+        assert!(sniffer.next().is_none());
+    }
+
+    #[test]
+    fn test_record_content_detection() {
+        let client = [192, 168, 1, 2];
+        let server = [192, 168, 1, 3];
+        let frames = vec![
+            frame(client, server, 0, b"#TMA:B:one\r\n"),
+            frame(client, [192, 168, 1, 4], 0, b"GET / HTTP/1.1\r\n"),
+            frame(client, server, 12, b"#TMA:B:two\r\n"),
+        ];
+        let path =
+            std::env::temp_dir().join(format!("fsdparser-{}-detect.pcap", std::process::id()));
+
+        let mut sniffer = memory_sniffer(frames.clone());
+        sniffer.set_content_detection(true);
+        sniffer.record_to_file(&path).unwrap();
+        while sniffer.next().is_some() {}
+        sniffer.stop_recording().unwrap();
+
+        // The first line was only known to be FSD once the second one parsed
+        let mut reader = PcapReader::open(&path).unwrap();
+        for expected in &[&frames[0], &frames[2]] {
+            assert_eq!(reader.next_frame().unwrap().unwrap().data, &expected[..]);
+        }
+        assert!(reader.next_frame().unwrap().is_none());
+
+        // Without any server traffic the file is still readable
+        let mut sniffer = memory_sniffer(vec![frames[1].clone()]);
+        sniffer.record_to_file(&path).unwrap();
+        assert!(sniffer.next().is_none());
+        sniffer.stop_recording().unwrap();
+        assert!(PcapReader::open(&path)
+            .unwrap()
+            .next_frame()
+            .unwrap()
+            .is_none());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_refresh_servers() {
+        let client = [10, 0, 0, 2];
+        let mut sniffer = memory_sniffer(vec![
+            frame(client, [10, 0, 0, 7], 0, b"#TMA:B:old address\r\n"),
+            frame(client, [10, 0, 0, 7], 20, b"#TMA:B:still resolving\r\n"),
+            frame(client, [10, 0, 0, 8], 0, b"#TMA:B:new address\r\n"),
+        ]);
 
-        return self.packet_queue.pop_front();
+        let current = Arc::new(std::sync::Mutex::new(Some([10, 0, 0, 7])));
+        let resolved = current.clone();
+        sniffer.set_resolver(move |_: &str| match *resolved.lock().unwrap() {
+            Some(ip) => Ok(vec![IpAddr::from(ip)]),
+            None => Err(io::Error::new(io::ErrorKind::NotFound, "No such host")),
+        });
+        sniffer.add_server_provider(ServerList::from_addresses(vec!["fsd.example.net"]));
+        sniffer.set_refresh_interval(Some(Duration::from_secs(0)));
+        sniffer.load_servers().unwrap();
+        assert_eq!(sniffer.search_ips.len(), 1);
+
+        // The refresh is stuck resolving, capturing goes on with the old address
+        let mut resolving = current.lock().unwrap();
+        assert!(sniffer.next().is_some());
+        assert!(sniffer.next().is_some());
+        *resolving = Some([10, 0, 0, 8]);
+        drop(resolving);
+        sniffer.finish_refresh(true).unwrap();
+        assert!(sniffer.next().is_some());
+        assert!(sniffer.search_ips.contains(&IpAddr::from([10, 0, 0, 8])));
+
+        // A failed lookup keeps the address from the last one that worked
+        *current.lock().unwrap() = None;
+        assert!(matches!(
+            sniffer.load_servers(),
+            Err(ServerListError::Unresolved(_))
+        ));
+        assert_eq!(
+            sniffer.search_ips,
+            HashSet::from([IpAddr::from([10, 0, 0, 8])])
+        );
+    }
+
+    #[test]
+    fn test_lines_across_frames() {
+        let server = [10, 0, 0, 1];
+        let client = [10, 0, 0, 2];
+        let mut sniffer = memory_sniffer(vec![
+            frame(client, server, 0, b"#TMA:B:hel"),
+            frame(client, server, 10, b"lo\r\n#TMA:B:second\r\n#TMA"),
+            frame(client, server, 33, b":B:unterminated"),
+        ]);
+
+        let mut texts = Vec::new();
+        while let Some(PacketSource {
+            packet: PacketTypes::TextMessage(message),
+            ..
+        }) = sniffer.next()
+        {
+            texts.push(message.text.into_owned());
+        }
+        assert_eq!(texts, vec!["hello", "second"]);
+        assert!(sniffer.source().is_empty());
+    }
+
+    #[test]
+    fn test_spawn() {
+        let server = [10, 0, 0, 1];
+        let client = [10, 0, 0, 2];
+        let frames = vec![
+            frame(client, server, 0, b"#TMA:B:one\r\n"),
+            frame(server, client, 0, b"#TMB:A:two\r\n"),
+            frame(client, server, 12, b"#TMA:B:three\r\n"),
+        ];
+
+        let mut capture = memory_sniffer(frames.clone()).spawn(16).unwrap();
+        let raw: Vec<String> = capture.by_ref().map(|packet| packet.raw).collect();
+        assert_eq!(raw, vec!["#TMA:B:one", "#TMB:A:two", "#TMA:B:three"]);
+        assert_eq!(capture.dropped(), 0);
+        assert!(capture.join().take_error().is_none());
+
+        // Nobody receives, so everything after the first packet is dropped
+        let capture = memory_sniffer(frames).spawn(1).unwrap();
+        while !capture.is_finished() {
+            thread::sleep(Duration::from_millis(1));
+        }
+        assert_eq!(capture.dropped(), 2);
+        assert_eq!(capture.receiver().try_iter().count(), 1);
     }
 
-    fn get_servers(&self) -> Vec<Server> {
-        let response =
-            requests::get(VATSIM_SERVER_FEED).expect("Could not retrieve VATSIM server list!");
+    // Never has a frame, like a live interface with no traffic
+    struct Quiet;
 
-        if !response.status_code().is_success() {
-            panic!("Could not retrieve VATSIM server list!");
+    impl CaptureSource for Quiet {
+        fn next_frame(&mut self) -> io::Result<Option<PcapFrame<'_>>> {
+            thread::sleep(Duration::from_millis(1));
+            Err(io::Error::new(io::ErrorKind::TimedOut, "no frame"))
         }
+    }
 
-        let data = response
-            .text()
-            .and_then(|x| serde_json::from_str::<DataFeed>(x).ok())
-            .expect("Could not deserialize VATSIM server list!");
+    #[test]
+    fn test_stop() {
+        let mut capture = Sniffer::with_source(Quiet).spawn(1).unwrap();
+        let stop = capture.stop_handle();
+        let stopper = thread::spawn(move || stop.stop());
 
-        return data.servers;
+        assert!(capture.next().is_none());
+        stopper.join().unwrap();
+        assert!(capture.join().take_error().is_none());
     }
 
-    fn load_server_ips(&mut self) {
-        let servers = self.get_servers();
-        self.search_ips
-            .extend(servers.into_iter().map(|s| s.hostname_or_ip));
+    #[cfg(feature = "tokio")]
+    #[tokio::test]
+    async fn test_stream() {
+        use futures_core::Stream;
+        use std::future::poll_fn;
+        use std::pin::Pin;
+
+        let server = [10, 0, 0, 1];
+        let client = [10, 0, 0, 2];
+        let mut stream = memory_sniffer(vec![
+            frame(client, server, 0, b"#TMA:B:one\r\n"),
+            frame(server, client, 0, b"#TMB:A:two\r\n"),
+        ])
+        .into_stream(4)
+        .unwrap();
+
+        let mut raw = Vec::new();
+        while let Some(packet) = poll_fn(|cx| Pin::new(&mut stream).poll_next(cx)).await {
+            raw.push(packet.raw);
+        }
+        assert_eq!(raw, vec!["#TMA:B:one", "#TMB:A:two"]);
+        assert_eq!(stream.dropped(), 0);
+    }
+
+    #[test]
+    fn test_invalid_offsets() {
+        let mut packet = ip_tcp(0);
+        packet[32] = 0xf0;
+        assert!(EthernetIpTcpPacket::new(&ethernet(&[0x08, 0x00], &packet)).is_err());
+
+        let mut packet = ip_tcp(0);
+        packet[0] = 0x44;
+        assert!(EthernetIpTcpPacket::new(&ethernet(&[0x08, 0x00], &packet)).is_err());
     }
 }
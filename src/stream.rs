@@ -0,0 +1,67 @@
+#![cfg(all(feature = "tokio", feature = "sniffer"))]
+use crate::capture::CaptureSource;
+use crate::flows::PacketSource;
+use crate::sniffer::{CaptureThread, Delivery, Sniffer, StopHandle};
+use futures_core::Stream;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::sync::mpsc;
+use tokio::sync::mpsc::error::TrySendError;
+
+impl<S: CaptureSource + Send + 'static> Sniffer<S> {
+    /// Like `spawn`, but the packets come out of an async `Stream`. `capacity` must not be 0.
+    pub fn into_stream(self, capacity: usize) -> io::Result<SnifferStream<S>> {
+        let (sender, receiver) = mpsc::channel(capacity);
+        let capture = self.spawn_with(move |packet| match sender.try_send(packet) {
+            Ok(_) => Delivery::Sent,
+            Err(TrySendError::Full(_)) => Delivery::Full,
+            Err(TrySendError::Closed(_)) => Delivery::Closed,
+        })?;
+        Ok(SnifferStream { receiver, capture })
+    }
+}
+
+/// Packets of a `Sniffer` capturing on a background thread, created with
+/// `Sniffer::into_stream`.
+///
+/// The stream ends once the capture has stopped, the source ran out or failed. Dropping it
+/// stops the capture.
+pub struct SnifferStream<S: CaptureSource> {
+    receiver: mpsc::Receiver<PacketSource>,
+    capture: CaptureThread<S>,
+}
+
+impl<S: CaptureSource> SnifferStream<S> {
+    pub fn stop_handle(&self) -> StopHandle {
+        self.capture.stop_handle()
+    }
+
+    /// Asks the capture thread to stop, packets already captured can still be received
+    pub fn stop(&self) {
+        self.capture.stop_handle().stop();
+    }
+
+    /// Packets thrown away because the consumer fell behind
+    pub fn dropped(&self) -> u64 {
+        self.capture.dropped()
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.capture.is_finished()
+    }
+
+    /// Stops capturing and hands back the sniffer. This waits for the capture thread, so
+    /// inside a runtime it belongs in `spawn_blocking`.
+    pub fn join(self) -> Sniffer<S> {
+        self.capture.join()
+    }
+}
+
+impl<S: CaptureSource> Stream for SnifferStream<S> {
+    type Item = PacketSource;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<PacketSource>> {
+        self.get_mut().receiver.poll_recv(cx)
+    }
+}
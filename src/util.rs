@@ -1,3 +1,4 @@
+use crate::error::ParseError;
 use serde::{Deserialize, Serialize};
 use serde_json::{self, Value};
 use std::collections::HashMap;
@@ -8,14 +9,22 @@ pub struct Frequency {
 }
 
 impl Frequency {
-    pub fn from_packet_string(data: &str) -> Self {
-        return Frequency {
+    pub fn from_packet_string(data: &str) -> Option<Self> {
+        if data.len() < 3 || !data.bytes().all(|x| x.is_ascii_digit()) {
+            return None;
+        }
+
+        Some(Frequency {
             text: format!("1{}.{}", &data[0..2], &data[2..]),
-        };
+        })
+    }
+
+    pub fn to_packet_string(&self) -> String {
+        self.text.chars().skip(1).filter(|c| *c != '.').collect()
     }
 }
 // All structs related to aircraft configuration
-#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+#[derive(Debug, PartialEq, Clone, Default, Serialize, Deserialize)]
 pub struct AircraftLights {
     strobe_on: bool,
     landing_on: bool,
@@ -24,30 +33,12 @@ pub struct AircraftLights {
     logo_on: bool,
 }
 
-impl Default for AircraftLights {
-    fn default() -> Self {
-        Self {
-            strobe_on: false,
-            landing_on: false,
-            beacon_on: false,
-            nav_on: false,
-            logo_on: false,
-        }
-    }
-}
-
-#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+#[derive(Debug, PartialEq, Clone, Default, Serialize, Deserialize)]
 pub struct AircraftEngine {
     on: bool,
 }
 
-impl Default for AircraftEngine {
-    fn default() -> Self {
-        Self { on: false }
-    }
-}
-
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Clone, Default)]
 pub struct AircraftConfiguration {
     lights: AircraftLights,
     engines: HashMap<String, AircraftEngine>,
@@ -57,68 +48,75 @@ pub struct AircraftConfiguration {
     on_ground: bool,
 }
 
-impl Default for AircraftConfiguration {
-    fn default() -> Self {
-        return Self {
-            lights: AircraftLights::default(),
-            engines: HashMap::new(),
-            flaps_pct: 0,
-            gear_down: false,
-            spoilers_out: false,
-            on_ground: false,
-        };
-    }
-}
-
 impl AircraftConfiguration {
     pub fn new() -> Self {
         Self::default()
     }
 
-    pub fn update_from_json(&mut self, v: &Value) {
+    /// Applies an `ACC` update, values of the wrong type are a `ParseError::InvalidJson`
+    pub fn update_from_json(&mut self, v: &Value) -> Result<(), ParseError> {
         if let Some(lights) = v.get("lights") {
             if let Some(strobe) = lights.get("strobe_on") {
-                self.lights.strobe_on = strobe.as_bool().unwrap();
+                self.lights.strobe_on = json_bool(strobe, "strobe_on")?;
             } else if let Some(beacon) = lights.get("beacon_on") {
-                self.lights.beacon_on = beacon.as_bool().unwrap();
+                self.lights.beacon_on = json_bool(beacon, "beacon_on")?;
             } else if let Some(nav) = lights.get("nav_on") {
-                self.lights.nav_on = nav.as_bool().unwrap();
+                self.lights.nav_on = json_bool(nav, "nav_on")?;
             } else if let Some(landing) = lights.get("landing_on") {
-                self.lights.landing_on = landing.as_bool().unwrap();
+                self.lights.landing_on = json_bool(landing, "landing_on")?;
             } else if let Some(logo) = lights.get("logo_on") {
-                self.lights.logo_on = logo.as_bool().unwrap();
+                self.lights.logo_on = json_bool(logo, "logo_on")?;
             }
         } else if let Some(engines) = v.get("engines") {
-            for (k, v) in engines.as_object().unwrap() {
+            let engines = engines
+                .as_object()
+                .ok_or_else(|| invalid_type("engines", "an object"))?;
+            for (k, v) in engines {
                 if let Some(engine) = self.engines.get_mut(k) {
                     if let Some(on) = v.get("on") {
-                        (*engine).on = on.as_bool().unwrap();
+                        engine.on = json_bool(on, "on")?;
                     }
                 } else {
-                    self.engines
-                        .insert(k.to_string(), serde_json::from_value(v.clone()).unwrap());
+                    let engine = serde_json::from_value(v.clone())
+                        .map_err(|e| ParseError::InvalidJson(e.to_string()))?;
+                    self.engines.insert(k.to_string(), engine);
                 }
             }
         } else if let Some(flaps_pct) = v.get("flaps_pct") {
-            self.flaps_pct = flaps_pct.as_u64().unwrap();
+            self.flaps_pct = flaps_pct
+                .as_u64()
+                .ok_or_else(|| invalid_type("flaps_pct", "a number"))?;
         } else if let Some(gear_down) = v.get("gear_down") {
-            self.gear_down = gear_down.as_bool().unwrap();
+            self.gear_down = json_bool(gear_down, "gear_down")?;
         } else if let Some(spoilers_out) = v.get("spoilers_out") {
-            self.spoilers_out = spoilers_out.as_bool().unwrap();
+            self.spoilers_out = json_bool(spoilers_out, "spoilers_out")?;
         } else if let Some(on_ground) = v.get("on_ground") {
-            self.on_ground = on_ground.as_bool().unwrap();
+            self.on_ground = json_bool(on_ground, "on_ground")?;
         }
+        Ok(())
     }
 }
 
+fn invalid_type(name: &str, expected: &str) -> ParseError {
+    ParseError::InvalidJson(format!("{} should be {}", name, expected))
+}
+
+fn json_bool(value: &Value, name: &str) -> Result<bool, ParseError> {
+    value
+        .as_bool()
+        .ok_or_else(|| invalid_type(name, "a boolean"))
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
 
     #[test]
     fn test_parse_packet_frequency() {
-        let freq = Frequency::from_packet_string(&"23950".to_string());
+        let freq = Frequency::from_packet_string("23950").unwrap();
         assert_eq!(freq.text, "123.950");
+        assert_eq!(Frequency::from_packet_string("2"), None);
+        assert_eq!(freq.to_packet_string(), "23950");
     }
 
     #[test]
@@ -129,7 +127,7 @@ mod test {
             "{\"config\":{\"lights\":{\"beacon_on\":true}, \"gear_down\": true}}",
         )
         .unwrap();
-        config.update_from_json(&data_string["config"]);
+        config.update_from_json(&data_string["config"]).unwrap();
         assert_eq!(
             config,
             AircraftConfiguration {
@@ -140,7 +138,9 @@ mod test {
                 ..Default::default()
             }
         );
-        config.update_from_json(&serde_json::from_str("{\"gear_down\": true}").unwrap());
+        config
+            .update_from_json(&serde_json::from_str("{\"gear_down\": true}").unwrap())
+            .unwrap();
         assert_eq!(
             config,
             AircraftConfiguration {
@@ -152,5 +152,15 @@ mod test {
                 ..Default::default()
             }
         );
+        assert_eq!(
+            config.update_from_json(&serde_json::from_str("{\"gear_down\": 1}").unwrap()),
+            Err(ParseError::InvalidJson(
+                "gear_down should be a boolean".to_string()
+            ))
+        );
+        assert!(config
+            .update_from_json(&serde_json::from_str("{\"engines\": {\"1\": 5}}").unwrap())
+            .is_err());
+        assert!(config.gear_down);
     }
 }